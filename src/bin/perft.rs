@@ -0,0 +1,65 @@
+use chesstacean::chess::game::board::{Board, STARTING_FEN};
+use std::{env, process, time::Instant};
+
+const USAGE: &str = "Usage: perft <depth> [fen] [--divide] [--size <height>x<width>]";
+
+fn main() {
+    let mut depth = None;
+    let mut fen = None;
+    let mut divide = false;
+    let (mut height, mut width) = (8, 8);
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--divide" => divide = true,
+            "--size" => {
+                let size = args.next().unwrap_or_default();
+                match size.split_once('x').map(|(h, w)| (h.parse(), w.parse())) {
+                    Some((Ok(h), Ok(w))) => (height, width) = (h, w),
+                    _ => exit(&format!("Invalid board size {size}")),
+                }
+            }
+            "-h" | "--help" => exit(""),
+            _ if depth.is_none() => match arg.parse::<u32>() {
+                Ok(d) => depth = Some(d),
+                Err(_) => exit(&format!("Invalid depth {arg}")),
+            },
+            _ if fen.is_none() => fen = Some(arg),
+            _ => exit(&format!("Unexpected argument {arg}")),
+        }
+    }
+
+    let depth = depth.unwrap_or_else(|| exit("Missing depth"));
+    let fen = fen.unwrap_or_else(|| STARTING_FEN.to_string());
+    let board = Board::new(&fen, height, width).unwrap_or_else(|e| exit(&e.to_string()));
+
+    let start = Instant::now();
+    let nodes = match divide {
+        true => {
+            let divided = board.divide(depth);
+            for (valid_move, nodes) in divided.iter() {
+                println!("{}: {nodes}", board.to_uci(valid_move));
+            }
+            println!();
+            divided.iter().map(|(_, nodes)| nodes).sum()
+        }
+        false => board.perft(depth),
+    };
+    let elapsed = start.elapsed();
+
+    println!("Nodes searched: {nodes}");
+    eprintln!(
+        "Took {:.3}s, {:.0} nodes per second",
+        elapsed.as_secs_f64(),
+        nodes as f64 / elapsed.as_secs_f64()
+    );
+}
+
+fn exit(message: &str) -> ! {
+    if !message.is_empty() {
+        eprintln!("{message}");
+    }
+    eprintln!("{USAGE}");
+    process::exit(1)
+}
@@ -1,2 +1,6 @@
+pub mod analysis;
 pub mod controller;
+pub mod engine;
 pub mod game;
+pub mod pgn;
+pub mod rating;
@@ -0,0 +1,464 @@
+use std::{collections::HashSet, error::Error, fmt::Display, sync::Arc, time::Duration};
+
+use anyhow::{bail, Result};
+use serde::Serialize;
+use tokio::sync::{mpsc, Mutex, RwLock};
+
+use super::{
+    engine::{Engine, SearchLimits},
+    game::{
+        board::{Board, FenError},
+        pieces::{TeamColor, ValidMove},
+    },
+};
+use crate::server::database::{games::StoredGame, Database, DatabaseMessage, DatabaseResult};
+
+/// Games analysed at once, each on its own blocking thread
+const WORKERS: usize = 2;
+
+/// Games that can wait for a worker before new requests are turned away
+const QUEUE_SIZE: usize = 16;
+
+/// Scores past this many centipawns are treated as decided, so they all count as the same
+const DECIDED: i32 = 1000;
+
+// Drops in winning chances, out of 100, that each judgement starts at
+const INACCURACY: f64 = 5.0;
+const MISTAKE: f64 = 10.0;
+const BLUNDER: f64 = 15.0;
+
+/// How deeply each position is searched
+pub fn analysis_limits() -> SearchLimits {
+    SearchLimits {
+        depth: 8,
+        time: Some(Duration::from_millis(300)),
+        margin: 0,
+    }
+}
+
+/// ### How good a position is for white
+///
+/// Mates are counted in moves, negative when black is the one mating
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Evaluation {
+    Centipawns(i32),
+    Mate(i32),
+    /// The game is over, and this side won
+    Checkmate(TeamColor),
+}
+
+impl Evaluation {
+    /// The evaluation in centipawns for `color`, with decided positions capped
+    fn centipawns(&self, color: &TeamColor) -> i32 {
+        let white = match self {
+            Self::Centipawns(score) => (*score).clamp(-DECIDED, DECIDED),
+            Self::Mate(moves) => DECIDED * moves.signum(),
+            Self::Checkmate(TeamColor::White) => DECIDED,
+            Self::Checkmate(TeamColor::Black) => -DECIDED,
+        };
+        match color {
+            TeamColor::White => white,
+            TeamColor::Black => -white,
+        }
+    }
+
+    /// ### The chance out of 100 that `color` goes on to win
+    ///
+    /// Uses the logistic curve fitted to online games, so equal losses count for less once a game is decided
+    fn win_chance(&self, color: &TeamColor) -> f64 {
+        let centipawns = self.centipawns(color) as f64;
+        50.0 + 50.0 * (2.0 / (1.0 + (-0.00368208 * centipawns).exp()) - 1.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Judgement {
+    Inaccuracy,
+    Mistake,
+    Blunder,
+}
+
+impl Judgement {
+    fn from_drop(drop: f64) -> Option<Self> {
+        match drop {
+            drop if drop >= BLUNDER => Some(Self::Blunder),
+            drop if drop >= MISTAKE => Some(Self::Mistake),
+            drop if drop >= INACCURACY => Some(Self::Inaccuracy),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MoveAnalysis {
+    /// The move played, in SAN
+    pub played: String,
+    /// The move the engine preferred, in SAN
+    pub best: String,
+    /// Centipawns lost compared to the position before the move
+    pub loss: i32,
+    pub accuracy: f64,
+    pub judgement: Option<Judgement>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PlayerSummary {
+    pub name: String,
+    /// `None` if the player made no moves
+    pub accuracy: Option<f64>,
+    pub average_loss: Option<f64>,
+    pub inaccuracies: usize,
+    pub mistakes: usize,
+    pub blunders: usize,
+}
+
+impl PlayerSummary {
+    fn new(name: String, moves: &[&MoveAnalysis]) -> Self {
+        let count = |judgement| moves.iter().filter(|m| m.judgement == Some(judgement)).count();
+        let average = |values: Vec<f64>| match values.is_empty() {
+            true => None,
+            false => Some(values.iter().sum::<f64>() / values.len() as f64),
+        };
+
+        Self {
+            name,
+            accuracy: average(moves.iter().map(|m| m.accuracy).collect()),
+            average_loss: average(moves.iter().map(|m| m.loss as f64).collect()),
+            inaccuracies: count(Judgement::Inaccuracy),
+            mistakes: count(Judgement::Mistake),
+            blunders: count(Judgement::Blunder),
+        }
+    }
+}
+
+/// ### A finished game, judged move by move
+///
+/// `positions` holds the evaluation before every move, then of the final position
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Analysis {
+    pub white: PlayerSummary,
+    pub black: PlayerSummary,
+    pub positions: Vec<Evaluation>,
+    pub moves: Vec<MoveAnalysis>,
+}
+
+impl Analysis {
+    /// ### Replays a stored game, searching every position along the way
+    ///
+    /// Each move is judged by how much it lowered its player's winning chances.
+    /// Playing the engine's choice never counts as a loss, so that deeper searches
+    /// of later positions don't blame the player for the engine's own mistakes
+    pub fn new(game: &StoredGame, limits: &SearchLimits) -> Result<Self, AnalysisError> {
+        let mut board = Board::new(&game.start_fen, game.height, game.width)?;
+        let mut engine = Engine::new();
+
+        let mut positions = vec![];
+        let mut moves = vec![];
+        let mut colors = vec![];
+        for (ply, coordinates) in game.moves.iter().enumerate() {
+            let played = board
+                .legal_moves()
+                .into_iter()
+                .find(|vm| &vm.to_coordinates(game.height) == coordinates)
+                .ok_or_else(|| AnalysisError::IllegalMove {
+                    ply: ply + 1,
+                    coordinates: coordinates.clone(),
+                })?;
+
+            let (evaluation, best) = Self::evaluate(&mut engine, &board, limits);
+            let best = best.expect("There is a legal move, as one was played");
+            colors.push(board.turn);
+            positions.push(evaluation);
+            moves.push((board.to_san(&played), board.to_san(&best), played == best));
+            board.make_move(&played);
+        }
+        positions.push(Self::evaluate(&mut engine, &board, limits).0);
+
+        let moves: Vec<MoveAnalysis> = moves
+            .into_iter()
+            .enumerate()
+            .map(|(ply, (played, best, was_best))| {
+                let color = &colors[ply];
+                let (before, after) = (&positions[ply], &positions[ply + 1]);
+                let (loss, drop) = match was_best {
+                    true => (0, 0.0),
+                    false => (
+                        (before.centipawns(color) - after.centipawns(color)).max(0),
+                        (before.win_chance(color) - after.win_chance(color)).max(0.0),
+                    ),
+                };
+
+                // The curve lichess fits from drops in winning chances to how accurate a move was
+                MoveAnalysis {
+                    played,
+                    best,
+                    loss,
+                    accuracy: (103.1668 * (-0.04354 * drop).exp() - 3.1669).clamp(0.0, 100.0),
+                    judgement: Judgement::from_drop(drop),
+                }
+            })
+            .collect();
+
+        let by = |color: TeamColor| -> Vec<&MoveAnalysis> {
+            moves
+                .iter()
+                .zip(&colors)
+                .filter(|(_, c)| **c == color)
+                .map(|(m, _)| m)
+                .collect()
+        };
+        Ok(Self {
+            white: PlayerSummary::new(game.white_name.clone(), &by(TeamColor::White)),
+            black: PlayerSummary::new(game.black_name.clone(), &by(TeamColor::Black)),
+            positions,
+            moves,
+        })
+    }
+
+    /// Searches a position, giving its evaluation for white and the best move, if there is one
+    fn evaluate(engine: &mut Engine, board: &Board, limits: &SearchLimits) -> (Evaluation, Option<ValidMove>) {
+        let sign = match board.turn {
+            TeamColor::White => 1,
+            TeamColor::Black => -1,
+        };
+
+        if board.legal_moves().is_empty() {
+            let evaluation = match board.is_in_check(&board.turn) {
+                true => Evaluation::Checkmate(board.turn.opposite()),
+                false => Evaluation::Centipawns(0),
+            };
+            return (evaluation, None);
+        }
+
+        let result = engine.search(board, limits);
+        let evaluation = match result.mate_in() {
+            Some(moves) => Evaluation::Mate(moves * sign),
+            None => Evaluation::Centipawns(result.score * sign),
+        };
+        (evaluation, result.best)
+    }
+}
+
+#[derive(Debug)]
+pub enum AnalysisError {
+    InvalidFen(FenError),
+    IllegalMove { ply: usize, coordinates: String },
+}
+
+impl From<FenError> for AnalysisError {
+    fn from(value: FenError) -> Self {
+        Self::InvalidFen(value)
+    }
+}
+
+impl Display for AnalysisError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidFen(e) => write!(f, "InvalidFen: The game's starting position is not valid ({e})"),
+            Self::IllegalMove { ply, coordinates } => {
+                write!(f, "IllegalMove: Move {coordinates} on ply {ply} is not legal")
+            }
+        }
+    }
+}
+
+impl Error for AnalysisError {}
+
+/// Where a game is in being analysed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum AnalysisStatus {
+    Queued,
+    InProgress,
+}
+
+/// ### Analyses finished games in the background
+///
+/// A fixed number of workers take games from a bounded queue, and search on blocking threads,
+/// so analysis never holds up the tasks running live games. Results are written to the database
+pub struct AnalysisPool {
+    jobs: mpsc::Sender<(String, StoredGame)>,
+    pending: RwLock<HashSet<String>>,
+    db_tx: mpsc::Sender<DatabaseMessage>,
+}
+
+impl AnalysisPool {
+    pub fn start(db_tx: &mpsc::Sender<DatabaseMessage>) -> Arc<Self> {
+        let (jobs, rx) = mpsc::channel(QUEUE_SIZE);
+        let this = Arc::new(Self {
+            jobs,
+            pending: RwLock::new(HashSet::new()),
+            db_tx: db_tx.clone(),
+        });
+
+        let rx = Arc::new(Mutex::new(rx));
+        for _ in 0..WORKERS {
+            tokio::task::spawn(Arc::clone(&this).work(Arc::clone(&rx)));
+        }
+
+        this
+    }
+
+    /// ### Gets a game's analysis as JSON, if it has been analysed
+    ///
+    /// Otherwise, returns how far along its analysis is, or `None` if it was never requested
+    pub async fn get(&self, code: String) -> Result<Result<String, Option<AnalysisStatus>>> {
+        if self.pending.read().await.contains(&code) {
+            return Ok(Err(Some(AnalysisStatus::InProgress)));
+        }
+
+        let func = move |db: &Database| DatabaseResult::from(db.games().get_analysis(code));
+        match DatabaseMessage::send(func, &self.db_tx).await? {
+            DatabaseResult::ResultOptionString(analysis) => Ok(analysis?.ok_or(None)),
+            _ => bail!(AnalysisRequestError::InternalError),
+        }
+    }
+
+    /// ### Queues a finished game for analysis
+    ///
+    /// Games that are already analysed are not analysed again, and their analysis is given back instead
+    pub async fn request(&self, code: String) -> Result<Result<String, AnalysisStatus>> {
+        match self.get(code.clone()).await? {
+            Ok(analysis) => return Ok(Ok(analysis)),
+            Err(Some(status)) => return Ok(Err(status)),
+            Err(None) => (),
+        }
+
+        let func = {
+            let code = code.clone();
+            move |db: &Database| DatabaseResult::from(db.games().get_game(code))
+        };
+        let game = match DatabaseMessage::send(func, &self.db_tx).await? {
+            DatabaseResult::StoredGame(game) => match game? {
+                Some(game) => game,
+                None => bail!(AnalysisRequestError::NoSuchGame),
+            },
+            _ => bail!(AnalysisRequestError::InternalError),
+        };
+
+        let mut pending = self.pending.write().await;
+        if !pending.insert(code.clone()) {
+            return Ok(Err(AnalysisStatus::InProgress));
+        }
+        if self.jobs.try_send((code.clone(), game)).is_err() {
+            pending.remove(&code);
+            bail!(AnalysisRequestError::QueueFull);
+        }
+
+        Ok(Err(AnalysisStatus::Queued))
+    }
+
+    async fn work(self: Arc<Self>, jobs: Arc<Mutex<mpsc::Receiver<(String, StoredGame)>>>) {
+        loop {
+            // Only waiting on the queue is locked, so other workers can take jobs while this one analyses
+            let job = jobs.lock().await.recv().await;
+            let (code, game) = match job {
+                Some(job) => job,
+                None => return,
+            };
+
+            let analysis = tokio::task::spawn_blocking(move || Analysis::new(&game, &analysis_limits())).await;
+            match analysis {
+                Ok(Ok(analysis)) => self.store(code.clone(), &analysis).await,
+                Ok(Err(e)) => eprint!("\rCould not analyse game {code} ({e})\n\n > "),
+                Err(e) => eprint!("\rAnalysis of game {code} panicked ({e})\n\n > "),
+            }
+            self.pending.write().await.remove(&code);
+        }
+    }
+
+    async fn store(&self, code: String, analysis: &Analysis) {
+        let json = match serde_json::to_string(analysis) {
+            Ok(json) => json,
+            Err(e) => {
+                eprint!("\rCould not serialize the analysis of game {code} ({e})\n\n > ");
+                return;
+            }
+        };
+
+        let func = {
+            let code = code.clone();
+            move |db: &Database| DatabaseResult::from(db.games().record_analysis(code, json))
+        };
+        match DatabaseMessage::send(func, &self.db_tx).await {
+            Ok(DatabaseResult::ResultEmpty(Ok(()))) => (),
+            _ => eprint!("\rCould not store the analysis of game {code}\n\n > "),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum AnalysisRequestError {
+    InternalError,
+    NoSuchGame,
+    QueueFull,
+}
+
+impl Display for AnalysisRequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InternalError => write!(f, "InternalError: Ran into an unknown internal error"),
+            Self::NoSuchGame => write!(f, "NoSuchGame: There is no finished game with this code"),
+            Self::QueueFull => write!(f, "QueueFull: Too many games are being analysed, try again later"),
+        }
+    }
+}
+
+impl Error for AnalysisRequestError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess::game::board::STARTING_FEN;
+
+    fn stored(fen: &str, moves: &[&str]) -> StoredGame {
+        StoredGame {
+            white_name: "White".to_string(),
+            black_name: "Black".to_string(),
+            start_fen: fen.to_string(),
+            width: 8,
+            height: 8,
+            moves: moves.iter().map(|m| m.to_string()).collect(),
+        }
+    }
+
+    fn limits() -> SearchLimits {
+        SearchLimits {
+            depth: 3,
+            time: None,
+            margin: 0,
+        }
+    }
+
+    #[test]
+    fn flags_blunders_and_scores_accuracy() {
+        // Fool's mate, where g4 allows mate in one
+        let game = stored(STARTING_FEN, &["f2f3", "e7e5", "g2g4", "d8h4"]);
+        let analysis = Analysis::new(&game, &limits()).unwrap();
+
+        assert_eq!(analysis.positions.len(), 5);
+        assert_eq!(analysis.positions[3], Evaluation::Mate(-1));
+        assert_eq!(analysis.positions[4], Evaluation::Checkmate(TeamColor::Black));
+
+        assert_eq!(analysis.moves[2].played, "g4");
+        assert_eq!(analysis.moves[2].judgement, Some(Judgement::Blunder));
+        assert_eq!(analysis.moves[3].played, "Qh4#");
+        assert_eq!(analysis.moves[3].judgement, None);
+
+        assert_eq!(analysis.white.blunders, 1);
+        assert_eq!(analysis.black.blunders, 0);
+        assert!(analysis.white.accuracy.unwrap() < analysis.black.accuracy.unwrap());
+    }
+
+    #[test]
+    fn games_without_moves_have_no_accuracy() {
+        let analysis = Analysis::new(&stored(STARTING_FEN, &[]), &limits()).unwrap();
+        assert_eq!(analysis.positions.len(), 1);
+        assert_eq!(analysis.white.accuracy, None);
+    }
+
+    #[test]
+    fn illegal_moves_are_reported() {
+        let result = Analysis::new(&stored(STARTING_FEN, &["e2e4", "e2e4"]), &limits());
+        assert!(matches!(result, Err(AnalysisError::IllegalMove { ply: 2, .. })));
+    }
+}
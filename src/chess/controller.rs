@@ -1,6 +1,10 @@
-use super::game::{
-    network::{ActionInterface, PlayerInterface},
-    GameConfig, InactiveGame,
+use super::{
+    engine::bot::{Bot, BotConfig},
+    game::{
+        network::{ActionInterface, PlayerInterface, Spectator, SpectatorInterface},
+        GameConfig, InactiveGame, TimeConfig,
+    },
+    rating::{Rating, TimeControl},
 };
 use crate::{
     server::{
@@ -9,13 +13,17 @@ use crate::{
         utils::{ArcLock, ArcLockTrait},
         ws::ControlEvent,
     },
-    traits::ChooseTake,
     word_loader::WordList,
 };
 use anyhow::{bail, Result};
-use rand::rngs::OsRng;
+use rand::{
+    rngs::{OsRng, StdRng},
+    seq::SliceRandom,
+    Rng, SeedableRng,
+};
+use serde::{Deserialize, Serialize};
 use std::{
-    collections::{BTreeMap, BTreeSet},
+    collections::BTreeMap,
     error::Error,
     fmt::Display,
     sync::{Arc, Weak},
@@ -30,14 +38,17 @@ pub struct GameControllerInterface {
     matchmaker: Arc<Matchmaker>,
     lobby_manager: RwLock<LobbyManager>,
     active_game_codes: RwLock<Vec<String>>,
+    /// Games that can be spectated, which disappear once the game is dropped
+    live_games: RwLock<BTreeMap<String, Weak<SpectatorInterface>>>,
 
     word_list: Arc<WordList>,
+    bots: Vec<BotConfig>,
 
     db_tx: mpsc::Sender<DatabaseMessage>,
 }
 // TODO: Convert a lot of the UserInfo parts to include Targets for transmission
 impl GameControllerInterface {
-    pub async fn new(word_list: WordList, db_tx: mpsc::Sender<DatabaseMessage>) -> Arc<Self> {
+    pub async fn new(word_list: WordList, db_tx: mpsc::Sender<DatabaseMessage>, bots: Vec<BotConfig>) -> Arc<Self> {
         let matchmaker = Matchmaker::new();
         Matchmaker::start(matchmaker.clone());
 
@@ -48,8 +59,10 @@ impl GameControllerInterface {
             lobby_manager,
 
             active_game_codes: RwLock::new(vec![]),
+            live_games: RwLock::new(BTreeMap::new()),
 
             word_list: Arc::new(word_list),
+            bots,
 
             db_tx,
         });
@@ -60,34 +73,142 @@ impl GameControllerInterface {
         this
     }
 
-    pub async fn join_queue(&self, user: &UserInfo) -> Result<oneshot::Receiver<Option<Arc<GameInterface>>>, ()> {
-        self.matchmaker.join_queue(user).await
+    /// ### Queues a user for a match in any of the given pools
+    ///
+    /// The returned receiver gets the game once the user is matched, or `None` if they leave the queue
+    pub async fn join_queue(
+        &self,
+        user: &UserInfo,
+        pools: &[Pool],
+    ) -> Result<oneshot::Receiver<Option<Arc<GameInterface>>>> {
+        if pools.is_empty() {
+            bail!(ControllerError::NoPools)
+        }
+
+        let mut ratings = vec![];
+        for pool in pools {
+            ratings.push((*pool, self.get_rating(user, pool.config().time_control()).await));
+        }
+
+        match self.matchmaker.join_queue(user, ratings).await {
+            Ok(rx) => Ok(rx),
+            Err(_) => bail!(ControllerError::AlreadyInQueue),
+        }
     }
-    pub async fn leave_queue(&self, user: &UserInfo) {
+    /// Returns `false` if the user was not in the queue
+    pub async fn leave_queue(&self, user: &UserInfo) -> bool {
         self.matchmaker.leave_queue(user).await
     }
 
-    pub async fn create_lobby(&self, user: &UserInfo, user_conn: ConnectionExtension) -> Result<String> {
+    /// The names of the bots users can play against
+    pub fn bots(&self) -> Vec<String> {
+        self.bots.iter().map(|bot| bot.name.clone()).collect()
+    }
+
+    /// ### Starts a game between a user and one of the configured bots
+    ///
+    /// The user is given a random color, and the game uses the pool's time control
+    pub async fn play_bot(&self, user: &UserInfo, bot: &str, pool: Pool) -> Result<Arc<GameInterface>> {
+        let bot = match self.bots.iter().find(|config| config.name == bot) {
+            Some(bot) => bot,
+            None => bail!(ControllerError::NoSuchBot),
+        };
+        let code = self.create_new_game().await?;
+
+        let (interface, move_rx, event_rx) = PlayerInterface::create(user.clone());
+        let (actions, action_rx) = ActionInterface::create();
+        let game = InactiveGame::new(
+            code.clone(),
+            interface,
+            actions,
+            pool.config(),
+            Some(self.db_tx.clone()),
+        )?;
+
+        let bot_interface = match Bot::from_config(bot, game.board().clone(), &pool.time(), action_rx.clone()).await {
+            Ok(interface) => interface,
+            Err(e) => {
+                eprint!("\rCould not start the bot {} ({e})\n\n > ", bot.name);
+                bail!(ControllerError::BotUnavailable)
+            }
+        };
+
+        let spectators = game.spectators().count();
+        self.watch_game(&code, game.spectators()).await;
+        let game_interface = GameInterface::new(
+            move_rx,
+            action_rx,
+            event_rx,
+            code,
+            user.clone(),
+            &game.messenger,
+            spectators,
+        );
+        game.start(bot_interface);
+
+        Ok(game_interface)
+    }
+
+    /// ### Opens a private lobby hosted by the user
+    ///
+    /// The returned receiver gets the game once the host starts it, and is dropped if the lobby closes
+    pub async fn create_lobby(
+        &self,
+        user: &UserInfo,
+        user_conn: ConnectionExtension,
+    ) -> Result<(String, oneshot::Receiver<Option<Arc<GameInterface>>>)> {
         let code = self.create_new_game().await?;
 
         let mut lobbies = self.lobby_manager.write().await;
-        lobbies.create_lobby(&code, &user, user_conn);
+        let rx = lobbies.create_lobby(&code, user, user_conn);
 
-        Ok(code)
+        Ok((code, rx))
     }
     pub async fn close_lobby(&self, user: &UserInfo, code: String) -> Result<()> {
         let mut lobbies = self.lobby_manager.write().await;
         lobbies.close_lobby(user, code).await
     }
-    pub async fn start_lobby(&self) {}
+    /// ### Starts the host's lobby as a game with the given config
+    ///
+    /// The host is the game's first player, so the config's color is theirs
+    pub async fn start_lobby(&self, user: &UserInfo, code: String, config: GameConfig) -> Result<()> {
+        let mut lobbies = self.lobby_manager.write().await;
+        let spectators = lobbies.start_lobby(user, code.clone(), config, &self.db_tx).await?;
+        self.watch_game(&code, &spectators).await;
+        Ok(())
+    }
 
-    pub async fn join_lobby(&self, code: &String, user: &UserInfo, user_conn: ConnectionExtension) -> Result<()> {
+    /// The returned receiver gets the game once the host starts it, and is dropped if the user leaves the lobby
+    pub async fn join_lobby(
+        &self,
+        code: &String,
+        user: &UserInfo,
+        user_conn: ConnectionExtension,
+    ) -> Result<oneshot::Receiver<Option<Arc<GameInterface>>>> {
         self.lobby_manager.write().await.join_lobby(code, user, user_conn).await
     }
     pub async fn leave_lobby(&self, code: &String, user: &UserInfo) -> Result<()> {
         self.lobby_manager.read().await.leave_lobby(code, user).await
     }
 
+    /// ### Starts watching a live game
+    ///
+    /// Returns `Err(ControllerError::NoSuchGame)` if no game with this code is being played
+    pub async fn spectate(&self, code: &str) -> Result<Spectator> {
+        let reader = self.live_games.read().await;
+        match reader.get(code).and_then(Weak::upgrade) {
+            Some(spectators) => Ok(spectators.watch()),
+            None => bail!(ControllerError::NoSuchGame),
+        }
+    }
+
+    /// Lets a game be spectated by its code, forgetting any games that have since been dropped
+    async fn watch_game(&self, code: &str, spectators: &Arc<SpectatorInterface>) {
+        let mut writer = self.live_games.write().await;
+        writer.retain(|_, game| game.strong_count() > 0);
+        writer.insert(code.to_string(), Arc::downgrade(spectators));
+    }
+
     async fn create_new_game(&self) -> Result<String> {
         let id = loop {
             let word = self.word_list.combo(&mut OsRng);
@@ -115,6 +236,28 @@ impl GameControllerInterface {
         Ok(id)
     }
 
+    /// ### Gets a user's rating for matchmaking
+    ///
+    /// Guests, and users whose rating could not be loaded, are given the default rating
+    async fn get_rating(&self, user: &UserInfo, time_control: TimeControl) -> Rating {
+        let handle = match user.get_handle() {
+            Some(handle) => handle,
+            None => return Rating::default(),
+        };
+
+        let func = move |db: &Database| DatabaseResult::from(db.ratings().get_rating(handle, time_control));
+        match DatabaseMessage::send(func, &self.db_tx).await {
+            Ok(DatabaseResult::Rating(Ok(rating))) => rating,
+            _ => {
+                eprint!(
+                    "\rCould not load rating for {}, using the default\n\n > ",
+                    user.get_display()
+                );
+                Rating::default()
+            }
+        }
+    }
+
     pub async fn upgrade(&self, original: UserInfo, user: UserInfo) {
         self.matchmaker.upgrade(original.clone(), user.clone()).await;
         self.lobby_manager.write().await.upgrade(original, user).await;
@@ -130,22 +273,42 @@ pub enum ControllerError {
     IsAlreadyHost,
     FullLobby,
     NotInLobby,
+    LobbyNotFull,
+
+    AlreadyInQueue,
+    NotInQueue,
+    NoPools,
+
+    NoSuchBot,
+    BotUnavailable,
+
+    NoSuchGame,
+    AlreadySpectating,
+    NotSpectating,
+    IsPlayer,
 }
 
 impl Display for ControllerError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}",
-            match self {
-                Self::InternalError => format!("InternalError: Ran into an unknown internal error"),
-                Self::NoSuchLobby => format!("NoSuchLobby: The requested lobby does not exist"),
-                Self::NotLobbyHost => format!("NotLobbyHost: You do not own this lobby"),
-                Self::IsAlreadyHost => format!("IsAlreadyHost: You cannot join a game with yourself"),
-                Self::FullLobby => format!("FullLobby: This lobby is already full"),
-                Self::NotInLobby => format!("NotInLobby: You are not in this lobby"),
-            }
-        )
+        let message = match self {
+            Self::InternalError => "InternalError: Ran into an unknown internal error",
+            Self::NoSuchLobby => "NoSuchLobby: The requested lobby does not exist",
+            Self::NotLobbyHost => "NotLobbyHost: You do not own this lobby",
+            Self::IsAlreadyHost => "IsAlreadyHost: You cannot join a game with yourself",
+            Self::FullLobby => "FullLobby: This lobby is already full",
+            Self::NotInLobby => "NotInLobby: You are not in this lobby",
+            Self::LobbyNotFull => "LobbyNotFull: Another player must join before the game can start",
+            Self::AlreadyInQueue => "AlreadyInQueue: You are already waiting for a match",
+            Self::NotInQueue => "NotInQueue: You are not waiting for a match",
+            Self::NoPools => "NoPools: At least one pool must be chosen to join the queue",
+            Self::NoSuchBot => "NoSuchBot: There is no bot with this name",
+            Self::BotUnavailable => "BotUnavailable: This bot could not be started, try another one",
+            Self::NoSuchGame => "NoSuchGame: There is no game being played with this code",
+            Self::AlreadySpectating => "AlreadySpectating: You are already spectating this game",
+            Self::NotSpectating => "NotSpectating: You are not spectating this game",
+            Self::IsPlayer => "IsPlayer: You cannot spectate a game you are playing in",
+        };
+        write!(f, "{message}")
     }
 }
 
@@ -166,9 +329,15 @@ impl LobbyManager {
 
     async fn upgrade(&mut self, original: UserInfo, user: UserInfo) {}
 
-    fn create_lobby(&mut self, code: &String, user: &UserInfo, user_conn: ConnectionExtension) {
-        let lobby = Lobby::new(code.clone(), user.clone(), user_conn);
-        self.codes.insert(code.clone(), Arc::clone(&lobby));
+    fn create_lobby(
+        &mut self,
+        code: &str,
+        user: &UserInfo,
+        user_conn: ConnectionExtension,
+    ) -> oneshot::Receiver<Option<Arc<GameInterface>>> {
+        let (tx, rx) = oneshot::channel();
+        let lobby = Lobby::new(code.to_string(), user.clone(), user_conn, tx);
+        self.codes.insert(code.to_string(), Arc::clone(&lobby));
 
         match self.users.get_mut(user) {
             None => {
@@ -178,6 +347,7 @@ impl LobbyManager {
                 vec.push(lobby);
             }
         }
+        rx
     }
     async fn close_lobby(&mut self, user: &UserInfo, code: String) -> Result<()> {
         let lobby = match self.codes.get(&code) {
@@ -211,11 +381,98 @@ impl LobbyManager {
             bail!(ControllerError::NotLobbyHost)
         }
     }
-    fn start_lobby(&mut self, user: &UserInfo, config: GameConfig) {
-        // TODO
+    /// ### Turns a full lobby into a game, and hands it to both players
+    ///
+    /// The lobby is removed once the game is created, but is kept if the config is not valid.
+    /// Returns the game's spectator interface, so it can be watched
+    async fn start_lobby(
+        &mut self,
+        user: &UserInfo,
+        code: String,
+        config: GameConfig,
+        db_tx: &mpsc::Sender<DatabaseMessage>,
+    ) -> Result<Arc<SpectatorInterface>> {
+        let lobby = match self.codes.get(&code) {
+            Some(l) => l,
+            None => bail!(ControllerError::NoSuchLobby),
+        };
+        {
+            let reader = lobby.read().await;
+            if !reader.is_host(user) {
+                bail!(ControllerError::NotLobbyHost)
+            }
+            if reader.client.is_none() {
+                bail!(ControllerError::LobbyNotFull)
+            }
+        }
+        config.validate()?;
+
+        let lobby = match self.codes.remove(&code) {
+            Some(l) => l,
+            None => bail!(ControllerError::NoSuchLobby),
+        };
+        if let Some(vec) = self.users.get_mut(user) {
+            vec.retain(|l| !Arc::ptr_eq(l, &lobby));
+            if vec.is_empty() {
+                self.users.remove(user);
+            }
+        }
+
+        // Every other reference to the lobby lives in the maps it was just removed from
+        let Lobby { host, client, code } = match Arc::try_unwrap(lobby) {
+            Ok(lobby) => lobby.into_inner(),
+            Err(_) => bail!(ControllerError::InternalError),
+        };
+        let client = match client {
+            Some(client) => client,
+            None => bail!(ControllerError::LobbyNotFull),
+        };
+
+        // Create player interfaces
+        let (host_interface, host_move_rx, host_event_rx) = PlayerInterface::create(host.info.clone());
+        let (client_interface, client_move_rx, client_event_rx) = PlayerInterface::create(client.info.clone());
+
+        // Create actions interface
+        let (actions, action_rx) = ActionInterface::create();
+
+        // Create game, which was validated with the config
+        let game = InactiveGame::new(code.clone(), host_interface, actions, config, Some(db_tx.clone()))?;
+
+        // Create game interfaces
+        let host_game_interface = GameInterface::new(
+            host_move_rx,
+            action_rx.clone(),
+            host_event_rx,
+            code.clone(),
+            host.info,
+            &game.messenger,
+            game.spectators().count(),
+        );
+        reply(host.reply, Some(host_game_interface));
+
+        let client_game_interface = GameInterface::new(
+            client_move_rx,
+            action_rx,
+            client_event_rx,
+            code,
+            client.info,
+            &game.messenger,
+            game.spectators().count(),
+        );
+        reply(client.reply, Some(client_game_interface));
+
+        // Start game
+        let spectators = Arc::clone(game.spectators());
+        game.start(client_interface);
+        Ok(spectators)
     }
 
-    async fn join_lobby(&mut self, code: &String, user: &UserInfo, user_conn: ConnectionExtension) -> Result<()> {
+    async fn join_lobby(
+        &mut self,
+        code: &String,
+        user: &UserInfo,
+        user_conn: ConnectionExtension,
+    ) -> Result<oneshot::Receiver<Option<Arc<GameInterface>>>> {
         let lobby = match self.codes.get(code) {
             Some(l) => l,
             None => bail!(ControllerError::NoSuchLobby),
@@ -224,8 +481,9 @@ impl LobbyManager {
         if writer.is_host(user) {
             bail!(ControllerError::IsAlreadyHost)
         } else {
-            writer.join(user.clone(), user_conn)?;
-            Ok(())
+            let (tx, rx) = oneshot::channel();
+            writer.join(user.clone(), user_conn, tx)?;
+            Ok(rx)
         }
     }
     async fn leave_lobby(&self, code: &String, user: &UserInfo) -> Result<()> {
@@ -244,12 +502,13 @@ struct Lobby {
 }
 
 impl Lobby {
-    fn new(code: String, host_info: UserInfo, host: ConnectionExtension) -> ArcLock<Self> {
+    fn new(code: String, host_info: UserInfo, host: ConnectionExtension, reply: MatchReply) -> ArcLock<Self> {
         ArcLock::new_arclock(Self {
             code,
             host: LobbyUser {
                 info: host_info,
                 conn: host,
+                reply,
             },
             client: None,
         })
@@ -267,13 +526,14 @@ impl Lobby {
         }
     }
 
-    fn join(&mut self, client_info: UserInfo, client: ConnectionExtension) -> Result<()> {
+    fn join(&mut self, client_info: UserInfo, client: ConnectionExtension, reply: MatchReply) -> Result<()> {
         if self.client.is_some() {
             bail!(ControllerError::FullLobby)
         }
         self.client = Some(LobbyUser {
             info: client_info,
             conn: client,
+            reply,
         });
         Ok(())
     }
@@ -317,49 +577,98 @@ impl Lobby {
 struct LobbyUser {
     info: UserInfo,
     conn: ConnectionExtension,
+    /// Gets the game once the lobby starts
+    reply: MatchReply,
+}
+
+/// The rating difference every player accepts as soon as they join the queue
+const BASE_WINDOW: f64 = 100.0;
+/// How much the acceptable rating difference grows for every second spent waiting
+const WINDOW_GROWTH: f64 = 10.0;
+/// The largest rating difference a player will ever be matched across
+const MAX_WINDOW: f64 = 500.0;
+/// How long both players must wait before being matched against their previous opponent again
+const REMATCH_DELAY: Duration = Duration::from_secs(30);
+
+/// Replies to a queued user with their game, or `None` if they left the queue
+type MatchReply = oneshot::Sender<Option<Arc<GameInterface>>>;
+
+/// Two matched players, and the pool they were matched in
+type PoolMatch = (Pool, (UserInfo, MatchReply), (UserInfo, MatchReply));
+
+fn reply(reply_to: MatchReply, interface: Option<Arc<GameInterface>>) {
+    // The user may have disconnected while waiting, in which case there is no one to tell
+    let _ = reply_to.send(interface);
 }
 
 struct Matchmaker {
-    queue: RwLock<Vec<UserInQueue>>,
-    in_queue: RwLock<BTreeSet<UserInfo>>,
+    queues: RwLock<BTreeMap<Pool, Vec<UserInQueue>>>,
+    waiting: RwLock<BTreeMap<UserInfo, MatchReply>>,
+    last_opponents: RwLock<BTreeMap<UserInfo, UserInfo>>,
+
+    rng: RwLock<StdRng>,
 
     controller_ref: RwLock<Weak<GameControllerInterface>>,
 }
 
 impl Matchmaker {
     fn new() -> Arc<Self> {
+        Self::with_rng(StdRng::from_entropy())
+    }
+
+    /// Creates a matchmaker that breaks ties using the given rng, so pairings can be reproduced
+    fn with_rng(rng: StdRng) -> Arc<Self> {
         Arc::new(Self {
-            queue: RwLock::new(vec![]),
-            in_queue: RwLock::new(BTreeSet::new()),
+            queues: RwLock::new(BTreeMap::new()),
+            waiting: RwLock::new(BTreeMap::new()),
+            last_opponents: RwLock::new(BTreeMap::new()),
+            rng: RwLock::new(rng),
             controller_ref: RwLock::new(Weak::new()),
         })
     }
 
     async fn upgrade(&self, original: UserInfo, user: UserInfo) {}
 
-    async fn join_queue(&self, user: &UserInfo) -> Result<oneshot::Receiver<Option<Arc<GameInterface>>>, ()> {
-        let mut in_queue = self.in_queue.write().await;
-        let mut queue = self.queue.write().await;
-
-        if in_queue.insert(user.clone()) {
-            let (uiq, rx) = UserInQueue::create(user);
-
-            queue.push(uiq);
+    /// ### Queues a user in each of the given pools, with their rating in that pool
+    ///
+    /// Returns `Err(())` if the user is already queued
+    async fn join_queue(
+        &self,
+        user: &UserInfo,
+        ratings: Vec<(Pool, Rating)>,
+    ) -> Result<oneshot::Receiver<Option<Arc<GameInterface>>>, ()> {
+        let mut waiting = self.waiting.write().await;
+        let mut queues = self.queues.write().await;
+
+        if waiting.contains_key(user) {
+            return Err(());
+        }
 
-            Ok(rx)
-        } else {
-            Err(())
+        let (reply_to, rx) = oneshot::channel();
+        waiting.insert(user.clone(), reply_to);
+        for (pool, rating) in ratings {
+            queues
+                .entry(pool)
+                .or_default()
+                .push(UserInQueue::new(user, rating.rating));
         }
+
+        Ok(rx)
     }
 
-    async fn leave_queue(&self, user: &UserInfo) {
-        let mut in_queue = self.in_queue.write().await;
-        let mut queue = self.queue.write().await;
+    async fn leave_queue(&self, user: &UserInfo) -> bool {
+        let mut waiting = self.waiting.write().await;
+        let mut queues = self.queues.write().await;
 
-        if in_queue.remove(user) {
-            let index = queue.iter().position(|u| &u.user == user).unwrap();
-            let uiq = queue.remove(index);
-            uiq.reply(None)
+        match waiting.remove(user) {
+            Some(reply_to) => {
+                for queue in queues.values_mut() {
+                    queue.retain(|u| &u.user != user);
+                }
+                reply(reply_to, None);
+                true
+            }
+            None => false,
         }
     }
 
@@ -373,112 +682,513 @@ impl Matchmaker {
         loop {
             interval.tick().await;
 
-            let mut queue = self.queue.write().await;
-            let mut in_queue = self.in_queue.write().await;
             let controller = self
                 .controller_ref
                 .read()
                 .await
                 .upgrade()
                 .expect("This should never be None");
-            Matchmaker::make_matches(&mut queue, &mut in_queue, &controller).await;
-            drop(queue);
-            drop(in_queue);
+            self.make_matches(&controller).await;
             drop(controller);
         }
     }
 
     /// ### Matchmaking algorithm
     ///
-    /// Currently does not implement rating
+    /// Pairs up waiting players by rating within each pool, and starts a game for each pair
     ///
-    /// Currently does not implement timestamp
+    /// Matched players are removed from every pool they were queued in
+    async fn make_matches(&self, controller: &Arc<GameControllerInterface>) {
+        let mut waiting = self.waiting.write().await;
+        let mut queues = self.queues.write().await;
+        let mut last_opponents = self.last_opponents.write().await;
+        let mut rng = self.rng.write().await;
+
+        let matches = Matchmaker::match_pools(
+            &mut waiting,
+            &mut queues,
+            &mut last_opponents,
+            Instant::now(),
+            &mut *rng,
+        );
+
+        drop(rng);
+        drop(last_opponents);
+        drop(queues);
+        drop(waiting);
+
+        for (pool, player1, player2) in matches {
+            Matchmaker::start_match(pool, player1, player2, controller).await;
+        }
+    }
+
+    /// ### Pairs players in every pool
     ///
-    /// Currently does not implement any statistic analysis
-    async fn make_matches(
+    /// Matched players stop waiting, and are removed from every pool
+    fn match_pools<R>(
+        waiting: &mut BTreeMap<UserInfo, MatchReply>,
+        queues: &mut BTreeMap<Pool, Vec<UserInQueue>>,
+        last_opponents: &mut BTreeMap<UserInfo, UserInfo>,
+        now: Instant,
+        rng: &mut R,
+    ) -> Vec<PoolMatch>
+    where
+        R: Rng + ?Sized,
+    {
+        let mut matches = vec![];
+        for (pool, queue) in queues.iter_mut() {
+            // Players matched in an earlier pool are no longer waiting
+            queue.retain(|u| waiting.contains_key(&u.user));
+
+            for (player1, player2) in Matchmaker::find_pairs(queue, last_opponents, now, rng) {
+                let (user1, user2) = (player1.user, player2.user);
+                let reply1 = waiting.remove(&user1).expect("Queued players should be waiting");
+                let reply2 = waiting.remove(&user2).expect("Queued players should be waiting");
+
+                last_opponents.insert(user1.clone(), user2.clone());
+                last_opponents.insert(user2.clone(), user1.clone());
+
+                matches.push((*pool, (user1, reply1), (user2, reply2)));
+            }
+        }
+        for queue in queues.values_mut() {
+            queue.retain(|u| waiting.contains_key(&u.user));
+        }
+
+        matches
+    }
+
+    /// ### Finds the pairs of players to match
+    ///
+    /// Players who have waited longest choose first, taking the closest rated player that both
+    /// of them accept. Ties are broken randomly with the given rng
+    ///
+    /// Paired players are removed from the queue
+    fn find_pairs<R>(
         queue: &mut Vec<UserInQueue>,
-        in_queue: &mut BTreeSet<UserInfo>,
+        last_opponents: &BTreeMap<UserInfo, UserInfo>,
+        now: Instant,
+        rng: &mut R,
+    ) -> Vec<(UserInQueue, UserInQueue)>
+    where
+        R: Rng + ?Sized,
+    {
+        queue.shuffle(rng);
+        // The sort is stable, so players who joined at the same time stay shuffled
+        queue.sort_by_key(|u| u.timestamp);
+
+        let mut paired = vec![false; queue.len()];
+        let mut pairs = vec![];
+        for i in 0..queue.len() {
+            if paired[i] {
+                continue;
+            }
+            let seeker = &queue[i];
+            let opponent = (i + 1..queue.len())
+                .filter(|&j| !paired[j] && seeker.accepts(&queue[j], last_opponents, now))
+                .min_by(|&a, &b| {
+                    let distance_a = (seeker.rating - queue[a].rating).abs();
+                    let distance_b = (seeker.rating - queue[b].rating).abs();
+                    distance_a.total_cmp(&distance_b)
+                });
+
+            if let Some(j) = opponent {
+                paired[i] = true;
+                paired[j] = true;
+                pairs.push((i, j));
+            }
+        }
+
+        let mut players: Vec<Option<UserInQueue>> = queue.drain(..).map(Some).collect();
+        let pairs = pairs
+            .into_iter()
+            .map(|(i, j)| (players[i].take().unwrap(), players[j].take().unwrap()))
+            .collect();
+        queue.extend(players.into_iter().flatten());
+
+        pairs
+    }
+
+    /// Creates and starts the game for a matched pair, replying to both players
+    async fn start_match(
+        pool: Pool,
+        (user1, reply1): (UserInfo, MatchReply),
+        (user2, reply2): (UserInfo, MatchReply),
         controller: &Arc<GameControllerInterface>,
     ) {
-        'while_loop: while queue.len() > 2 {
-            // Get random players
-            let player1 = queue.take_random(&mut OsRng).unwrap();
-            let player2 = queue.take_random(&mut OsRng).unwrap();
-
-            let game_code = controller.create_new_game().await;
-
-            let game_code = match game_code {
-                Ok(gc) => gc,
-                Err(e) => {
-                    eprint!("\rRan into error ({e:?}) creating game code during matchmaking\n\n > ");
-                    player1.reply(None);
-                    player2.reply(None);
-                    continue 'while_loop;
-                }
-            };
+        let game_code = controller.create_new_game().await;
+
+        let game_code = match game_code {
+            Ok(gc) => gc,
+            Err(e) => {
+                eprint!("\rRan into error ({e:?}) creating game code during matchmaking\n\n > ");
+                reply(reply1, None);
+                reply(reply2, None);
+                return;
+            }
+        };
 
-            in_queue.remove(&player1.user);
-            in_queue.remove(&player2.user);
-
-            // Create player interfaces
-            let (player1_interface, p1_move_rx, p1_event_rx) = PlayerInterface::create(player1.user.clone());
-            let (player2_interface, p2_move_rx, p2_event_rx) = PlayerInterface::create(player2.user.clone());
-
-            // Create actions interface
-            let (actions, action_rx) = ActionInterface::create();
-
-            // Create game
-            let game = InactiveGame::new(player1_interface, actions, GameConfig::default());
-
-            // Create game interfaces
-            let p1_msg = game.messenger.channel();
-            let player1_game_interface = GameInterface::new(
-                p1_move_rx,
-                action_rx.clone(),
-                p1_event_rx,
-                game_code.clone(),
-                p1_msg.0,
-                p1_msg.1,
-            );
-            player1.reply(Some(player1_game_interface));
-
-            let p2_msg = game.messenger.channel();
-            let player2_game_interface =
-                GameInterface::new(p2_move_rx, action_rx, p2_event_rx, game_code, p2_msg.0, p2_msg.1);
-            player2.reply(Some(player2_game_interface));
-
-            // Start game
-            game.start(player2_interface);
-        }
+        // Create player interfaces
+        let (player1_interface, p1_move_rx, p1_event_rx) = PlayerInterface::create(user1.clone());
+        let (player2_interface, p2_move_rx, p2_event_rx) = PlayerInterface::create(user2.clone());
+
+        // Create actions interface
+        let (actions, action_rx) = ActionInterface::create();
+
+        // Create game
+        let game = match InactiveGame::new(
+            game_code.clone(),
+            player1_interface,
+            actions,
+            pool.config(),
+            Some(controller.db_tx.clone()),
+        ) {
+            Ok(game) => game,
+            Err(e) => {
+                eprint!("\rRan into error ({e}) creating game during matchmaking\n\n > ");
+                reply(reply1, None);
+                reply(reply2, None);
+                return;
+            }
+        };
+
+        // Create game interfaces
+        let player1_game_interface = GameInterface::new(
+            p1_move_rx,
+            action_rx.clone(),
+            p1_event_rx,
+            game_code.clone(),
+            user1,
+            &game.messenger,
+            game.spectators().count(),
+        );
+        reply(reply1, Some(player1_game_interface));
+
+        let player2_game_interface = GameInterface::new(
+            p2_move_rx,
+            action_rx,
+            p2_event_rx,
+            game_code.clone(),
+            user2,
+            &game.messenger,
+            game.spectators().count(),
+        );
+        reply(reply2, Some(player2_game_interface));
+        controller.watch_game(&game_code, game.spectators()).await;
+
+        // Start game
+        game.start(player2_interface);
     }
 }
 
-#[allow(dead_code)]
 struct UserInQueue {
     user: UserInfo,
-    rating: u16,        // Currently not used
-    timestamp: Instant, // Currently not used
-
-    reply_to: oneshot::Sender<Option<Arc<GameInterface>>>,
+    rating: f64,
+    timestamp: Instant,
     // Stats can go here to help with matchmaking, if necessary
     // stats: STUFF
 }
 
 impl UserInQueue {
-    fn new(user: &UserInfo, reply_to: oneshot::Sender<Option<Arc<GameInterface>>>) -> Self {
-        let now = Instant::now();
+    fn new(user: &UserInfo, rating: f64) -> Self {
         Self {
             user: user.clone(),
-            timestamp: now, // Currently timestamp is not used, but is included for if we change the matchmaking algo
-            rating: 1200, // Currently Rating does not change, and is just a static value applied to every player. Additionally rating is not used for matchmaking currently
-            reply_to,
+            timestamp: Instant::now(),
+            rating,
+        }
+    }
+    /// The largest rating difference this player accepts, which grows the longer they wait
+    fn window(&self, now: Instant) -> f64 {
+        let waited = now.saturating_duration_since(self.timestamp).as_secs_f64();
+        (BASE_WINDOW + WINDOW_GROWTH * waited).min(MAX_WINDOW)
+    }
+
+    /// ### Checks if both players are willing to be matched together
+    ///
+    /// Both must accept the rating difference, and previous opponents must both have waited a while
+    fn accepts(&self, other: &UserInQueue, last_opponents: &BTreeMap<UserInfo, UserInfo>, now: Instant) -> bool {
+        let difference = (self.rating - other.rating).abs();
+        if difference > self.window(now) || difference > other.window(now) {
+            return false;
+        }
+
+        let rematch =
+            last_opponents.get(&self.user) == Some(&other.user) || last_opponents.get(&other.user) == Some(&self.user);
+        let both_waited = now.saturating_duration_since(self.timestamp) >= REMATCH_DELAY
+            && now.saturating_duration_since(other.timestamp) >= REMATCH_DELAY;
+
+        !rematch || both_waited
+    }
+}
+
+/// ### A matchmaking pool, with its own queue and time control
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Pool {
+    /// 1 minute, with no increment
+    Bullet,
+    /// 3 minutes, with 2 seconds added per move
+    Blitz,
+    /// 10 minutes, with 5 seconds added per move
+    Rapid,
+    /// 30 minutes, with 20 seconds added per move
+    Classical,
+    /// No clock
+    Unlimited,
+}
+
+impl Pool {
+    pub fn config(&self) -> GameConfig {
+        GameConfig::with_time(self.time())
+    }
+
+    pub fn time(&self) -> TimeConfig {
+        let timed = |minutes: u64, seconds| TimeConfig::Timed {
+            limit: Duration::from_secs(minutes * 60),
+            added: Duration::from_secs(seconds),
+        };
+        match self {
+            Self::Bullet => timed(1, 0),
+            Self::Blitz => timed(3, 2),
+            Self::Rapid => timed(10, 5),
+            Self::Classical => timed(30, 20),
+            Self::Unlimited => TimeConfig::NotTimed,
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::server::database::tests::spawn_test_database;
+
+    /// A controller with a one-word game code list, an in-memory database and no bots
+    pub async fn test_controller() -> Arc<GameControllerInterface> {
+        let words = WordList {
+            adjectives: vec!["Quick".to_string()],
+            nouns: vec!["Fox".to_string()],
+            verbs: vec!["Jumps".to_string()],
+        };
+        GameControllerInterface::new(words, spawn_test_database(), vec![]).await
+    }
+
+    fn waiting(rating: f64, waited: u64, now: Instant) -> UserInQueue {
+        let mut user = UserInQueue::new(&UserInfo::new_guest(), rating);
+        user.timestamp = now - Duration::from_secs(waited);
+        user
+    }
+
+    fn pair_ratings(
+        queue: &mut Vec<UserInQueue>,
+        last_opponents: &BTreeMap<UserInfo, UserInfo>,
+        now: Instant,
+    ) -> Vec<(f64, f64)> {
+        Matchmaker::find_pairs(queue, last_opponents, now, &mut StdRng::seed_from_u64(0))
+            .into_iter()
+            .map(|(a, b)| (a.rating, b.rating))
+            .collect()
+    }
+
+    fn no_connections() -> ConnectionExtension {
+        (&ArcLock::new_arclock(std::collections::HashMap::new())).into()
+    }
+
+    #[tokio::test]
+    async fn started_lobbies_become_games() {
+        let (db_tx, _db_rx) = mpsc::channel(1);
+        let (host, client) = (UserInfo::new_guest(), UserInfo::new_guest());
+        let code = "open-lobby".to_string();
+
+        let mut lobbies = LobbyManager::new();
+        let host_rx = lobbies.create_lobby(&code, &host, no_connections());
+
+        let early = lobbies
+            .start_lobby(&host, code.clone(), GameConfig::default(), &db_tx)
+            .await;
+        assert!(matches!(
+            early.unwrap_err().downcast_ref(),
+            Some(ControllerError::LobbyNotFull)
+        ));
+
+        let client_rx = lobbies.join_lobby(&code, &client, no_connections()).await.unwrap();
+        let by_client = lobbies
+            .start_lobby(&client, code.clone(), GameConfig::default(), &db_tx)
+            .await;
+        assert!(matches!(
+            by_client.unwrap_err().downcast_ref(),
+            Some(ControllerError::NotLobbyHost)
+        ));
+
+        let invalid = GameConfig::with_time(TimeConfig::Timed {
+            limit: Duration::ZERO,
+            added: Duration::ZERO,
+        });
+        assert!(lobbies.start_lobby(&host, code.clone(), invalid, &db_tx).await.is_err());
+        assert!(lobbies.codes.contains_key(&code));
+
+        lobbies
+            .start_lobby(&host, code.clone(), GameConfig::default(), &db_tx)
+            .await
+            .unwrap();
+        assert!(lobbies.codes.is_empty() && lobbies.users.is_empty());
+
+        assert_eq!(host_rx.await.unwrap().unwrap().code(), &code);
+        assert_eq!(client_rx.await.unwrap().unwrap().code(), &code);
+    }
+
+    #[tokio::test]
+    async fn users_join_and_leave_the_queue() {
+        let controller = test_controller().await;
+        let user = UserInfo::new_guest();
+
+        let no_pools = controller.join_queue(&user, &[]).await;
+        assert!(matches!(
+            no_pools.unwrap_err().downcast_ref(),
+            Some(ControllerError::NoPools)
+        ));
+
+        let rx = controller.join_queue(&user, &[Pool::Blitz]).await.unwrap();
+        let again = controller.join_queue(&user, &[Pool::Rapid]).await;
+        assert!(matches!(
+            again.unwrap_err().downcast_ref(),
+            Some(ControllerError::AlreadyInQueue)
+        ));
+
+        assert!(controller.leave_queue(&user).await);
+        assert!(rx.await.unwrap().is_none());
+        assert!(!controller.leave_queue(&user).await);
+    }
+
+    #[tokio::test]
+    async fn leaving_the_queue_removes_every_pool() {
+        let controller = test_controller().await;
+        let (user, other) = (UserInfo::new_guest(), UserInfo::new_guest());
+        let pools = [Pool::Bullet, Pool::Rapid, Pool::Unlimited];
+
+        let _rx = controller.join_queue(&user, &pools).await.unwrap();
+        let _other_rx = controller.join_queue(&other, &[Pool::Classical]).await.unwrap();
+        for pool in &pools {
+            assert!(controller.matchmaker.queues.read().await[pool]
+                .iter()
+                .any(|u| u.user == user));
         }
+
+        assert!(controller.leave_queue(&user).await);
+        let queues = controller.matchmaker.queues.read().await;
+        assert!(queues.values().flatten().all(|u| u.user != user));
+        assert_eq!(queues[&Pool::Classical].len(), 1);
+        assert!(!controller.matchmaker.waiting.read().await.contains_key(&user));
     }
-    fn create(user: &UserInfo) -> (Self, oneshot::Receiver<Option<Arc<GameInterface>>>) {
-        let (reply_to, reply_rx) = oneshot::channel();
-        (Self::new(user, reply_to), reply_rx)
+
+    #[test]
+    fn pairs_closest_ratings() {
+        let now = Instant::now();
+        let mut queue = vec![
+            waiting(1500.0, 0, now),
+            waiting(1900.0, 0, now),
+            waiting(1550.0, 0, now),
+            waiting(1850.0, 0, now),
+        ];
+
+        let mut pairs = pair_ratings(&mut queue, &BTreeMap::new(), now);
+        pairs.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        assert!(queue.is_empty());
+        assert!(pairs.iter().all(|(a, b)| (a - b).abs() == 50.0));
     }
-    fn reply(self, interface: Option<Arc<GameInterface>>) {
-        self.reply_to.send(interface).unwrap();
+
+    #[test]
+    fn rating_window_grows_while_waiting() {
+        let now = Instant::now();
+        let mut queue = vec![waiting(1500.0, 0, now), waiting(1800.0, 0, now)];
+        assert!(pair_ratings(&mut queue, &BTreeMap::new(), now).is_empty());
+        assert_eq!(queue.len(), 2);
+
+        // Both players must have waited long enough to accept the difference
+        let mut queue = vec![waiting(1500.0, 60, now), waiting(1800.0, 0, now)];
+        assert!(pair_ratings(&mut queue, &BTreeMap::new(), now).is_empty());
+
+        let mut queue = vec![waiting(1500.0, 60, now), waiting(1800.0, 30, now)];
+        assert_eq!(pair_ratings(&mut queue, &BTreeMap::new(), now).len(), 1);
+    }
+
+    #[test]
+    fn avoids_instant_rematches() {
+        let now = Instant::now();
+        let mut queue = vec![waiting(1500.0, 0, now), waiting(1500.0, 0, now)];
+        let last_opponents = BTreeMap::from([
+            (queue[0].user.clone(), queue[1].user.clone()),
+            (queue[1].user.clone(), queue[0].user.clone()),
+        ]);
+
+        assert!(pair_ratings(&mut queue, &last_opponents, now).is_empty());
+
+        for user in queue.iter_mut() {
+            user.timestamp -= REMATCH_DELAY;
+        }
+        assert_eq!(pair_ratings(&mut queue, &last_opponents, now).len(), 1);
+    }
+
+    #[test]
+    fn matched_players_leave_every_pool() {
+        let now = Instant::now();
+        let (both, blitz, rapid) = (UserInfo::new_guest(), UserInfo::new_guest(), UserInfo::new_guest());
+
+        let mut waiting = BTreeMap::new();
+        let mut receivers = vec![];
+        for user in [&both, &blitz, &rapid] {
+            let (tx, rx) = oneshot::channel();
+            waiting.insert(user.clone(), tx);
+            receivers.push(rx);
+        }
+        let mut queues = BTreeMap::from([
+            (
+                Pool::Blitz,
+                vec![UserInQueue::new(&both, 1500.0), UserInQueue::new(&blitz, 1500.0)],
+            ),
+            (
+                Pool::Rapid,
+                vec![UserInQueue::new(&both, 1500.0), UserInQueue::new(&rapid, 1500.0)],
+            ),
+        ]);
+
+        let matches = Matchmaker::match_pools(
+            &mut waiting,
+            &mut queues,
+            &mut BTreeMap::new(),
+            now,
+            &mut StdRng::seed_from_u64(0),
+        );
+
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].0, Pool::Blitz);
+        assert!(queues[&Pool::Blitz].is_empty());
+        assert_eq!(queues[&Pool::Rapid].len(), 1);
+        assert_eq!(queues[&Pool::Rapid][0].user, rapid);
+        assert!(waiting.contains_key(&rapid) && !waiting.contains_key(&both));
+    }
+
+    #[test]
+    fn seeded_pairing_is_deterministic() {
+        let now = Instant::now();
+        let queue: Vec<_> = (0..6).map(|_| waiting(1500.0, 0, now)).collect();
+        let users: Vec<_> = queue.iter().map(|u| u.user.clone()).collect();
+
+        let pairings = |seed| {
+            let mut queue: Vec<_> = users
+                .iter()
+                .map(|user| {
+                    let mut uiq = UserInQueue::new(user, 1500.0);
+                    uiq.timestamp = now;
+                    uiq
+                })
+                .collect();
+            Matchmaker::find_pairs(&mut queue, &BTreeMap::new(), now, &mut StdRng::seed_from_u64(seed))
+                .into_iter()
+                .map(|(a, b)| (a.user, b.user))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(pairings(7), pairings(7));
+        assert_eq!(pairings(7).len(), 3);
     }
 }
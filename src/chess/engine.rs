@@ -0,0 +1,481 @@
+use std::{
+    fmt::Display,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use rand::{seq::SliceRandom, thread_rng};
+use serde::{Deserialize, Serialize};
+
+use self::{
+    eval::{evaluate, tile_value},
+    table::{Bound, Entry, TranspositionTable},
+};
+use super::game::{board::Board, pieces::ValidMove};
+
+pub mod bot;
+pub mod eval;
+pub mod table;
+pub mod uci;
+
+const INFINITY: i32 = 1_000_000;
+
+/// Score of being checkmated now, which shrinks by one for every ply until the mate
+const MATE: i32 = 100_000;
+
+/// Scores this close to `MATE` are forced mates rather than evaluations
+const MATE_THRESHOLD: i32 = MATE - 1_000;
+
+/// Holds `2^16` positions, which is a few megabytes
+const TABLE_BITS: u32 = 16;
+
+/// Rough gain from promoting, used to try promotions early
+const PROMOTION_GAIN: i32 = 800;
+
+/// How often the clock is checked during a search
+const NODES_BETWEEN_CHECKS: u64 = 1024;
+
+/// ### How well a computer player plays
+///
+/// Weaker levels search less deeply, and may play any move scoring close to the best one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Strength {
+    Beginner,
+    Casual,
+    Intermediate,
+    Advanced,
+    Master,
+}
+
+impl Strength {
+    pub fn limits(&self) -> SearchLimits {
+        let (depth, time, margin) = match self {
+            Self::Beginner => (1, 1, 150),
+            Self::Casual => (2, 1, 75),
+            Self::Intermediate => (3, 2, 25),
+            Self::Advanced => (5, 3, 0),
+            Self::Master => (64, 5, 0),
+        };
+
+        SearchLimits {
+            depth,
+            time: Some(Duration::from_secs(time)),
+            margin,
+        }
+    }
+}
+
+impl Display for Strength {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Beginner => "beginner",
+                Self::Casual => "casual",
+                Self::Intermediate => "intermediate",
+                Self::Advanced => "advanced",
+                Self::Master => "master",
+            }
+        )
+    }
+}
+
+impl FromStr for Strength {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "beginner" => Ok(Self::Beginner),
+            "casual" => Ok(Self::Casual),
+            "intermediate" => Ok(Self::Intermediate),
+            "advanced" => Ok(Self::Advanced),
+            "master" => Ok(Self::Master),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchLimits {
+    /// Deepest iteration to search, in plies
+    pub depth: u32,
+    /// Stops searching once this has passed, keeping the last finished iteration
+    pub time: Option<Duration>,
+    /// Any root move within this many points of the best one may be picked
+    pub margin: i32,
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchResult {
+    /// `None` if there are no legal moves
+    pub best: Option<ValidMove>,
+    /// In hundredths of a pawn, from the side to move's view
+    pub score: i32,
+    /// The deepest iteration that finished
+    pub depth: u32,
+    pub nodes: u64,
+}
+
+impl SearchResult {
+    /// ### The number of moves until mate, if the search found one
+    ///
+    /// Negative if the side to move is being mated
+    pub fn mate_in(&self) -> Option<i32> {
+        match self.score.abs() > MATE_THRESHOLD {
+            true => Some(self.score.signum() * (MATE - self.score.abs() + 1) / 2),
+            false => None,
+        }
+    }
+}
+
+/// ### An alpha-beta searcher
+///
+/// Keeps its transposition table between searches, so it should follow a single game
+pub struct Engine {
+    table: TranspositionTable,
+    nodes: u64,
+    deadline: Option<Instant>,
+    stopped: bool,
+    /// Positions on the current search path, for spotting repetitions
+    path: Vec<u64>,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Self {
+            table: TranspositionTable::new(TABLE_BITS),
+            nodes: 0,
+            deadline: None,
+            stopped: false,
+            path: vec![],
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.table.clear();
+    }
+
+    /// ### Finds a move with iterative deepening
+    ///
+    /// Searches one ply deeper each iteration until the depth or time limit, and plays from the
+    /// last iteration that finished
+    pub fn search(&mut self, board: &Board, limits: &SearchLimits) -> SearchResult {
+        self.nodes = 0;
+        self.stopped = false;
+        self.path.clear();
+
+        let deadline = limits.time.map(|time| Instant::now() + time);
+        let mut board = board.clone();
+        let mut scored: Vec<(ValidMove, i32)> = board.legal_moves().into_iter().map(|vm| (vm, 0)).collect();
+        let mut depth = 0;
+
+        while depth < limits.depth.max(1) {
+            // The first iteration always finishes, so there is always a move to play
+            self.deadline = if depth == 0 { None } else { deadline };
+
+            let iteration = self.search_root(&mut board, depth + 1, &scored, limits.margin > 0);
+            if self.stopped {
+                break;
+            }
+
+            scored = iteration;
+            depth += 1;
+
+            match scored.first() {
+                Some((_, score)) if score.abs() > MATE_THRESHOLD => break,
+                None => break,
+                _ => (),
+            }
+        }
+
+        // Without a margin, moves after the first only have upper bounds, which may tie with the best
+        let (best, score) = match scored.first() {
+            Some((best, best_score)) if limits.margin == 0 => (Some(best.clone()), *best_score),
+            Some((_, best_score)) => {
+                let candidates: Vec<&(ValidMove, i32)> = scored
+                    .iter()
+                    .filter(|(_, score)| best_score - score <= limits.margin)
+                    .collect();
+                let (best, score) = candidates
+                    .choose(&mut thread_rng())
+                    .expect("The best move is always a candidate");
+                (Some(best.clone()), *score)
+            }
+            None => (None, -evaluate(&board)),
+        };
+
+        SearchResult {
+            best,
+            score,
+            depth,
+            nodes: self.nodes,
+        }
+    }
+
+    /// ### Scores every root move, best first
+    ///
+    /// Moves are tried in the order of the previous iteration. Unless every score must be exact,
+    /// moves that cannot beat the best so far are only given an upper bound
+    fn search_root(
+        &mut self,
+        board: &mut Board,
+        depth: u32,
+        previous: &[(ValidMove, i32)],
+        exact: bool,
+    ) -> Vec<(ValidMove, i32)> {
+        let mut scored = Vec::with_capacity(previous.len());
+        let mut alpha = -INFINITY;
+
+        self.path.push(board.zobrist_hash());
+        for (valid_move, _) in previous {
+            board.make_move(valid_move);
+            let score = -self.negamax(board, depth - 1, -INFINITY, -alpha, 1);
+            board.unmake_move(valid_move);
+
+            if self.stopped {
+                break;
+            }
+            if !exact {
+                alpha = alpha.max(score);
+            }
+            scored.push((valid_move.clone(), score));
+        }
+        self.path.pop();
+
+        // Stable, so equal moves keep the previous iteration's order
+        scored.sort_by_key(|(_, score)| -score);
+        scored
+    }
+
+    fn negamax(&mut self, board: &mut Board, depth: u32, mut alpha: i32, beta: i32, ply: i32) -> i32 {
+        if self.out_of_time() {
+            return 0;
+        }
+
+        let hash = board.zobrist_hash();
+        if board.halfmove_clock >= 100 || self.path.contains(&hash) {
+            return 0;
+        }
+        if depth == 0 {
+            return self.quiescence(board, alpha, beta);
+        }
+
+        let mut table_move = None;
+        if let Some(entry) = self.table.get(hash) {
+            let score = from_table(entry.score, ply);
+            if entry.depth >= depth {
+                match entry.bound {
+                    Bound::Exact => return score,
+                    Bound::Lower if score >= beta => return score,
+                    Bound::Upper if score <= alpha => return score,
+                    _ => (),
+                }
+            }
+            table_move = entry.best.clone();
+        }
+
+        let mut moves = board.legal_moves();
+        if moves.is_empty() {
+            return match board.is_in_check(&board.turn) {
+                true => -MATE + ply,
+                false => 0,
+            };
+        }
+        order_moves(board, &mut moves, table_move.as_ref());
+
+        let original_alpha = alpha;
+        let mut best_score = -INFINITY;
+        let mut best_move = None;
+
+        self.path.push(hash);
+        for valid_move in moves {
+            board.make_move(&valid_move);
+            let score = -self.negamax(board, depth - 1, -beta, -alpha, ply + 1);
+            board.unmake_move(&valid_move);
+
+            if self.stopped {
+                self.path.pop();
+                return 0;
+            }
+            if score > best_score {
+                best_score = score;
+                best_move = Some(valid_move);
+            }
+            alpha = alpha.max(score);
+            if alpha >= beta {
+                break;
+            }
+        }
+        self.path.pop();
+
+        let bound = if best_score <= original_alpha {
+            Bound::Upper
+        } else if best_score >= beta {
+            Bound::Lower
+        } else {
+            Bound::Exact
+        };
+        self.table.insert(Entry {
+            hash,
+            depth,
+            score: to_table(best_score, ply),
+            bound,
+            best: best_move,
+        });
+
+        best_score
+    }
+
+    /// ### Searches captures and promotions until the position is quiet
+    ///
+    /// Stops the search from misjudging a position in the middle of an exchange. The side to move
+    /// may also stand pat, as it is rarely forced to capture
+    fn quiescence(&mut self, board: &mut Board, mut alpha: i32, beta: i32) -> i32 {
+        if self.out_of_time() {
+            return 0;
+        }
+
+        let stand_pat = evaluate(board);
+        if stand_pat >= beta {
+            return stand_pat;
+        }
+        alpha = alpha.max(stand_pat);
+
+        let mut moves: Vec<ValidMove> = board
+            .legal_moves()
+            .into_iter()
+            .filter(|vm| vm.captured.is_some() || vm.promotion.is_some())
+            .collect();
+        order_moves(board, &mut moves, None);
+
+        for valid_move in moves {
+            board.make_move(&valid_move);
+            let score = -self.quiescence(board, -beta, -alpha);
+            board.unmake_move(&valid_move);
+
+            if self.stopped {
+                return 0;
+            }
+            if score >= beta {
+                return score;
+            }
+            alpha = alpha.max(score);
+        }
+
+        alpha
+    }
+
+    fn out_of_time(&mut self) -> bool {
+        self.nodes += 1;
+        if self.nodes.is_multiple_of(NODES_BETWEEN_CHECKS) {
+            if let Some(deadline) = self.deadline {
+                self.stopped |= Instant::now() >= deadline;
+            }
+        }
+        self.stopped
+    }
+}
+
+/// Tries the remembered best move first, then captures of the most valuable pieces by the least valuable
+fn order_moves(board: &Board, moves: &mut [ValidMove], table_move: Option<&ValidMove>) {
+    moves.sort_by_cached_key(|vm| {
+        if Some(vm) == table_move {
+            return -INFINITY;
+        }
+
+        let victim = vm.captured.as_ref().map(eval::material).unwrap_or_default();
+        let promotion = match vm.promotion {
+            Some(_) => PROMOTION_GAIN,
+            None => 0,
+        };
+        match victim + promotion {
+            0 => 0,
+            gain => -(gain * 10 - tile_value(board.get_tile(&vm.source))),
+        }
+    });
+}
+
+/// Mate scores are stored relative to the position, as the same position can be reached at any ply
+fn to_table(score: i32, ply: i32) -> i32 {
+    if score > MATE_THRESHOLD {
+        score + ply
+    } else if score < -MATE_THRESHOLD {
+        score - ply
+    } else {
+        score
+    }
+}
+
+fn from_table(score: i32, ply: i32) -> i32 {
+    if score > MATE_THRESHOLD {
+        score - ply
+    } else if score < -MATE_THRESHOLD {
+        score + ply
+    } else {
+        score
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess::game::board::STARTING_FEN;
+
+    fn best_move(fen: &str, depth: u32) -> SearchResult {
+        let board = Board::new(fen, 8, 8).unwrap();
+        let limits = SearchLimits {
+            depth,
+            time: None,
+            margin: 0,
+        };
+        Engine::new().search(&board, &limits)
+    }
+
+    #[test]
+    fn evaluation_is_symmetric() {
+        assert_eq!(evaluate(&Board::default()), 0);
+
+        let white = Board::new("4k3/8/8/8/3N4/8/PP6/4K3 w - - 0 1", 8, 8).unwrap();
+        let black = Board::new("4k3/pp6/8/3n4/8/8/8/4K3 b - - 0 1", 8, 8).unwrap();
+        assert!(evaluate(&white) > 0);
+        assert_eq!(evaluate(&white), evaluate(&black));
+    }
+
+    #[test]
+    fn takes_hanging_pieces() {
+        let result = best_move("4k3/8/8/3q4/8/8/8/3RK3 w - - 0 1", 2);
+        assert_eq!(result.best.unwrap().to_coordinates(8), "d1d5");
+    }
+
+    #[test]
+    fn finds_mates() {
+        let result = best_move("6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1", 3);
+        assert_eq!(result.best.as_ref().unwrap().to_coordinates(8), "a1a8");
+        assert_eq!(result.mate_in(), Some(1));
+
+        // Only 1. Kf7 Kh7 2. Rh1# mates, as 1. Ra8+ lets the king out
+        let result = best_move("7k/8/5K2/8/8/8/8/R7 w - - 0 1", 4);
+        assert_eq!(result.mate_in(), Some(2));
+    }
+
+    #[test]
+    fn every_strength_finds_a_move() {
+        let board = Board::new(STARTING_FEN, 8, 8).unwrap();
+        for strength in [Strength::Beginner, Strength::Casual] {
+            let result = Engine::new().search(&board, &strength.limits());
+            assert!(board.legal_moves().contains(&result.best.unwrap()));
+        }
+
+        let stalemate = Board::new("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1", 8, 8).unwrap();
+        assert!(Engine::new()
+            .search(&stalemate, &Strength::Master.limits())
+            .best
+            .is_none());
+    }
+}
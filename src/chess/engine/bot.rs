@@ -0,0 +1,280 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+use tokio::sync::{mpsc, oneshot, watch};
+
+use super::{
+    uci::{GoClock, GoLimits, UciConfig, UciEngine, UciError},
+    Engine, SearchLimits, Strength,
+};
+use crate::{
+    chess::game::{
+        board::Board,
+        clock::ClockState,
+        network::{Action, ActionType, Event, PlayerInterface},
+        pieces::{TeamColor, Turn},
+        TimeConfig,
+    },
+    server::user::UserInfo,
+};
+
+type MoveRx = mpsc::Receiver<oneshot::Sender<(Turn, oneshot::Sender<bool>)>>;
+type ActionRx = watch::Receiver<Option<mpsc::Sender<Action>>>;
+
+/// Spends at most this share of the remaining clock on one move
+const CLOCK_SHARE: u32 = 30;
+
+/// ### A bot opponent players can choose, as listed in the server config
+///
+/// Written in JSON as `{ "name": "Casual", "engine": { "Builtin": { "strength": "Casual" } } }`,
+/// or with `{ "Uci": { "path": "/usr/bin/stockfish", "options": { "Skill Level": "5" } } }` as the engine
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct BotConfig {
+    pub name: String,
+    pub engine: BotEngine,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub enum BotEngine {
+    Builtin { strength: Strength },
+    Uci(UciConfig),
+}
+
+impl BotConfig {
+    /// One bot for each strength of the built-in engine, used when no bots are configured
+    pub fn builtin() -> Vec<Self> {
+        [
+            Strength::Beginner,
+            Strength::Casual,
+            Strength::Intermediate,
+            Strength::Advanced,
+            Strength::Master,
+        ]
+        .into_iter()
+        .map(|strength| Self {
+            name: strength.to_string(),
+            engine: BotEngine::Builtin { strength },
+        })
+        .collect()
+    }
+}
+
+/// What a bot thinks with
+enum Brain {
+    Builtin {
+        strength: Strength,
+        engine: Engine,
+    },
+    /// An external engine, which is sent the starting position and every move played since
+    Uci {
+        engine: Box<UciEngine>,
+        start: String,
+        moves: Vec<String>,
+        movetime: Duration,
+        increment: Duration,
+    },
+}
+
+/// ### A computer player
+///
+/// Follows the game through its events, and asks its engine for a move whenever the game asks for one.
+/// If the engine fails, the bot resigns
+pub struct Bot {
+    user: UserInfo,
+    actions: ActionRx,
+    brain: Brain,
+    board: Board,
+    clock: Option<ClockState>,
+}
+
+impl Bot {
+    /// ### Creates a computer player using the built-in engine, for a game starting from `board`
+    ///
+    /// It can take either side, and plays until the game ends. `actions` are the game's, used to resign
+    pub fn create(strength: Strength, board: Board, actions: ActionRx) -> PlayerInterface {
+        let brain = Brain::Builtin {
+            strength,
+            engine: Engine::new(),
+        };
+        Self::start(format!("Computer ({strength})"), brain, board, actions)
+    }
+
+    /// ### Creates the configured bot for a game starting from `board`
+    ///
+    /// External engines are started here, and `Err` is returned if one fails to start
+    pub async fn from_config(
+        config: &BotConfig,
+        board: Board,
+        time: &TimeConfig,
+        actions: ActionRx,
+    ) -> Result<PlayerInterface, UciError> {
+        let brain = match &config.engine {
+            BotEngine::Builtin { strength } => Brain::Builtin {
+                strength: *strength,
+                engine: Engine::new(),
+            },
+            BotEngine::Uci(uci) => {
+                let mut engine = UciEngine::spawn(uci).await?;
+                engine.new_game().await?;
+                Brain::Uci {
+                    engine: Box::new(engine),
+                    start: board.to_fen(),
+                    moves: vec![],
+                    movetime: Duration::from_millis(uci.movetime),
+                    increment: match time {
+                        TimeConfig::NotTimed => Duration::ZERO,
+                        TimeConfig::Timed { added, .. } => *added,
+                    },
+                }
+            }
+        };
+        Ok(Self::start(
+            format!("Computer ({})", config.name),
+            brain,
+            board,
+            actions,
+        ))
+    }
+
+    fn start(name: String, brain: Brain, board: Board, actions: ActionRx) -> PlayerInterface {
+        let user = UserInfo::new_bot(name);
+        let (interface, moves, events) = PlayerInterface::create(user.clone());
+        let bot = Self {
+            user,
+            actions,
+            brain,
+            board,
+            clock: None,
+        };
+
+        tokio::task::spawn(bot.run(moves, events));
+        interface
+    }
+
+    async fn run(mut self, mut moves: MoveRx, mut events: mpsc::Receiver<Event>) {
+        loop {
+            tokio::select! {
+                // Events are handled first, so the board is up to date when asked for a move
+                biased;
+
+                event = events.recv() => match event {
+                    Some(Event::ValidMove { valid_move, clock }) => {
+                        if let Brain::Uci { moves, .. } = &mut self.brain {
+                            moves.push(self.board.to_uci(&valid_move));
+                        }
+                        self.board.make_move(&valid_move);
+                        self.clock = clock;
+                    }
+                    Some(Event::MoveWasUndone(valid_move)) => {
+                        if let Brain::Uci { moves, .. } = &mut self.brain {
+                            moves.pop();
+                        }
+                        self.board.unmake_move(&valid_move);
+                    }
+                    Some(Event::GameEnd { .. }) | None => return,
+                    Some(_) => (),
+                },
+                request = moves.recv() => match request {
+                    Some(request) if !request.is_closed() => self.play(request).await,
+                    Some(_) => (),
+                    None => return,
+                },
+            }
+        }
+    }
+
+    /// Answers the game's request with the move the engine found, or resigns if the engine failed
+    async fn play(&mut self, request: oneshot::Sender<(Turn, oneshot::Sender<bool>)>) {
+        let best = match &mut self.brain {
+            Brain::Builtin { strength, engine } => {
+                let limits = Self::limits(strength, &self.board, self.clock);
+                Ok(Self::search_builtin(engine, &self.board, limits).await)
+            }
+            Brain::Uci {
+                engine,
+                start,
+                moves,
+                movetime,
+                increment,
+            } => {
+                let limits = GoLimits {
+                    clock: self.clock.map(|clock| GoClock {
+                        white: clock.white,
+                        black: clock.black,
+                        increment: *increment,
+                    }),
+                    movetime: self.clock.is_none().then_some(*movetime),
+                    depth: None,
+                };
+                engine.go(start, moves, &limits).await.map(|search| search.best)
+            }
+        };
+
+        let best = match best {
+            Ok(Some(best)) => best,
+            Ok(None) => return,
+            Err(e) => {
+                eprint!("\rBot engine stopped responding ({e}), so the bot resigns\n\n > ");
+                self.resign(request).await;
+                return;
+            }
+        };
+
+        // The request is closed if the turn was interrupted, and the game will ask again
+        let (result_tx, result_rx) = oneshot::channel();
+        if request.send((Turn::Notation(best), result_tx)).is_ok() {
+            let _ = result_rx.await;
+        }
+    }
+
+    /// ### Resigns the game through its actions
+    ///
+    /// The request is held until the resignation ends the turn, otherwise the game would see the bot abandon it
+    async fn resign(&self, mut request: oneshot::Sender<(Turn, oneshot::Sender<bool>)>) {
+        let actions = self.actions.borrow().clone();
+        if let Some(actions) = actions {
+            if actions
+                .send(Action::new(self.user.clone(), ActionType::Resign))
+                .await
+                .is_ok()
+            {
+                request.closed().await;
+            }
+        }
+    }
+
+    /// Searches on a blocking thread, so the game's other tasks keep running
+    async fn search_builtin(engine: &mut Engine, board: &Board, limits: SearchLimits) -> Option<String> {
+        let mut searcher = std::mem::take(engine);
+        let position = board.clone();
+
+        let (searcher, result) = tokio::task::spawn_blocking(move || {
+            let result = searcher.search(&position, &limits);
+            (searcher, result)
+        })
+        .await
+        .expect("Search should not panic");
+        *engine = searcher;
+
+        result.best.map(|best| board.to_uci(&best))
+    }
+
+    /// The strength's limits, cut down to a share of the bot's remaining time
+    fn limits(strength: &Strength, board: &Board, clock: Option<ClockState>) -> SearchLimits {
+        let mut limits = strength.limits();
+        if let Some(clock) = clock {
+            let remaining = match board.turn {
+                TeamColor::White => clock.white,
+                TeamColor::Black => clock.black,
+            };
+            let budget = remaining / CLOCK_SHARE;
+            limits.time = Some(
+                limits
+                    .time
+                    .map_or(budget, |time| time.min(budget))
+                    .max(Duration::from_millis(10)),
+            );
+        }
+        limits
+    }
+}
@@ -0,0 +1,141 @@
+use crate::chess::game::{
+    board::{tile::Tile, Board},
+    pieces::{Piece, PieceType, TeamColor},
+};
+
+/// Material is worth this many points per pawn, so piece-square bonuses can be finer grained
+const PAWN: i32 = 100;
+
+// Piece-square tables from Tomasz Michniewski's simplified evaluation function,
+// written from white's side with the eighth rank first, the same order tiles are stored in
+
+#[rustfmt::skip]
+const PAWN_TABLE: [i32; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  0,
+    50, 50, 50, 50, 50, 50, 50, 50,
+    10, 10, 20, 30, 30, 20, 10, 10,
+     5,  5, 10, 25, 25, 10,  5,  5,
+     0,  0,  0, 20, 20,  0,  0,  0,
+     5, -5,-10,  0,  0,-10, -5,  5,
+     5, 10, 10,-20,-20, 10, 10,  5,
+     0,  0,  0,  0,  0,  0,  0,  0,
+];
+
+#[rustfmt::skip]
+const KNIGHT_TABLE: [i32; 64] = [
+    -50,-40,-30,-30,-30,-30,-40,-50,
+    -40,-20,  0,  0,  0,  0,-20,-40,
+    -30,  0, 10, 15, 15, 10,  0,-30,
+    -30,  5, 15, 20, 20, 15,  5,-30,
+    -30,  0, 15, 20, 20, 15,  0,-30,
+    -30,  5, 10, 15, 15, 10,  5,-30,
+    -40,-20,  0,  5,  5,  0,-20,-40,
+    -50,-40,-30,-30,-30,-30,-40,-50,
+];
+
+#[rustfmt::skip]
+const BISHOP_TABLE: [i32; 64] = [
+    -20,-10,-10,-10,-10,-10,-10,-20,
+    -10,  0,  0,  0,  0,  0,  0,-10,
+    -10,  0,  5, 10, 10,  5,  0,-10,
+    -10,  5,  5, 10, 10,  5,  5,-10,
+    -10,  0, 10, 10, 10, 10,  0,-10,
+    -10, 10, 10, 10, 10, 10, 10,-10,
+    -10,  5,  0,  0,  0,  0,  5,-10,
+    -20,-10,-10,-10,-10,-10,-10,-20,
+];
+
+#[rustfmt::skip]
+const ROOK_TABLE: [i32; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  0,
+     5, 10, 10, 10, 10, 10, 10,  5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+     0,  0,  0,  5,  5,  0,  0,  0,
+];
+
+#[rustfmt::skip]
+const QUEEN_TABLE: [i32; 64] = [
+    -20,-10,-10, -5, -5,-10,-10,-20,
+    -10,  0,  0,  0,  0,  0,  0,-10,
+    -10,  0,  5,  5,  5,  5,  0,-10,
+     -5,  0,  5,  5,  5,  5,  0, -5,
+      0,  0,  5,  5,  5,  5,  0, -5,
+    -10,  5,  5,  5,  5,  5,  0,-10,
+    -10,  0,  5,  0,  0,  0,  0,-10,
+    -20,-10,-10, -5, -5,-10,-10,-20,
+];
+
+#[rustfmt::skip]
+const KING_TABLE: [i32; 64] = [
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -20,-30,-30,-40,-40,-30,-30,-20,
+    -10,-20,-20,-20,-20,-20,-20,-10,
+     20, 20,  0,  0,  0,  0, 20, 20,
+     20, 30, 10,  0,  0, 10, 30, 20,
+];
+
+/// ### Scores the position for the side to move, in hundredths of a pawn
+///
+/// Counts material, plus piece-square bonuses on 8x8 boards. The tables don't fit other sizes,
+/// so those are judged on material alone
+pub fn evaluate(board: &Board) -> i32 {
+    let standard = board.width == 8 && board.height == 8;
+
+    let score: i32 = [TeamColor::White, TeamColor::Black]
+        .iter()
+        .flat_map(|color| board.pieces(color))
+        .map(|piece| {
+            let value = material(piece) + if standard { placement(piece) } else { 0 };
+            match piece.get_color() {
+                TeamColor::White => value,
+                TeamColor::Black => -value,
+            }
+        })
+        .sum();
+
+    match board.turn {
+        TeamColor::White => score,
+        TeamColor::Black => -score,
+    }
+}
+
+/// The value of a piece, leaving out the king as both sides always have one
+pub fn material(piece: &Piece) -> i32 {
+    match piece.is_king() {
+        true => 0,
+        false => piece.get_value() as i32 * PAWN,
+    }
+}
+
+/// The value of whatever a tile holds, used to order captures
+pub fn tile_value(tile: &Tile) -> i32 {
+    match tile {
+        Tile::Piece { piece } => material(piece),
+        _ => 0,
+    }
+}
+
+fn placement(piece: &Piece) -> i32 {
+    let position = piece.get_position();
+    let rank = match piece.get_color() {
+        TeamColor::White => position.y as usize,
+        TeamColor::Black => 7 - position.y as usize,
+    };
+    let index = rank * 8 + position.x as usize;
+
+    match piece.get_kind() {
+        PieceType::Pawn => PAWN_TABLE[index],
+        PieceType::Knight => KNIGHT_TABLE[index],
+        PieceType::Bishop => BISHOP_TABLE[index],
+        PieceType::Rook => ROOK_TABLE[index],
+        PieceType::Queen => QUEEN_TABLE[index],
+        PieceType::King => KING_TABLE[index],
+    }
+}
@@ -0,0 +1,62 @@
+use crate::chess::game::pieces::ValidMove;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Bound {
+    /// The score is exact
+    Exact,
+    /// The search failed high, so the score is at least this
+    Lower,
+    /// The search failed low, so the score is at most this
+    Upper,
+}
+
+#[derive(Clone)]
+pub struct Entry {
+    pub hash: u64,
+    pub depth: u32,
+    pub score: i32,
+    pub bound: Bound,
+    pub best: Option<ValidMove>,
+}
+
+/// ### Remembers searched positions by their zobrist hash
+///
+/// Each hash maps to one slot, and a newer or deeper search of a position replaces what was there
+pub struct TranspositionTable {
+    entries: Vec<Option<Entry>>,
+}
+
+impl TranspositionTable {
+    /// Creates a table with room for `2^bits` positions
+    pub fn new(bits: u32) -> Self {
+        Self {
+            entries: vec![None; 1 << bits],
+        }
+    }
+
+    fn slot(&self, hash: u64) -> usize {
+        (hash & (self.entries.len() as u64 - 1)) as usize
+    }
+
+    pub fn get(&self, hash: u64) -> Option<&Entry> {
+        self.entries[self.slot(hash)]
+            .as_ref()
+            .filter(|entry| entry.hash == hash)
+    }
+
+    pub fn insert(&mut self, entry: Entry) {
+        let slot = self.slot(entry.hash);
+        let keep_existing = matches!(
+            &self.entries[slot],
+            Some(existing) if existing.hash == entry.hash && existing.depth > entry.depth
+        );
+
+        if !keep_existing {
+            self.entries[slot] = Some(entry);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.iter_mut().for_each(|entry| *entry = None);
+    }
+}
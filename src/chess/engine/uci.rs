@@ -0,0 +1,565 @@
+use std::{collections::BTreeMap, error::Error, fmt::Display, process::Stdio, time::Duration};
+
+use serde::Deserialize;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+    process::{Child, ChildStdin, ChildStdout, Command},
+    time,
+};
+
+/// How long an engine has to start up, or to answer `isready`
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// ### How to start an external engine
+///
+/// The engine is run as `path args...`, and each option is set with `setoption` before a game
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct UciConfig {
+    pub path: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub options: BTreeMap<String, String>,
+    /// Thinking time per move in milliseconds when the game has no clock
+    #[serde(default = "default_movetime")]
+    pub movetime: u64,
+    /// Milliseconds the engine may think past its expected time before it is stopped,
+    /// and then has to answer `stop` in before it is killed
+    #[serde(default = "default_grace")]
+    pub grace: u64,
+}
+
+fn default_movetime() -> u64 {
+    1000
+}
+
+fn default_grace() -> u64 {
+    10_000
+}
+
+/// ### What to search for with `go`
+///
+/// Clock times are sent as `wtime`/`btime`, otherwise the engine is given a fixed `movetime`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct GoLimits {
+    pub clock: Option<GoClock>,
+    pub movetime: Option<Duration>,
+    pub depth: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GoClock {
+    pub white: Duration,
+    pub black: Duration,
+    pub increment: Duration,
+}
+
+impl GoLimits {
+    fn command(&self) -> String {
+        let mut command = String::from("go");
+        if let Some(clock) = self.clock {
+            command += &format!(
+                " wtime {} btime {} winc {} binc {}",
+                clock.white.as_millis(),
+                clock.black.as_millis(),
+                clock.increment.as_millis(),
+                clock.increment.as_millis()
+            );
+        }
+        if let Some(movetime) = self.movetime {
+            command += &format!(" movetime {}", movetime.as_millis());
+        }
+        if let Some(depth) = self.depth {
+            command += &format!(" depth {depth}");
+        }
+        command
+    }
+
+    /// The longest the engine could reasonably think for, or `None` if it is only limited by depth
+    fn deadline(&self, grace: Duration) -> Option<Duration> {
+        let thinking = match (self.movetime, self.clock) {
+            (Some(movetime), _) => movetime,
+            (None, Some(clock)) => clock.white.max(clock.black),
+            (None, None) => return None,
+        };
+        Some(thinking + grace)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UciScore {
+    /// Hundredths of a pawn, from the side to move's view
+    Centipawns(i32),
+    /// Moves until mate, negative if the side to move is getting mated
+    Mate(i32),
+}
+
+/// ### The parts of an `info` line this server uses
+///
+/// Anything else the engine reports, such as `currmove` or `hashfull`, is skipped
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct UciInfo {
+    pub depth: Option<u32>,
+    pub score: Option<UciScore>,
+    pub nodes: Option<u64>,
+    pub pv: Vec<String>,
+}
+
+/// ### Parses an `info` line
+///
+/// Returns `None` for other lines, and for `info string` messages that carry no search details
+pub fn parse_info(line: &str) -> Option<UciInfo> {
+    let mut tokens = line.split_whitespace();
+    if tokens.next() != Some("info") {
+        return None;
+    }
+
+    let mut info = UciInfo::default();
+    while let Some(token) = tokens.next() {
+        match token {
+            "depth" => info.depth = tokens.next().and_then(|depth| depth.parse().ok()),
+            "nodes" => info.nodes = tokens.next().and_then(|nodes| nodes.parse().ok()),
+            "score" => {
+                let kind = tokens.next();
+                let value = tokens.next().and_then(|value| value.parse().ok());
+                info.score = match (kind, value) {
+                    (Some("cp"), Some(value)) => Some(UciScore::Centipawns(value)),
+                    (Some("mate"), Some(value)) => Some(UciScore::Mate(value)),
+                    _ => None,
+                };
+            }
+            // The principal variation runs to the end of the line
+            "pv" => info.pv = tokens.by_ref().map(String::from).collect(),
+            // Everything after `string` is free text
+            "string" => break,
+            _ => (),
+        }
+    }
+
+    match info == UciInfo::default() {
+        true => None,
+        false => Some(info),
+    }
+}
+
+/// ### Parses a `bestmove` line into the move in UCI notation
+///
+/// Engines report `bestmove (none)` or `bestmove 0000` when they have no legal move, which gives `Some(None)`
+pub fn parse_bestmove(line: &str) -> Option<Option<String>> {
+    let mut tokens = line.split_whitespace();
+    if tokens.next() != Some("bestmove") {
+        return None;
+    }
+    match tokens.next() {
+        None | Some("(none)") | Some("0000") => Some(None),
+        Some(best) => Some(Some(best.to_string())),
+    }
+}
+
+/// ### What an engine found with `go`
+///
+/// `info` holds the deepest search details the engine reported, merged across its `info` lines
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UciSearch {
+    pub best: Option<String>,
+    pub info: UciInfo,
+}
+
+/// ### An external engine speaking the Universal Chess Interface
+///
+/// The process is killed when this is dropped, or when it stops answering
+pub struct UciEngine {
+    name: Option<String>,
+    grace: Duration,
+    /// Set once the engine is killed for not answering, after which it has to be restarted
+    dead: bool,
+    child: Child,
+    stdin: ChildStdin,
+    lines: Lines<BufReader<ChildStdout>>,
+}
+
+impl UciEngine {
+    /// ### Starts the engine and waits until it is ready
+    ///
+    /// Sends `uci`, sets the configured options, then waits on `readyok`
+    pub async fn spawn(config: &UciConfig) -> Result<Self, UciError> {
+        let mut child = Command::new(&config.path)
+            .args(&config.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()?;
+
+        let stdin = child.stdin.take().ok_or(UciError::Closed)?;
+        let stdout = child.stdout.take().ok_or(UciError::Closed)?;
+
+        let mut engine = Self {
+            name: None,
+            grace: Duration::from_millis(config.grace),
+            dead: false,
+            child,
+            stdin,
+            lines: BufReader::new(stdout).lines(),
+        };
+
+        engine.send("uci").await?;
+        let mut name = None;
+        time::timeout(HANDSHAKE_TIMEOUT, async {
+            loop {
+                let line = engine.next_line().await?;
+                if let Some(id) = line.strip_prefix("id name ") {
+                    name = Some(id.trim().to_string());
+                }
+                if line.trim() == "uciok" {
+                    return Ok::<_, UciError>(());
+                }
+            }
+        })
+        .await
+        .map_err(|_| UciError::Timeout("uciok"))??;
+        engine.name = name;
+
+        for (option, value) in &config.options {
+            engine.send(&format!("setoption name {option} value {value}")).await?;
+        }
+        engine.ready().await?;
+
+        Ok(engine)
+    }
+
+    /// The name the engine gave with `id name`, if any
+    pub fn name(&self) -> Option<&String> {
+        self.name.as_ref()
+    }
+
+    /// Sends `isready` and waits on `readyok`
+    pub async fn ready(&mut self) -> Result<(), UciError> {
+        self.send("isready").await?;
+        time::timeout(HANDSHAKE_TIMEOUT, async {
+            while self.next_line().await?.trim() != "readyok" {}
+            Ok(())
+        })
+        .await
+        .map_err(|_| UciError::Timeout("readyok"))?
+    }
+
+    /// Tells the engine the next positions are from a different game
+    pub async fn new_game(&mut self) -> Result<(), UciError> {
+        self.send("ucinewgame").await?;
+        self.ready().await
+    }
+
+    /// ### Searches a position given as a FEN and the UCI moves played from it
+    ///
+    /// Collects `info` lines until the engine answers with `bestmove`.
+    /// Returns `Err(UciError::Unresponsive)` if the engine was killed after an earlier search
+    pub async fn go(&mut self, fen: &str, moves: &[String], limits: &GoLimits) -> Result<UciSearch, UciError> {
+        if self.dead {
+            return Err(UciError::Unresponsive);
+        }
+
+        let position = match moves.is_empty() {
+            true => format!("position fen {fen}"),
+            false => format!("position fen {fen} moves {}", moves.join(" ")),
+        };
+        self.send(&position).await?;
+        self.send(&limits.command()).await?;
+
+        let deadline = match limits.deadline(self.grace) {
+            Some(deadline) => deadline,
+            None => return self.search().await,
+        };
+        match time::timeout(deadline, self.search()).await {
+            Ok(search) => search,
+            Err(_) => {
+                self.stop().await;
+                Err(UciError::Timeout("bestmove"))
+            }
+        }
+    }
+
+    /// Reads the engine's output until it answers with `bestmove`
+    async fn search(&mut self) -> Result<UciSearch, UciError> {
+        let mut info = UciInfo::default();
+        loop {
+            let line = self.next_line().await?;
+            if let Some(best) = parse_bestmove(&line) {
+                return Ok(UciSearch { best, info });
+            }
+            if let Some(update) = parse_info(&line) {
+                info.merge(update);
+            }
+        }
+    }
+
+    /// ### Stops a search that ran out of time
+    ///
+    /// Reads up to the `bestmove` the engine still owes, so it isn't taken as the answer to the next search.
+    /// If it doesn't come in time, the engine is killed
+    async fn stop(&mut self) {
+        let grace = self.grace;
+        let stopped = async {
+            self.send("stop").await?;
+            self.search().await
+        };
+        if !matches!(time::timeout(grace, stopped).await, Ok(Ok(_))) {
+            self.dead = true;
+            let _ = self.child.start_kill();
+        }
+    }
+
+    /// Asks the engine to exit
+    pub async fn quit(mut self) {
+        let _ = self.send("quit").await;
+    }
+
+    async fn send(&mut self, command: &str) -> Result<(), UciError> {
+        self.stdin.write_all(format!("{command}\n").as_bytes()).await?;
+        self.stdin.flush().await?;
+        Ok(())
+    }
+
+    async fn next_line(&mut self) -> Result<String, UciError> {
+        self.lines.next_line().await?.ok_or(UciError::Closed)
+    }
+}
+
+impl UciInfo {
+    /// Takes the newer details, keeping older ones that the update left out
+    fn merge(&mut self, update: UciInfo) {
+        if update.depth.is_some() {
+            self.depth = update.depth;
+        }
+        if update.score.is_some() {
+            self.score = update.score;
+        }
+        if update.nodes.is_some() {
+            self.nodes = update.nodes;
+        }
+        if !update.pv.is_empty() {
+            self.pv = update.pv;
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum UciError {
+    Io(std::io::Error),
+    Timeout(&'static str),
+    Closed,
+    Unresponsive,
+}
+
+impl From<std::io::Error> for UciError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl Display for UciError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "Io: Could not talk to the engine ({error})"),
+            Self::Timeout(reply) => write!(f, "Timeout: The engine did not reply with {reply} in time"),
+            Self::Closed => write!(f, "Closed: The engine exited unexpectedly"),
+            Self::Unresponsive => write!(f, "Unresponsive: The engine stopped answering and has to be restarted"),
+        }
+    }
+}
+
+impl Error for UciError {}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// ### A stand-in engine that always plays `bestmove`
+    ///
+    /// It answers the handshake, and reports a short search before every move
+    pub fn scripted_engine(bestmove: &str) -> UciConfig {
+        let script = format!(
+            r#"while read -r line; do
+                case "$line" in
+                    uci) echo "id name Scripted"; echo "uciok" ;;
+                    isready) echo "readyok" ;;
+                    go*) echo "info depth 1 score cp 13 nodes 20 pv {bestmove}"
+                         echo "info depth 2 nodes 45"
+                         echo "bestmove {bestmove}" ;;
+                    quit) exit 0 ;;
+                esac
+            done"#
+        );
+        UciConfig {
+            path: "sh".to_string(),
+            args: vec!["-c".to_string(), script],
+            options: BTreeMap::new(),
+            movetime: 100,
+            grace: 100,
+        }
+    }
+
+    /// A stand-in engine that starts up, then never answers `go` or `stop`
+    pub fn silent_engine() -> UciConfig {
+        shell_engine(
+            r#"while read -r line; do
+                case "$line" in
+                    uci) echo "uciok" ;;
+                    isready) echo "readyok" ;;
+                esac
+            done"#,
+        )
+    }
+
+    fn shell_engine(script: &str) -> UciConfig {
+        UciConfig {
+            path: "sh".to_string(),
+            args: vec!["-c".to_string(), script.to_string()],
+            options: BTreeMap::new(),
+            movetime: 100,
+            grace: 100,
+        }
+    }
+
+    #[test]
+    fn parses_engine_output() {
+        assert_eq!(
+            parse_info("info depth 12 seldepth 18 multipv 1 score mate -3 nodes 1234 nps 99 pv e2e4 e7e5 g1f3"),
+            Some(UciInfo {
+                depth: Some(12),
+                score: Some(UciScore::Mate(-3)),
+                nodes: Some(1234),
+                pv: vec!["e2e4".to_string(), "e7e5".to_string(), "g1f3".to_string()],
+            })
+        );
+        assert_eq!(
+            parse_info("info score cp -25 lowerbound depth 3").and_then(|info| info.score),
+            Some(UciScore::Centipawns(-25))
+        );
+        assert_eq!(parse_info("info string NNUE evaluation using nn.bin"), None);
+        assert_eq!(parse_info("readyok"), None);
+
+        assert_eq!(
+            parse_bestmove("bestmove e7e8q ponder a1a2"),
+            Some(Some("e7e8q".to_string()))
+        );
+        assert_eq!(parse_bestmove("bestmove (none)"), Some(None));
+        assert_eq!(parse_bestmove("info depth 1"), None);
+    }
+
+    #[test]
+    fn builds_go_commands() {
+        let clock = GoClock {
+            white: Duration::from_secs(60),
+            black: Duration::from_millis(59_500),
+            increment: Duration::from_secs(2),
+        };
+        assert_eq!(
+            GoLimits {
+                clock: Some(clock),
+                ..GoLimits::default()
+            }
+            .command(),
+            "go wtime 60000 btime 59500 winc 2000 binc 2000"
+        );
+        assert_eq!(
+            GoLimits {
+                movetime: Some(Duration::from_millis(250)),
+                depth: Some(8),
+                ..GoLimits::default()
+            }
+            .command(),
+            "go movetime 250 depth 8"
+        );
+    }
+
+    #[tokio::test]
+    async fn talks_to_an_engine_process() {
+        let mut engine = UciEngine::spawn(&scripted_engine("g1f3")).await.unwrap();
+        assert_eq!(engine.name(), Some(&"Scripted".to_string()));
+        engine.new_game().await.unwrap();
+
+        let limits = GoLimits {
+            movetime: Some(Duration::from_millis(100)),
+            ..GoLimits::default()
+        };
+        let search = engine
+            .go("startpos-fen", &["e2e4".to_string(), "e7e5".to_string()], &limits)
+            .await
+            .unwrap();
+
+        assert_eq!(search.best, Some("g1f3".to_string()));
+        assert_eq!(
+            search.info,
+            UciInfo {
+                depth: Some(2),
+                score: Some(UciScore::Centipawns(13)),
+                nodes: Some(45),
+                pv: vec!["g1f3".to_string()],
+            }
+        );
+        engine.quit().await;
+    }
+
+    #[tokio::test]
+    async fn late_answers_are_not_taken_for_the_next_search() {
+        // Only answers `go` when told to stop, until its second search
+        let mut engine = UciEngine::spawn(&shell_engine(
+            r#"searches=0
+            while read -r line; do
+                case "$line" in
+                    uci) echo "uciok" ;;
+                    isready) echo "readyok" ;;
+                    go*) searches=$((searches + 1))
+                         if [ "$searches" -gt 1 ]; then echo "bestmove e2e4"; fi ;;
+                    stop) echo "bestmove a2a3" ;;
+                esac
+            done"#,
+        ))
+        .await
+        .unwrap();
+
+        let limits = GoLimits {
+            movetime: Some(Duration::from_millis(100)),
+            ..GoLimits::default()
+        };
+        assert!(matches!(
+            engine.go("fen", &[], &limits).await,
+            Err(UciError::Timeout("bestmove"))
+        ));
+        assert_eq!(
+            engine.go("fen", &[], &limits).await.unwrap().best,
+            Some("e2e4".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn engines_that_ignore_stop_are_killed() {
+        let mut engine = UciEngine::spawn(&silent_engine()).await.unwrap();
+
+        let limits = GoLimits {
+            movetime: Some(Duration::from_millis(100)),
+            ..GoLimits::default()
+        };
+        assert!(matches!(
+            engine.go("fen", &[], &limits).await,
+            Err(UciError::Timeout("bestmove"))
+        ));
+        assert!(matches!(
+            engine.go("fen", &[], &limits).await,
+            Err(UciError::Unresponsive)
+        ));
+    }
+
+    #[tokio::test]
+    async fn missing_engines_fail_to_start() {
+        let config = UciConfig {
+            path: "./no-such-engine".to_string(),
+            args: vec![],
+            options: BTreeMap::new(),
+            movetime: 100,
+            grace: 100,
+        };
+        assert!(matches!(UciEngine::spawn(&config).await, Err(UciError::Io(_))));
+    }
+}
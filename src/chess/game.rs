@@ -1,57 +1,168 @@
-use std::{sync::Arc, time::Duration};
+use std::{collections::HashMap, fmt::Display, str::FromStr, sync::Arc, time::Duration};
 
 use futures_util::{Future, FutureExt};
 use rand::{seq::SliceRandom, thread_rng};
 use serde::{Deserialize, Serialize};
-use tokio::{sync::broadcast, time::sleep};
+use tokio::{
+    sync::{broadcast, mpsc},
+    time::sleep,
+};
 
 use self::{
-    board::Board,
-    network::{ActionInterface, ApprovedChatMessage, MessageInterface, PlayerInterface},
-    pieces::ValidMove,
+    board::{Board, FenError, STARTING_FEN},
+    clock::Clock,
+    network::{
+        Action, ActionInterface, ActionType, Event, MessageInterface, PlayerInterface, RoomMessage, SpectatorEvent,
+        SpectatorInterface,
+    },
+    pieces::{TeamColor, ValidMove},
+};
+use super::rating::TimeControl;
+use crate::server::{
+    database::{games::GameRecord, Database, DatabaseMessage, DatabaseResult},
+    user::UserInfo,
+    utils::get_timestamp,
 };
 
 pub mod board;
+pub mod chat;
+pub mod clock;
 pub mod network;
 pub mod pieces;
 pub mod player;
 
 pub struct Game<S> {
+    code: String,
+    config: GameConfig,
+
     black: PlayerInterface,
     white: PlayerInterface,
 
     board: board::Board,
     move_history: Vec<ValidMove>,
+    move_times: Vec<u128>,
+    started: u128,
+    repetitions: HashMap<u64, u8>,
+    clock: Option<Clock>,
 
     actions: ActionInterface,
+    draw_offer: Option<TeamColor>,
+    undo_request: Option<TeamColor>,
 
     pub messenger: Arc<MessageInterface>,
+    spectators: Arc<SpectatorInterface>,
+
+    rating_pool: Option<TimeControl>,
+    db_tx: Option<mpsc::Sender<DatabaseMessage>>,
 
     state: S,
 }
 
 impl<S> Game<S> {
-    pub fn spectate(&self) -> broadcast::Receiver<ApprovedChatMessage> {
-        self.messenger.spectate()
+    pub fn spectate(&self) -> broadcast::Receiver<RoomMessage> {
+        self.messenger.spectators()
+    }
+
+    /// Sends an event to both players
+    async fn broadcast(&self, event: Event) {
+        let _ = self.white.send_event(event.clone()).await;
+        let _ = self.black.send_event(event).await;
+    }
+
+    fn player(&self, color: &TeamColor) -> &PlayerInterface {
+        match color {
+            TeamColor::White => &self.white,
+            TeamColor::Black => &self.black,
+        }
+    }
+
+    fn color_of(&self, user: &UserInfo) -> Option<TeamColor> {
+        if self.white.user() == user {
+            Some(TeamColor::White)
+        } else if self.black.user() == user {
+            Some(TeamColor::Black)
+        } else {
+            None
+        }
+    }
+
+    /// How many times the current position has been reached
+    fn repetition_count(&self) -> u8 {
+        self.repetitions.get(&self.board.zobrist_hash()).copied().unwrap_or(0)
+    }
+
+    /// ### Checks if the side to move may claim a draw
+    ///
+    /// A draw may be claimed once a position repeats three times, or after fifty
+    /// moves by each side without a capture or pawn move
+    fn claimable_draw(&self) -> Option<EndState> {
+        if self.repetition_count() >= 3 {
+            Some(EndState::RepeatThree)
+        } else if self.board.halfmove_clock >= 100 {
+            Some(EndState::FiftyMove)
+        } else {
+            None
+        }
     }
 }
 
 pub struct InactiveGame {
+    code: String,
     config: GameConfig,
+    board: Board,
     player1: PlayerInterface,
 
     actions: ActionInterface,
 
     pub messenger: Arc<MessageInterface>,
+    spectators: Arc<SpectatorInterface>,
+
+    db_tx: Option<mpsc::Sender<DatabaseMessage>>,
 }
 
 impl InactiveGame {
-    pub fn new(interface: PlayerInterface, actions: ActionInterface, config: GameConfig) -> Self {
-        Self {
+    /// ### Creates a game waiting on its second player
+    ///
+    /// Results are only recorded if a database is given
+    ///
+    /// Returns `Err(FenError)` if the config's starting position is not valid
+    pub fn new(
+        code: String,
+        interface: PlayerInterface,
+        actions: ActionInterface,
+        config: GameConfig,
+        db_tx: Option<mpsc::Sender<DatabaseMessage>>,
+    ) -> Result<Self, FenError> {
+        let board = Board::new(&config.starting_fen, config.height, config.width)?;
+        let messenger = MessageInterface::create(!config.chat_disabled);
+        Ok(Self {
+            code,
+            spectators: SpectatorInterface::create(&board, config.spectator_delay, &messenger),
             config,
+            board,
             player1: interface,
-            messenger: MessageInterface::create(),
+            messenger,
             actions,
+            db_tx,
+        })
+    }
+
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    pub fn spectators(&self) -> &Arc<SpectatorInterface> {
+        &self.spectators
+    }
+
+    /// Only games between registered users from the standard starting position are rated
+    fn rating_pool(&self, player2: &PlayerInterface) -> Option<TimeControl> {
+        let guest_playing = self.player1.user().get_handle().is_none() || player2.user().get_handle().is_none();
+        let standard = self.config.starting_fen == STARTING_FEN && self.config.width == 8 && self.config.height == 8;
+
+        match guest_playing || !standard {
+            true => None,
+            false => Some(self.config.time_control()),
         }
     }
 
@@ -62,11 +173,11 @@ impl InactiveGame {
 
 impl From<(InactiveGame, PlayerInterface)> for Game<PlayerTurn> {
     fn from((value, player2): (InactiveGame, PlayerInterface)) -> Self {
-        // TODO: Add timed games
-        // match value.config.time {
-        //     TimeConfig::NotTimed => (),
-        //     TimeConfig::Timed { limit, added } => (),
-        // }
+        let rating_pool = value.rating_pool(&player2);
+        let clock = match value.config.time {
+            TimeConfig::NotTimed => None,
+            TimeConfig::Timed { limit, added } => Some(Clock::new(limit, added)),
+        };
 
         let (black, white) = match value.config.player1_color {
             TeamConfig::White => (player2, value.player1),
@@ -81,79 +192,289 @@ impl From<(InactiveGame, PlayerInterface)> for Game<PlayerTurn> {
             }
         };
 
+        let turn = match value.board.turn {
+            TeamColor::White => Turn::White,
+            TeamColor::Black => Turn::Black,
+        };
+
+        let repetitions = HashMap::from([(value.board.zobrist_hash(), 1)]);
+        value.actions.open();
+        value.messenger.set_players(white.user(), black.user());
+        value.spectators.set_players(
+            white.user(),
+            black.user(),
+            clock.as_ref().map(|clock| clock.state(&value.board.turn)),
+        );
+
         Self {
+            code: value.code,
+            config: value.config,
+
             black,
             white,
 
-            board: Board::new(value.config.starting_fen, value.config.height, value.config.width),
+            board: value.board,
             move_history: vec![],
+            move_times: vec![],
+            started: get_timestamp(),
+            repetitions,
+            clock,
 
             messenger: value.messenger,
+            spectators: value.spectators,
+
+            rating_pool,
+            db_tx: value.db_tx,
 
             actions: value.actions,
+            draw_offer: None,
+            undo_request: None,
 
-            state: PlayerTurn {
-                turn: match value.config.white_starts {
-                    true => Turn::White,
-                    false => Turn::Black,
-                },
-            },
+            state: PlayerTurn { turn },
         }
     }
 }
 
 impl Game<PlayerTurn> {
-    async fn get_valid_move(&self) -> Result<ValidMove, ()> {
-        match self.state.turn {
-            Turn::White => &self.white,
-            Turn::Black => &self.black,
-        }
-        .valid_move(&self.board)
-        .await
-    }
     fn wait_for_player(mut self) -> std::pin::Pin<Box<dyn Future<Output = ()> + Send>> {
         async move {
+            let player = match self.state.turn {
+                Turn::White => &self.white,
+                Turn::Black => &self.black,
+            };
+
+            let time_left = self
+                .clock
+                .as_ref()
+                .map(|clock| clock.remaining(&self.board.turn, &self.board.turn));
+            let flag_fall = async {
+                match time_left {
+                    Some(time_left) => sleep(time_left).await,
+                    None => std::future::pending().await,
+                }
+            };
+
             let turn_event = tokio::select! {
-                m = self.get_valid_move() => TurnEvent::Move(m.unwrap())
-                // TODO: Manage undo requests here
-                // TODO: Manage draw offer requests here
-                // TODO: Manage timeout here
-                // TODO: Manage resignation here
+                m = player.valid_move(&self.board) => match m {
+                    Ok(vm) => TurnEvent::Move(vm),
+                    Err(()) => self.abandon(),
+                },
+                action = self.actions.recv() => self.handle_action(action).await,
+                _ = flag_fall => self.flag_fall(),
             };
 
             match turn_event {
                 TurnEvent::Move(vm) => {
-                    self.board.make_move(vm);
+                    let mover = self.board.turn;
+                    self.board.make_move(&vm);
+                    *self.repetitions.entry(self.board.zobrist_hash()).or_insert(0) += 1;
+
+                    if let Some(clock) = &mut self.clock {
+                        clock.end_turn(&mover);
+                    }
+
+                    // Moving instead of answering declines any pending offers
+                    if self.draw_offer != Some(mover) {
+                        self.draw_offer = None;
+                    }
+                    self.undo_request = None;
+
+                    let clock = self.clock.as_ref().map(|clock| clock.state(&self.board.turn));
+
+                    self.broadcast(Event::ValidMove {
+                        valid_move: vm.clone(),
+                        clock,
+                    })
+                    .await;
+                    self.spectators.publish(
+                        &self.board,
+                        SpectatorEvent::Move {
+                            valid_move: vm.clone(),
+                            clock,
+                        },
+                    );
+                    self.move_history.push(vm);
+                    self.move_times.push(get_timestamp());
                     Game::<Calculating>::from(self).calculate().await;
                 }
-                TurnEvent::Undo => (),
-                TurnEvent::OfferDraw => (),
-                TurnEvent::GameEnd(..) => (),
+                TurnEvent::Undo(plies) => {
+                    self.undo(plies).await;
+                    self.wait_for_player().await
+                }
+                TurnEvent::GameEnd(winner, state) => Game::<Ended>::from((self, winner, state)).end_game().await,
+                TurnEvent::Continue => self.wait_for_player().await,
             }
         }
         .boxed()
     }
+
+    /// ### Ends the game when the player to move runs out of time
+    ///
+    /// Their opponent wins, unless the opponent could never checkmate, which makes it a draw
+    fn flag_fall(&self) -> TurnEvent {
+        let opponent = self.board.turn.opposite();
+        let winner = match (self.board.cannot_checkmate(&opponent), opponent) {
+            (true, _) => Winner::None,
+            (false, TeamColor::White) => Winner::White,
+            (false, TeamColor::Black) => Winner::Black,
+        };
+        TurnEvent::GameEnd(winner, EndState::Timeout)
+    }
+
+    /// ### Ends the game when the player to move can no longer be reached
+    ///
+    /// Their opponent wins, as if they had resigned
+    fn abandon(&self) -> TurnEvent {
+        let winner = match self.board.turn {
+            TeamColor::White => Winner::Black,
+            TeamColor::Black => Winner::White,
+        };
+        TurnEvent::GameEnd(winner, EndState::Abandoned)
+    }
+
+    /// ### Takes back the last few moves
+    ///
+    /// Rolls back the board, history and repetitions, and tells both players about each undone move
+    async fn undo(&mut self, plies: usize) {
+        for _ in 0..plies {
+            let vm = match self.move_history.pop() {
+                Some(vm) => vm,
+                None => break,
+            };
+            self.move_times.pop();
+
+            if let Some(count) = self.repetitions.get_mut(&self.board.zobrist_hash()) {
+                *count = count.saturating_sub(1);
+            }
+            self.board.unmake_move(&vm);
+
+            if let Some(clock) = &mut self.clock {
+                clock.take_back(&self.board.turn);
+            }
+
+            self.spectators
+                .publish(&self.board, SpectatorEvent::MoveWasUndone(vm.clone()));
+            self.broadcast(Event::MoveWasUndone(vm)).await;
+        }
+
+        self.state.turn = match self.board.turn {
+            TeamColor::White => Turn::White,
+            TeamColor::Black => Turn::Black,
+        };
+    }
+
+    /// How many moves must be undone to take back the given player's last move
+    fn plies_to_undo(&self, color: &TeamColor) -> Option<usize> {
+        let plies = match color == &self.board.turn {
+            true => 2,
+            false => 1,
+        };
+        (self.move_history.len() >= plies).then_some(plies)
+    }
+
+    async fn handle_action(&mut self, action: Action) -> TurnEvent {
+        let color = match self.color_of(&action.sender) {
+            Some(color) => color,
+            None => return TurnEvent::Continue,
+        };
+
+        match action.kind {
+            ActionType::Resign => {
+                let winner = match color {
+                    TeamColor::White => Winner::Black,
+                    TeamColor::Black => Winner::White,
+                };
+                TurnEvent::GameEnd(winner, EndState::Resignation)
+            }
+            ActionType::OfferDraw => {
+                if self.draw_offer.is_none() {
+                    self.draw_offer = Some(color);
+                    self.broadcast(Event::OfferDraw(color)).await;
+                }
+                TurnEvent::Continue
+            }
+            ActionType::AcceptDraw => match self.draw_offer {
+                Some(offerer) if offerer != color => TurnEvent::GameEnd(Winner::None, EndState::Agreement),
+                _ => TurnEvent::Continue,
+            },
+            ActionType::RequestUndo => {
+                if self.undo_request.is_none() && self.plies_to_undo(&color).is_some() {
+                    self.undo_request = Some(color);
+                    self.broadcast(Event::RequestUndo(color)).await;
+                }
+                TurnEvent::Continue
+            }
+            ActionType::AcceptUndo => {
+                let requester = match self.undo_request {
+                    Some(requester) if requester != color => requester,
+                    _ => return TurnEvent::Continue,
+                };
+                self.undo_request = None;
+                self.draw_offer = None;
+
+                match self.plies_to_undo(&requester) {
+                    Some(plies) => TurnEvent::Undo(plies),
+                    None => TurnEvent::Continue,
+                }
+            }
+            ActionType::Nudge => {
+                // Only the player waiting on their opponent may nudge
+                if color != self.board.turn {
+                    let _ = self.player(&self.board.turn).send_event(Event::Nudge).await;
+                }
+                TurnEvent::Continue
+            }
+            ActionType::ClaimDraw => {
+                // Only the player to move may claim a draw
+                let claim = match color == self.board.turn {
+                    true => self.claimable_draw(),
+                    false => None,
+                };
+
+                match claim {
+                    Some(state) => TurnEvent::GameEnd(Winner::None, state),
+                    None => {
+                        let _ = self.player(&color).send_event(Event::DrawClaimRejected).await;
+                        TurnEvent::Continue
+                    }
+                }
+            }
+        }
+    }
 }
 
 enum TurnEvent {
     Move(ValidMove),
-    Undo,
-    OfferDraw,
+    Undo(usize),
     GameEnd(Winner, EndState),
+    Continue,
 }
 
 impl From<Game<PlayerTurn>> for Game<Calculating> {
     fn from(value: Game<PlayerTurn>) -> Self {
         Self {
+            code: value.code,
+            config: value.config,
+
             black: value.black,
             white: value.white,
 
             board: value.board,
             move_history: value.move_history,
+            move_times: value.move_times,
+            started: value.started,
+            repetitions: value.repetitions,
+            clock: value.clock,
 
             messenger: value.messenger,
+            spectators: value.spectators,
+
+            rating_pool: value.rating_pool,
+            db_tx: value.db_tx,
 
             actions: value.actions,
+            draw_offer: value.draw_offer,
+            undo_request: value.undo_request,
 
             state: Calculating {
                 last_turn: value.state.turn,
@@ -165,7 +486,38 @@ impl From<Game<PlayerTurn>> for Game<Calculating> {
 impl Game<Calculating> {
     fn calculate(self) -> std::pin::Pin<Box<dyn Future<Output = ()> + Send>> {
         async move {
-            // Calculate things like check, checkmate, move lists, etc.
+            let in_check = self.board.is_in_check(&self.board.turn);
+
+            if self.board.legal_moves().is_empty() {
+                let (winner, state) = match in_check {
+                    true => (Winner::from(&self.state.last_turn), EndState::Checkmate),
+                    false => (Winner::None, EndState::Stalemate),
+                };
+                return Game::<Ended>::from((self, winner, state)).end_game().await;
+            }
+
+            // Draws that end the game without needing to be claimed
+            let automatic_draw = if self.board.insufficient_material() {
+                Some(EndState::InsufficientMaterial)
+            } else if self.repetition_count() >= 5 {
+                Some(EndState::RepeatFive)
+            } else if self.board.halfmove_clock >= 150 {
+                Some(EndState::SeventyFiveMove)
+            } else {
+                None
+            };
+
+            if let Some(state) = automatic_draw {
+                return Game::<Ended>::from((self, Winner::None, state)).end_game().await;
+            }
+
+            if in_check {
+                self.broadcast(Event::Check).await;
+            }
+
+            if self.claimable_draw().is_some() {
+                let _ = self.player(&self.board.turn).send_event(Event::CanClaimDraw).await;
+            }
 
             Game::<PlayerTurn>::from(self).wait_for_player().await;
         }
@@ -176,15 +528,28 @@ impl Game<Calculating> {
 impl From<Game<Calculating>> for Game<PlayerTurn> {
     fn from(value: Game<Calculating>) -> Self {
         Self {
+            code: value.code,
+            config: value.config,
+
             black: value.black,
             white: value.white,
 
             board: value.board,
             move_history: value.move_history,
+            move_times: value.move_times,
+            started: value.started,
+            repetitions: value.repetitions,
+            clock: value.clock,
 
             messenger: value.messenger,
+            spectators: value.spectators,
+
+            rating_pool: value.rating_pool,
+            db_tx: value.db_tx,
 
             actions: value.actions,
+            draw_offer: value.draw_offer,
+            undo_request: value.undo_request,
 
             state: PlayerTurn {
                 turn: value.state.last_turn.switch(),
@@ -193,17 +558,30 @@ impl From<Game<Calculating>> for Game<PlayerTurn> {
     }
 }
 
-impl From<(Game<Calculating>, Winner, EndState)> for Game<Ended> {
-    fn from(value: (Game<Calculating>, Winner, EndState)) -> Self {
+impl<S> From<(Game<S>, Winner, EndState)> for Game<Ended> {
+    fn from(value: (Game<S>, Winner, EndState)) -> Self {
         Self {
+            code: value.0.code,
+            config: value.0.config,
+
             black: value.0.black,
             white: value.0.white,
             board: value.0.board,
             move_history: value.0.move_history,
+            move_times: value.0.move_times,
+            started: value.0.started,
+            repetitions: value.0.repetitions,
+            clock: value.0.clock,
 
             messenger: value.0.messenger,
+            spectators: value.0.spectators,
+
+            rating_pool: value.0.rating_pool,
+            db_tx: value.0.db_tx,
 
             actions: value.0.actions,
+            draw_offer: value.0.draw_offer,
+            undo_request: value.0.undo_request,
 
             state: Ended {
                 winner: value.1,
@@ -215,12 +593,94 @@ impl From<(Game<Calculating>, Winner, EndState)> for Game<Ended> {
 
 impl Game<Ended> {
     async fn end_game(self) {
+        self.actions.close();
+        self.messenger.end();
+        self.update_ratings().await;
+        self.record_game().await;
+        self.broadcast(Event::GameEnd {
+            winner: self.state.winner,
+            state: self.state.state,
+        })
+        .await;
+        self.spectators.publish(
+            &self.board,
+            SpectatorEvent::GameEnd {
+                winner: self.state.winner,
+                state: self.state.state,
+            },
+        );
+
         sleep(Duration::from_secs(300)).await;
         self.messenger
             .stop()
             .await
             .expect("Messenger should always successfully shut down");
     }
+
+    /// Writes the finished game to the database
+    async fn record_game(&self) {
+        let db_tx = match &self.db_tx {
+            Some(db_tx) => db_tx,
+            None => return,
+        };
+
+        let record = GameRecord {
+            code: self.code.clone(),
+            white: self.white.user().clone(),
+            black: self.black.user().clone(),
+            winner: self.state.winner,
+            end_state: self.state.state,
+            time: self.config.time,
+            start_fen: self.config.starting_fen.clone(),
+            width: self.config.width,
+            height: self.config.height,
+            moves: self
+                .move_history
+                .iter()
+                .map(|vm| vm.to_coordinates(self.config.height))
+                .collect(),
+            move_times: self.move_times.clone(),
+            started: self.started,
+            ended: get_timestamp(),
+        };
+
+        let func = move |db: &Database| DatabaseResult::from(db.games().record_game(record));
+        match DatabaseMessage::send(func, db_tx).await {
+            Ok(DatabaseResult::ResultEmpty(Ok(_))) => (),
+            Ok(DatabaseResult::ResultEmpty(Err(e))) | Err(e) => {
+                eprint!("\rFailed to record game {} with error: {e}\n\n > ", self.code)
+            }
+            Ok(_) => eprint!("\rFailed to record game, database returned the wrong result\n\n > "),
+        }
+    }
+
+    /// Records the result in both players' ratings, if the game was rated
+    async fn update_ratings(&self) {
+        let (time_control, db_tx) = match (self.rating_pool, &self.db_tx) {
+            (Some(time_control), Some(db_tx)) => (time_control, db_tx),
+            _ => return,
+        };
+        let (white, black) = match (self.white.user().get_handle(), self.black.user().get_handle()) {
+            (Some(white), Some(black)) => (white, black),
+            _ => return,
+        };
+        let white_score = match self.state.winner {
+            Winner::White => 1.0,
+            Winner::None => 0.5,
+            Winner::Black => 0.0,
+        };
+
+        let func = move |db: &Database| {
+            DatabaseResult::from(db.ratings().record_game(white, black, time_control, white_score))
+        };
+        match DatabaseMessage::send(func, db_tx).await {
+            Ok(DatabaseResult::RatingPair(Ok(_))) => (),
+            Ok(DatabaseResult::RatingPair(Err(e))) | Err(e) => {
+                eprint!("\rFailed to update ratings with error: {e}\n\n > ")
+            }
+            Ok(_) => eprint!("\rFailed to update ratings, database returned the wrong result\n\n > "),
+        }
+    }
 }
 
 enum Turn {
@@ -245,25 +705,40 @@ struct Calculating {
     last_turn: Turn,
 }
 
-enum EndState {
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EndState {
     Checkmate,
     Resignation,
     Timeout,
+    /// The player to move stopped answering, such as a bot whose engine failed
+    Abandoned,
 
     Stalemate,
     InsufficientMaterial,
     FiftyMove,
+    SeventyFiveMove,
     RepeatThree,
+    RepeatFive,
 
     Agreement,
 }
 
-enum Winner {
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Winner {
     None,
     Black,
     White,
 }
 
+impl From<&Turn> for Winner {
+    fn from(value: &Turn) -> Self {
+        match value {
+            Turn::White => Self::White,
+            Turn::Black => Self::Black,
+        }
+    }
+}
+
 struct Ended {
     winner: Winner,
     state: EndState,
@@ -271,7 +746,6 @@ struct Ended {
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct GameConfig {
-    white_starts: bool,
     player1_color: TeamConfig,
 
     starting_fen: String,
@@ -280,33 +754,827 @@ pub struct GameConfig {
     height: u8,
 
     time: TimeConfig,
+
+    #[serde(default)]
+    spectator_delay: SpectatorDelay,
+    /// Turns off chat for everyone, players and spectators alike
+    #[serde(default)]
+    chat_disabled: bool,
 }
 
 impl Default for GameConfig {
     fn default() -> Self {
         Self {
-            white_starts: true,
             player1_color: TeamConfig::Random,
-            starting_fen: "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR".to_string(),
+            starting_fen: STARTING_FEN.to_string(),
             time: TimeConfig::Timed {
                 limit: Duration::from_secs(600),
                 added: Duration::from_secs(5),
             },
             width: 8,
             height: 8,
+            spectator_delay: SpectatorDelay::None,
+            chat_disabled: false,
         }
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+impl GameConfig {
+    /// A standard game with the given clock
+    pub fn with_time(time: TimeConfig) -> Self {
+        Self {
+            time,
+            ..Self::default()
+        }
+    }
+
+    /// The time control this game would be rated in
+    pub fn time_control(&self) -> TimeControl {
+        TimeControl::from(&self.time)
+    }
+
+    pub fn spectator_delay(&self) -> SpectatorDelay {
+        self.spectator_delay
+    }
+
+    /// ### Checks the config describes a game that can be played
+    ///
+    /// The board must be a supported size, and its starting position legal with a move to make
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        for size in [self.width, self.height] {
+            if !(MIN_BOARD_SIZE..=MAX_BOARD_SIZE).contains(&size) {
+                return Err(ConfigError::InvalidSize(size));
+            }
+        }
+
+        if let TimeConfig::Timed { limit, added } = self.time {
+            if limit.is_zero() || limit > MAX_TIME_LIMIT || added > MAX_TIME_ADDED {
+                return Err(ConfigError::InvalidTime);
+            }
+        }
+
+        let delay_too_long = match self.spectator_delay {
+            SpectatorDelay::None => false,
+            SpectatorDelay::Moves(plies) => plies > MAX_SPECTATOR_DELAY_MOVES,
+            SpectatorDelay::Time(delay) => delay > MAX_SPECTATOR_DELAY_TIME,
+        };
+        if delay_too_long {
+            return Err(ConfigError::InvalidSpectatorDelay);
+        }
+
+        let board = Board::new(&self.starting_fen, self.height, self.width)?;
+        for color in [TeamColor::White, TeamColor::Black] {
+            if board.pieces(&color).filter(|piece| piece.is_king()).count() != 1 {
+                return Err(ConfigError::KingCount(color));
+            }
+        }
+        if board.is_in_check(&board.turn.opposite()) {
+            return Err(ConfigError::WaitingSideInCheck);
+        }
+        if board.legal_moves().is_empty() {
+            return Err(ConfigError::NoLegalMoves);
+        }
+
+        Ok(())
+    }
+}
+
+/// Boards are square-ish grids between these sizes, so tiles can be named by a letter and number
+const MIN_BOARD_SIZE: u8 = 4;
+const MAX_BOARD_SIZE: u8 = 16;
+
+const MAX_TIME_LIMIT: Duration = Duration::from_secs(3 * 60 * 60);
+const MAX_TIME_ADDED: Duration = Duration::from_secs(3 * 60);
+
+const MAX_SPECTATOR_DELAY_MOVES: u8 = 40;
+const MAX_SPECTATOR_DELAY_TIME: Duration = Duration::from_secs(30 * 60);
+
+#[derive(Debug)]
+pub enum ConfigError {
+    InvalidSize(u8),
+    InvalidTime,
+    InvalidFen(FenError),
+    KingCount(TeamColor),
+    WaitingSideInCheck,
+    NoLegalMoves,
+    InvalidSpectatorDelay,
+}
+
+impl From<FenError> for ConfigError {
+    fn from(value: FenError) -> Self {
+        Self::InvalidFen(value)
+    }
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidSize(size) => write!(
+                f,
+                "InvalidSize: Boards must be {MIN_BOARD_SIZE} to {MAX_BOARD_SIZE} tiles across, not {size}"
+            ),
+            Self::InvalidTime => write!(
+                f,
+                "InvalidTime: Clocks must start with up to 3 hours, and add up to 3 minutes per move"
+            ),
+            Self::InvalidFen(e) => write!(f, "InvalidFen: The starting position is not valid ({e})"),
+            Self::KingCount(color) => write!(f, "KingCount: {color:?} must have exactly one king"),
+            Self::WaitingSideInCheck => write!(
+                f,
+                "WaitingSideInCheck: The side that is not moving first cannot start in check"
+            ),
+            Self::NoLegalMoves => write!(f, "NoLegalMoves: The side moving first has no legal moves"),
+            Self::InvalidSpectatorDelay => write!(
+                f,
+                "InvalidSpectatorDelay: Spectators can be held back by up to 40 moves or 30 minutes"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
 pub enum TimeConfig {
     NotTimed,
     Timed { limit: Duration, added: Duration },
 }
 
+/// Written as in PGN, such as `600+5` for ten minutes with five seconds added, or `-` when untimed
+impl Display for TimeConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotTimed => write!(f, "-"),
+            Self::Timed { limit, added } => write!(f, "{}+{}", limit.as_secs(), added.as_secs()),
+        }
+    }
+}
+
+impl FromStr for TimeConfig {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "-" {
+            return Ok(Self::NotTimed);
+        }
+        let (limit, added) = s.split_once('+').ok_or(())?;
+        Ok(Self::Timed {
+            limit: Duration::from_secs(limit.parse().map_err(|_| ())?),
+            added: Duration::from_secs(added.parse().map_err(|_| ())?),
+        })
+    }
+}
+
+/// ### How far behind the live game spectators are kept
+///
+/// Moves are counted for each player, so `Moves(2)` holds back one move from each side.
+/// Only spectators are delayed, and everything held back is shown as soon as the game ends
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+pub enum SpectatorDelay {
+    #[default]
+    None,
+    Moves(u8),
+    Time(Duration),
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub enum TeamConfig {
     White,
     Black,
     Random,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        chat::ChatError,
+        network::{Action, ApprovedChatMessage, ChatMessage, ChatRole, ChatRoom},
+        pieces::Move,
+        pieces::Position,
+        pieces::Turn,
+        *,
+    };
+    use crate::chess::engine::{
+        bot::{Bot, BotConfig, BotEngine},
+        uci::tests::{scripted_engine, silent_engine},
+        Strength,
+    };
+    use crate::server::user::{interface::GameInterface, UserInfo};
+    use tokio::sync::{mpsc, oneshot, watch};
+
+    type MoveRx = mpsc::Receiver<oneshot::Sender<(Turn, oneshot::Sender<bool>)>>;
+
+    struct TestPlayer {
+        user: UserInfo,
+        moves: MoveRx,
+        events: mpsc::Receiver<Event>,
+        actions: watch::Receiver<Option<mpsc::Sender<Action>>>,
+    }
+
+    impl TestPlayer {
+        async fn play(&mut self, source: &str, target: &str) -> bool {
+            let movement = Move {
+                source: Position::from_square(source, 8).unwrap(),
+                target: Position::from_square(target, 8).unwrap(),
+                promotion: None,
+            };
+            self.send_turn(movement.into()).await
+        }
+
+        async fn send_turn(&mut self, turn: Turn) -> bool {
+            let tx = loop {
+                let tx = self.moves.recv().await.expect("Game should ask for a move");
+                if !tx.is_closed() {
+                    break tx;
+                }
+            };
+            let (result_tx, result_rx) = oneshot::channel();
+            tx.send((turn, result_tx)).unwrap();
+            result_rx.await.unwrap()
+        }
+
+        async fn act(&self, kind: ActionType) {
+            let tx = self.actions.borrow().clone().expect("Game should be accepting actions");
+            tx.send(Action::new(self.user.clone(), kind)).await.unwrap();
+        }
+
+        async fn wait_for(&mut self, matches: impl Fn(&Event) -> bool) -> Event {
+            while let Some(event) = self.events.recv().await {
+                if matches(&event) {
+                    return event;
+                }
+            }
+            panic!("Event channel closed before the expected event arrived");
+        }
+
+        async fn wait_for_end(&mut self) -> (Winner, EndState) {
+            match self.wait_for(|e| matches!(e, Event::GameEnd { .. })).await {
+                Event::GameEnd { winner, state } => (winner, state),
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    fn start_game(config: GameConfig) -> (TestPlayer, TestPlayer) {
+        let (white, black, _) = start_watched_game(config);
+        (white, black)
+    }
+
+    fn start_watched_game(config: GameConfig) -> (TestPlayer, TestPlayer, Arc<SpectatorInterface>) {
+        let (white_user, black_user) = (UserInfo::new_guest(), UserInfo::new_guest());
+        let (white, white_moves, white_events) = PlayerInterface::create(white_user.clone());
+        let (black, black_moves, black_events) = PlayerInterface::create(black_user.clone());
+        let (actions, action_rx) = ActionInterface::create();
+
+        let config = GameConfig {
+            player1_color: TeamConfig::White,
+            ..config
+        };
+        let game = InactiveGame::new("test".to_string(), white, actions, config, None).unwrap();
+        let spectators = Arc::clone(game.spectators());
+        game.start(black);
+
+        (
+            TestPlayer {
+                user: white_user,
+                moves: white_moves,
+                events: white_events,
+                actions: action_rx.clone(),
+            },
+            TestPlayer {
+                user: black_user,
+                moves: black_moves,
+                events: black_events,
+                actions: action_rx,
+            },
+            spectators,
+        )
+    }
+
+    #[tokio::test]
+    async fn running_out_of_time_loses() {
+        let (mut white, mut black) = start_game(GameConfig {
+            starting_fen: "4k2r/8/8/8/8/8/8/4K3 w - - 0 1".to_string(),
+            time: TimeConfig::Timed {
+                limit: Duration::from_millis(50),
+                added: Duration::ZERO,
+            },
+            ..GameConfig::default()
+        });
+
+        assert_eq!(white.wait_for_end().await, (Winner::Black, EndState::Timeout));
+        assert_eq!(black.wait_for_end().await, (Winner::Black, EndState::Timeout));
+    }
+
+    #[tokio::test]
+    async fn running_out_of_time_draws_if_opponent_cannot_mate() {
+        let (mut white, _black) = start_game(GameConfig {
+            starting_fen: "4k3/8/8/8/8/8/7P/4K3 w - - 0 1".to_string(),
+            time: TimeConfig::Timed {
+                limit: Duration::from_millis(50),
+                added: Duration::ZERO,
+            },
+            ..GameConfig::default()
+        });
+
+        assert_eq!(white.wait_for_end().await, (Winner::None, EndState::Timeout));
+    }
+
+    #[tokio::test]
+    async fn moves_report_clocks_with_increment() {
+        let (mut white, mut black) = start_game(GameConfig {
+            time: TimeConfig::Timed {
+                limit: Duration::from_secs(60),
+                added: Duration::from_secs(5),
+            },
+            ..GameConfig::default()
+        });
+
+        assert!(white.play("e2", "e4").await);
+        let clock = match black.wait_for(|e| matches!(e, Event::ValidMove { .. })).await {
+            Event::ValidMove { clock, .. } => clock.expect("Timed games should report their clocks"),
+            _ => unreachable!(),
+        };
+
+        assert!(clock.white > Duration::from_secs(60));
+        assert!(clock.black <= Duration::from_secs(60));
+    }
+
+    #[tokio::test]
+    async fn resigning_ends_game() {
+        let (mut white, mut black) = start_game(GameConfig::default());
+
+        assert!(white.play("e2", "e4").await);
+        white.act(ActionType::Resign).await;
+
+        assert_eq!(black.wait_for_end().await, (Winner::Black, EndState::Resignation));
+    }
+
+    #[tokio::test]
+    async fn draw_offer_can_be_accepted() {
+        let (mut white, mut black) = start_game(GameConfig::default());
+
+        white.act(ActionType::OfferDraw).await;
+        assert!(matches!(
+            black.wait_for(|e| matches!(e, Event::OfferDraw(_))).await,
+            Event::OfferDraw(TeamColor::White)
+        ));
+
+        // The offering player moving does not withdraw their offer
+        assert!(white.play("e2", "e4").await);
+        black.act(ActionType::AcceptDraw).await;
+
+        assert_eq!(white.wait_for_end().await, (Winner::None, EndState::Agreement));
+    }
+
+    #[tokio::test]
+    async fn spectators_catch_up_then_follow_the_game() {
+        let (mut white, mut black, spectators) = start_watched_game(GameConfig::default());
+        let count = spectators.count();
+
+        assert!(white.play("e2", "e4").await);
+        black.wait_for(|e| matches!(e, Event::ValidMove { .. })).await;
+
+        let mut spectator = spectators.watch();
+        assert_eq!(*count.borrow(), 1);
+        match spectator.recv().await {
+            Some(SpectatorEvent::Snapshot(snapshot)) => {
+                assert_eq!(snapshot.moves.len(), 1);
+                assert_eq!(snapshot.white.as_ref(), Some(&white.user));
+                assert_eq!(
+                    snapshot.fen,
+                    "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1"
+                );
+            }
+            _ => panic!("Spectators should start with a snapshot"),
+        }
+
+        assert!(black.play("e7", "e5").await);
+        assert!(matches!(spectator.recv().await, Some(SpectatorEvent::Move { .. })));
+
+        black.act(ActionType::Resign).await;
+        assert!(matches!(
+            spectator.recv().await,
+            Some(SpectatorEvent::GameEnd {
+                winner: Winner::White,
+                state: EndState::Resignation
+            })
+        ));
+
+        drop(spectator);
+        assert_eq!(*count.borrow(), 0);
+    }
+
+    #[tokio::test]
+    async fn spectators_are_held_back_by_the_delay() {
+        let (mut white, mut black, spectators) = start_watched_game(GameConfig {
+            spectator_delay: SpectatorDelay::Moves(2),
+            ..GameConfig::default()
+        });
+
+        assert!(white.play("e2", "e4").await);
+        assert!(black.play("e7", "e5").await);
+        assert!(white.play("g1", "f3").await);
+        black.wait_for(|e| matches!(e, Event::ValidMove { .. })).await;
+
+        let mut spectator = spectators.watch();
+        match spectator.recv().await {
+            Some(SpectatorEvent::Snapshot(snapshot)) => assert_eq!(snapshot.moves.len(), 1),
+            _ => panic!("Spectators should start with a snapshot"),
+        }
+
+        // Ending the game shows everything that was held back
+        black.act(ActionType::Resign).await;
+        assert!(matches!(spectator.recv().await, Some(SpectatorEvent::Move { .. })));
+        assert!(matches!(spectator.recv().await, Some(SpectatorEvent::Move { .. })));
+        assert!(matches!(spectator.recv().await, Some(SpectatorEvent::GameEnd { .. })));
+    }
+
+    #[tokio::test]
+    async fn spectators_are_held_back_by_time() {
+        let (mut white, _black, spectators) = start_watched_game(GameConfig {
+            spectator_delay: SpectatorDelay::Time(Duration::from_millis(100)),
+            ..GameConfig::default()
+        });
+        let mut spectator = spectators.watch();
+        assert!(matches!(spectator.recv().await, Some(SpectatorEvent::Snapshot(_))));
+
+        let played = tokio::time::Instant::now();
+        assert!(white.play("e2", "e4").await);
+        assert!(matches!(spectator.recv().await, Some(SpectatorEvent::Move { .. })));
+        assert!(played.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn chat_rooms_are_kept_apart_until_the_game_ends() {
+        let (mut white, black, spectators) = start_watched_game(GameConfig::default());
+        let mut spectator = spectators.watch();
+        assert!(matches!(spectator.recv().await, Some(SpectatorEvent::Snapshot(_))));
+        let messenger = spectator.messenger();
+        let mut players = messenger.players();
+        let say = |user: &UserInfo, text: &str| {
+            ApprovedChatMessage::try_from(ChatMessage::new(user.clone(), text.to_string())).unwrap()
+        };
+
+        messenger
+            .send(ChatRole::Player, say(&white.user, "good luck"))
+            .await
+            .unwrap();
+        messenger
+            .send(ChatRole::Spectator, say(&UserInfo::new_guest(), "take the knight"))
+            .await
+            .unwrap();
+        assert!(matches!(players.recv().await, Ok((ChatRoom::Players, _))));
+        assert!(matches!(
+            spectator.recv().await,
+            Some(SpectatorEvent::Message {
+                room: ChatRoom::Spectators,
+                ..
+            })
+        ));
+
+        assert!(messenger.mute(&white.user, true));
+        assert!(messenger.is_muted(&white.user, &say(&black.user, "hi")));
+        assert!(!messenger.is_muted(&black.user, &say(&white.user, "hi")));
+
+        black.act(ActionType::Resign).await;
+        white.wait_for_end().await;
+        assert!(matches!(spectator.recv().await, Some(SpectatorEvent::GameEnd { .. })));
+
+        // The spectators' message never reached the players, who now share a room with them
+        messenger
+            .send(ChatRole::Player, say(&white.user, "good game"))
+            .await
+            .unwrap();
+        assert!(matches!(players.recv().await, Ok((ChatRoom::PostGame, _))));
+        assert!(matches!(
+            spectator.recv().await,
+            Some(SpectatorEvent::Message {
+                room: ChatRoom::PostGame,
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn chat_can_be_disabled() {
+        let (white, _black, spectators) = start_watched_game(GameConfig {
+            chat_disabled: true,
+            ..GameConfig::default()
+        });
+        let message = ApprovedChatMessage::try_from(ChatMessage::new(white.user, "hello".to_string())).unwrap();
+
+        let sent = spectators.watch().messenger().send(ChatRole::Player, message).await;
+        assert!(matches!(sent, Err(ChatError::Disabled)));
+    }
+
+    #[tokio::test]
+    async fn draw_offer_expires_on_next_move() {
+        let (mut white, mut black) = start_game(GameConfig::default());
+
+        white.act(ActionType::OfferDraw).await;
+        black.wait_for(|e| matches!(e, Event::OfferDraw(_))).await;
+        assert!(white.play("e2", "e4").await);
+        assert!(black.play("e7", "e5").await);
+
+        // Black moved instead of accepting, so the offer is gone
+        black.act(ActionType::AcceptDraw).await;
+        black.act(ActionType::Resign).await;
+        assert_eq!(white.wait_for_end().await, (Winner::White, EndState::Resignation));
+    }
+
+    #[tokio::test]
+    async fn takeback_rolls_back_moves() {
+        let (mut white, mut black) = start_game(GameConfig::default());
+
+        assert!(white.play("e2", "e4").await);
+        assert!(black.play("e7", "e5").await);
+
+        // It is white's turn, so taking back white's last move undoes both
+        white.act(ActionType::RequestUndo).await;
+        black
+            .wait_for(|e| matches!(e, Event::RequestUndo(TeamColor::White)))
+            .await;
+        black.act(ActionType::AcceptUndo).await;
+
+        for _ in 0..2 {
+            white.wait_for(|e| matches!(e, Event::MoveWasUndone(_))).await;
+        }
+
+        assert!(!white.play("e4", "e5").await);
+        assert!(white.play("d2", "d4").await);
+
+        // Both undone moves give back the increment they earned
+        let clock = match white.wait_for(|e| matches!(e, Event::ValidMove { .. })).await {
+            Event::ValidMove { clock, .. } => clock.expect("Timed games should report their clocks"),
+            _ => unreachable!(),
+        };
+        assert!(clock.white <= Duration::from_secs(605));
+        assert!(clock.black <= Duration::from_secs(600));
+
+        assert!(black.play("e7", "e5").await);
+    }
+
+    #[tokio::test]
+    async fn checkmate_ends_game() {
+        let (mut white, mut black) = start_game(GameConfig::default());
+
+        assert!(white.play("f2", "f3").await);
+        assert!(black.play("e7", "e5").await);
+        assert!(!white.play("g2", "g5").await);
+        assert!(white.play("g2", "g4").await);
+        assert!(black.play("d8", "h4").await);
+
+        assert_eq!(white.wait_for_end().await, (Winner::Black, EndState::Checkmate));
+        assert_eq!(black.wait_for_end().await, (Winner::Black, EndState::Checkmate));
+    }
+
+    #[tokio::test]
+    async fn moves_can_be_sent_as_notation() {
+        let (mut white, mut black) = start_game(GameConfig::default());
+        let notation = |text: &str| Turn::Notation(text.to_string());
+
+        assert!(white.send_turn(notation("f2f3")).await);
+        assert!(black.send_turn(notation("e5")).await);
+        assert!(!white.send_turn(notation("g5")).await);
+        assert!(white.send_turn(notation("g4")).await);
+        assert!(black.send_turn(notation("Qh4#")).await);
+
+        assert_eq!(white.wait_for_end().await, (Winner::Black, EndState::Checkmate));
+    }
+
+    #[tokio::test]
+    async fn moves_are_accepted_after_repeated_nudges() {
+        let (white_user, black_user) = (UserInfo::new_guest(), UserInfo::new_guest());
+        let (white, white_moves, white_events) = PlayerInterface::create(white_user.clone());
+        let (black, black_moves, black_events) = PlayerInterface::create(black_user.clone());
+        let (actions, action_rx) = ActionInterface::create();
+
+        let config = GameConfig {
+            player1_color: TeamConfig::White,
+            ..GameConfig::default()
+        };
+        let game = InactiveGame::new("test".to_string(), white, actions, config, None).unwrap();
+        let white = GameInterface::new(
+            white_moves,
+            action_rx.clone(),
+            white_events,
+            "test".to_string(),
+            white_user,
+            &game.messenger,
+            game.spectators().count(),
+        );
+        game.start(black);
+
+        let black = TestPlayer {
+            user: black_user,
+            moves: black_moves,
+            events: black_events,
+            actions: action_rx,
+        };
+        // Each nudge replaces white's move request, until the game has more requests than the channel holds
+        let settle = || tokio::time::sleep(Duration::from_millis(20));
+        settle().await;
+        for _ in 0..2 {
+            black.act(ActionType::Nudge).await;
+            settle().await;
+        }
+
+        assert!(white.send_move(Turn::Notation("e4".to_string())).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn bots_play_their_turns() {
+        let white_user = UserInfo::new_guest();
+        let (white, white_moves, white_events) = PlayerInterface::create(white_user.clone());
+        let (actions, action_rx) = ActionInterface::create();
+
+        let config = GameConfig {
+            player1_color: TeamConfig::White,
+            starting_fen: "r5k1/5ppp/8/8/8/8/1P3PPP/6K1 w - - 0 1".to_string(),
+            ..GameConfig::default()
+        };
+        let game = InactiveGame::new("test".to_string(), white, actions, config, None).unwrap();
+        let bot = Bot::create(Strength::Advanced, game.board.clone(), action_rx.clone());
+        game.start(bot);
+
+        let mut white = TestPlayer {
+            user: white_user,
+            moves: white_moves,
+            events: white_events,
+            actions: action_rx,
+        };
+        assert!(white.play("b2", "b3").await);
+        assert_eq!(white.wait_for_end().await, (Winner::Black, EndState::Checkmate));
+    }
+
+    #[tokio::test]
+    async fn external_engines_play_their_turns() {
+        let black_user = UserInfo::new_guest();
+        let (black, black_moves, black_events) = PlayerInterface::create(black_user.clone());
+        let (actions, action_rx) = ActionInterface::create();
+
+        let config = GameConfig {
+            player1_color: TeamConfig::Black,
+            starting_fen: "6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1".to_string(),
+            ..GameConfig::default()
+        };
+        let time = config.time;
+        let game = InactiveGame::new("test".to_string(), black, actions, config, None).unwrap();
+        let bot_config = BotConfig {
+            name: "Scripted".to_string(),
+            engine: BotEngine::Uci(scripted_engine("a1a8")),
+        };
+        let bot = Bot::from_config(&bot_config, game.board.clone(), &time, action_rx.clone())
+            .await
+            .unwrap();
+        game.start(bot);
+
+        let mut black = TestPlayer {
+            user: black_user,
+            moves: black_moves,
+            events: black_events,
+            actions: action_rx,
+        };
+        assert_eq!(black.wait_for_end().await, (Winner::White, EndState::Checkmate));
+    }
+
+    #[tokio::test]
+    async fn stalled_engines_resign() {
+        let black_user = UserInfo::new_guest();
+        let (black, black_moves, black_events) = PlayerInterface::create(black_user.clone());
+        let (actions, action_rx) = ActionInterface::create();
+
+        let config = GameConfig {
+            player1_color: TeamConfig::Black,
+            ..GameConfig::default()
+        };
+        let time = config.time;
+        let game = InactiveGame::new("test".to_string(), black, actions, config, None).unwrap();
+        let bot_config = BotConfig {
+            name: "Silent".to_string(),
+            engine: BotEngine::Uci(silent_engine()),
+        };
+        let bot = Bot::from_config(&bot_config, game.board.clone(), &time, action_rx.clone())
+            .await
+            .unwrap();
+        game.start(bot);
+
+        let mut black = TestPlayer {
+            user: black_user,
+            moves: black_moves,
+            events: black_events,
+            actions: action_rx,
+        };
+        assert_eq!(black.wait_for_end().await, (Winner::Black, EndState::Resignation));
+    }
+
+    #[tokio::test]
+    async fn unreachable_players_abandon() {
+        let (white, mut black) = start_game(GameConfig::default());
+        drop(white);
+
+        assert_eq!(black.wait_for_end().await, (Winner::Black, EndState::Abandoned));
+    }
+
+    #[test]
+    fn validates_game_configs() {
+        let config = |fen: &str, width, height| GameConfig {
+            starting_fen: fen.to_string(),
+            width,
+            height,
+            ..GameConfig::default()
+        };
+
+        assert!(GameConfig::default().validate().is_ok());
+        assert!(config("k7/8/8/8/8/8/8/K7 w - - 0 1", 8, 8).validate().is_ok());
+        assert!(config("k3/4/4/K3 w - - 0 1", 4, 4).validate().is_ok());
+
+        assert!(matches!(
+            config("k2/3/K2 w - - 0 1", 3, 3).validate(),
+            Err(ConfigError::InvalidSize(3))
+        ));
+        assert!(matches!(
+            config(STARTING_FEN, 8, 10).validate(),
+            Err(ConfigError::InvalidFen(_))
+        ));
+        assert!(matches!(
+            config("8/8/8/8/8/8/8/K7 w - - 0 1", 8, 8).validate(),
+            Err(ConfigError::KingCount(TeamColor::Black))
+        ));
+        assert!(matches!(
+            config("k6R/8/8/8/8/8/8/K7 w - - 0 1", 8, 8).validate(),
+            Err(ConfigError::WaitingSideInCheck)
+        ));
+        assert!(matches!(
+            config("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1", 8, 8).validate(),
+            Err(ConfigError::NoLegalMoves)
+        ));
+        assert!(matches!(
+            GameConfig::with_time(TimeConfig::Timed {
+                limit: Duration::ZERO,
+                added: Duration::ZERO,
+            })
+            .validate(),
+            Err(ConfigError::InvalidTime)
+        ));
+        assert!(matches!(
+            GameConfig {
+                spectator_delay: SpectatorDelay::Time(Duration::from_secs(3600)),
+                ..GameConfig::default()
+            }
+            .validate(),
+            Err(ConfigError::InvalidSpectatorDelay)
+        ));
+    }
+
+    #[tokio::test]
+    async fn stalemate_ends_game() {
+        let (mut white, mut black) = start_game(GameConfig {
+            starting_fen: "7k/8/6Q1/8/8/8/8/K7 w - - 0 1".to_string(),
+            ..GameConfig::default()
+        });
+
+        assert!(white.play("g6", "f7").await);
+
+        assert_eq!(white.wait_for_end().await, (Winner::None, EndState::Stalemate));
+        assert_eq!(black.wait_for_end().await, (Winner::None, EndState::Stalemate));
+    }
+
+    #[tokio::test]
+    async fn insufficient_material_ends_game() {
+        let (mut white, mut black) = start_game(GameConfig {
+            starting_fen: "4k3/8/8/8/8/8/3p4/4K3 w - - 0 1".to_string(),
+            ..GameConfig::default()
+        });
+
+        assert!(white.play("e1", "d2").await);
+
+        assert_eq!(
+            black.wait_for_end().await,
+            (Winner::None, EndState::InsufficientMaterial)
+        );
+    }
+
+    #[tokio::test]
+    async fn threefold_repetition_can_be_claimed() {
+        let (mut white, mut black) = start_game(GameConfig::default());
+
+        white.act(ActionType::ClaimDraw).await;
+        white.wait_for(|e| matches!(e, Event::DrawClaimRejected)).await;
+
+        for _ in 0..2 {
+            assert!(white.play("g1", "f3").await);
+            assert!(black.play("g8", "f6").await);
+            assert!(white.play("f3", "g1").await);
+            assert!(black.play("f6", "g8").await);
+        }
+
+        // Only the player to move may claim
+        black.act(ActionType::ClaimDraw).await;
+        black.wait_for(|e| matches!(e, Event::DrawClaimRejected)).await;
+
+        white.wait_for(|e| matches!(e, Event::CanClaimDraw)).await;
+        white.act(ActionType::ClaimDraw).await;
+        assert_eq!(white.wait_for_end().await, (Winner::None, EndState::RepeatThree));
+    }
+}
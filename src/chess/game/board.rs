@@ -1,99 +1,816 @@
-use std::collections::BTreeMap;
+use serde::Serialize;
 
-use super::pieces::{self, Move, Position, ValidMove};
+use super::pieces::{self, Move, Position, TeamColor, Turn, ValidMove};
 
+/// ### A position, with the rules of chess for moving between positions
+///
+/// Tiles are stored rank by rank from the top of the board. Standard 8x8 boards also keep
+/// bitboards of their pieces, which attack and move generation use instead of walking tiles
+#[derive(Clone)]
 pub struct Board {
-    pub tiles: BTreeMap<Position, tile::Tile>,
+    tiles: Vec<tile::Tile>,
+    bitboards: Option<bitboard::Bitboards>,
     pub height: u8,
     pub width: u8,
-    pub black_can_castle: bool,
-    pub white_can_castle: bool,
+    pub castling: CastlingRights,
+    pub turn: TeamColor,
+    pub en_passant: Option<Position>,
+    pub halfmove_clock: u16,
+    pub fullmove_number: u16,
 }
 
 impl Board {
-    pub fn make_move(&mut self, valid_move: pieces::ValidMove) {}
+    /// ### Applies a `ValidMove` to the board
+    ///
+    /// Moves the piece, removes anything it captured, moves the rook when castling,
+    /// swaps in the promoted piece, and hands the turn to the other side
+    pub fn make_move(&mut self, valid_move: &ValidMove) {
+        let mut piece = match self.replace_tile(&valid_move.source, tile::Tile::Empty) {
+            tile::Tile::Piece { piece } => piece,
+            _ => return,
+        };
 
-    pub fn validate_move(&self, try_move: Move) -> Option<ValidMove> {
-        Some(try_move.into())
-    }
-
-    pub fn new(fen: String, height: u8, width: u8) -> Self {
-        // create starting formation here (10x12 board)
-        let mut tiles = BTreeMap::new();
-        let mut x = 1;
-        let mut y = 1;
-
-        for char in fen.chars() {
-            match char.to_digit(10) {
-                Some(num) => {
-                    for i in 0..num {
-                        tiles.insert(Position { x, y }, tile::Tile::Empty);
-                        x += 1
-                    }
+        match &valid_move.kind {
+            pieces::MoveKind::EnPassant { captured } => {
+                self.replace_tile(captured, tile::Tile::Empty);
+            }
+            pieces::MoveKind::Castle {
+                rook_source,
+                rook_target,
+            } => {
+                if let tile::Tile::Piece { mut piece } = self.replace_tile(rook_source, tile::Tile::Empty) {
+                    piece.set_position(rook_target.clone());
+                    self.replace_tile(rook_target, tile::Tile::Piece { piece });
                 }
-                None => {
-                    if char == '/' {
-                        y += 1;
-                        x = 0;
-                    } else {
-                        let pos = Position { x, y };
-                        tiles.insert(
-                            pos.clone(),
-                            tile::Tile::Piece {
-                                piece: Board::char_to_piece(char, pos),
-                            },
-                        );
-                        x += 1;
-                    }
+            }
+            _ => (),
+        }
+
+        if piece.is_king() {
+            self.castling.revoke_all(piece.get_color());
+        }
+        // A rook leaving or being captured in its corner loses that side's castling
+        for pos in [&valid_move.source, &valid_move.target] {
+            self.revoke_corner(pos);
+        }
+
+        if piece.is_pawn() || valid_move.captured.is_some() {
+            self.halfmove_clock = 0;
+        } else {
+            self.halfmove_clock += 1;
+        }
+        if piece.get_color() == &TeamColor::Black {
+            self.fullmove_number += 1;
+        }
+
+        self.en_passant = match valid_move.kind {
+            pieces::MoveKind::DoublePush => Some(Position {
+                x: valid_move.source.x,
+                y: (valid_move.source.y + valid_move.target.y) / 2,
+            }),
+            _ => None,
+        };
+
+        if let Some(kind) = valid_move.promotion {
+            piece.set_kind(kind);
+        }
+        piece.set_position(valid_move.target.clone());
+        self.replace_tile(&valid_move.target, tile::Tile::Piece { piece });
+
+        self.turn = self.turn.opposite();
+    }
+
+    /// ### Reverts a `ValidMove` previously applied with `make_move`
+    ///
+    /// Moves must be undone in the reverse order they were made
+    pub fn unmake_move(&mut self, valid_move: &ValidMove) {
+        let mut piece = match self.replace_tile(&valid_move.target, tile::Tile::Empty) {
+            tile::Tile::Piece { piece } => piece,
+            _ => return,
+        };
+
+        if valid_move.promotion.is_some() {
+            piece.set_kind(pieces::PieceType::Pawn);
+        }
+        piece.set_position(valid_move.source.clone());
+        self.replace_tile(&valid_move.source, tile::Tile::Piece { piece });
+
+        match &valid_move.kind {
+            pieces::MoveKind::Castle {
+                rook_source,
+                rook_target,
+            } => {
+                if let tile::Tile::Piece { mut piece } = self.replace_tile(rook_target, tile::Tile::Empty) {
+                    piece.set_position(rook_source.clone());
+                    self.replace_tile(rook_source, tile::Tile::Piece { piece });
+                }
+            }
+            pieces::MoveKind::EnPassant { captured } => {
+                if let Some(piece) = &valid_move.captured {
+                    self.replace_tile(captured, tile::Tile::Piece { piece: piece.clone() });
+                }
+            }
+            _ => {
+                if let Some(piece) = &valid_move.captured {
+                    self.replace_tile(&valid_move.target, tile::Tile::Piece { piece: piece.clone() });
                 }
             }
         }
 
-        Self {
-            tiles,
-            height,
-            width,
-            black_can_castle: true,
-            white_can_castle: true,
+        self.restore_state(&valid_move.previous);
+        self.turn = self.turn.opposite();
+        if self.turn == TeamColor::Black {
+            self.fullmove_number -= 1;
         }
     }
 
-    pub fn get_tile(&self, pos: &Position) -> &tile::Tile {
-        if pos.x >= self.width as u16 || pos.y >= self.height as u16 {
-            &tile::Tile::Wall
-        } else {
-            &self.tiles[pos]
+    /// Snapshot of the state a move cannot be undone without
+    pub fn state(&self) -> BoardState {
+        BoardState {
+            en_passant: self.en_passant.clone(),
+            castling: self.castling,
+            halfmove_clock: self.halfmove_clock,
         }
     }
 
-    pub fn char_to_piece(char: char, pos: Position) -> pieces::Piece {
-        let color = if char.is_uppercase() {
-            pieces::TeamColor::White
+    fn restore_state(&mut self, state: &BoardState) {
+        self.en_passant = state.en_passant.clone();
+        self.castling = state.castling;
+        self.halfmove_clock = state.halfmove_clock;
+    }
+
+    fn revoke_corner(&mut self, pos: &Position) {
+        let color = if pos.y == self.back_rank(&TeamColor::White) {
+            TeamColor::White
+        } else if pos.y == self.back_rank(&TeamColor::Black) {
+            TeamColor::Black
         } else {
-            pieces::TeamColor::Black
+            return;
         };
 
-        pieces::Piece::new(
-            color,
-            pos,
-            match char.to_ascii_lowercase() {
-                'k' => pieces::PieceType::King,
-                'q' => pieces::PieceType::Queen,
-                'p' => pieces::PieceType::Pawn,
-                'b' => pieces::PieceType::Bishop,
-                'n' => pieces::PieceType::Knight,
-                'r' => pieces::PieceType::Rook,
-                _ => panic!("Should never be this"),
-            },
-        )
+        if pos.x == 0 {
+            self.castling.revoke(&color, false);
+        } else if pos.x == self.width as u16 - 1 {
+            self.castling.revoke(&color, true);
+        }
+    }
+
+    /// ### Checks a player's turn, in whichever notation they sent it
+    pub fn validate_turn(&self, turn: Turn) -> Option<ValidMove> {
+        match turn {
+            Turn::Coordinates(try_move) => self.validate_move(try_move),
+            Turn::Notation(notation) => self.parse_move(&notation),
+        }
+    }
+
+    /// ### Checks a move against the rules of chess
+    ///
+    /// Returns `Some(ValidMove)` if the piece on `source` belongs to the side to move,
+    /// is allowed to reach `target`, and does not leave its own king in check
+    ///
+    /// Returns `None` otherwise
+    pub fn validate_move(&self, try_move: Move) -> Option<ValidMove> {
+        let piece = match self.get_tile(&try_move.source) {
+            tile::Tile::Piece { piece } if piece.get_color() == &self.turn => piece,
+            _ => return None,
+        };
+
+        let mut moves = self.piece_moves(piece);
+        if piece.is_king() {
+            moves.append(&mut self.castling_moves(piece));
+        }
+
+        // Pawns that reach the last rank promote to a queen unless told otherwise
+        let promotion = try_move.promotion.or(Some(pieces::PieceType::Queen));
+
+        moves
+            .into_iter()
+            .filter(|vm| vm.promotion.is_none() || vm.promotion == promotion)
+            .find(|vm| vm.target == try_move.target)
+            .filter(|vm| !self.leaves_king_in_check(vm))
+    }
+
+    /// ### Gets every legal move for the side to move
+    pub fn legal_moves(&self) -> Vec<ValidMove> {
+        let mut moves = Vec::new();
+
+        for piece in self.pieces(&self.turn) {
+            moves.append(&mut self.piece_moves(piece));
+            if piece.is_king() {
+                moves.append(&mut self.castling_moves(piece));
+            }
+        }
+
+        let mut scratch = self.clone();
+        moves.retain(|vm| !scratch.exposes_king(vm));
+        moves
+    }
+
+    /// ### Gets every pseudo-legal move for a piece, not counting castling
+    ///
+    /// Pieces other than pawns move along their bitboard attacks on 8x8 boards
+    fn piece_moves(&self, piece: &pieces::Piece) -> Vec<ValidMove> {
+        let bitboards = match &self.bitboards {
+            Some(bitboards) if !piece.is_pawn() => bitboards,
+            _ => return piece.get_piece_moves(self),
+        };
+
+        let color = piece.get_color();
+        let source = bitboard::square(piece.get_position());
+        let targets = bitboards.attacks(source, color, *piece.get_kind()) & !bitboards.color(color);
+
+        bitboard::squares(targets)
+            .map(|target| ValidMove::new(self, piece.get_position().clone(), bitboard::position(target)))
+            .collect()
+    }
+
+    /// ### Checks if the king of the given color is currently attacked
+    pub fn is_in_check(&self, color: &TeamColor) -> bool {
+        match self.find_king(color) {
+            Some(pos) => self.is_attacked(&pos, &color.opposite()),
+            None => false,
+        }
+    }
+
+    /// ### Checks if any piece of the color `by` attacks the given position
+    pub fn is_attacked(&self, pos: &Position, by: &TeamColor) -> bool {
+        match &self.bitboards {
+            Some(bitboards) => bitboards.is_attacked(bitboard::square(pos), by),
+            None => self.pieces(by).any(|piece| piece.attacks(self, pos)),
+        }
+    }
+
+    /// ### Checks if neither side has enough material left to ever checkmate
+    ///
+    /// This is the case with bare kings, a single minor piece, or only bishops
+    /// that all stand on the same colored tiles
+    pub fn insufficient_material(&self) -> bool {
+        let mut minors = Vec::new();
+
+        for tile in self.tiles.iter() {
+            if let tile::Tile::Piece { piece } = tile {
+                match piece.get_kind() {
+                    pieces::PieceType::King => (),
+                    pieces::PieceType::Bishop | pieces::PieceType::Knight => minors.push(piece),
+                    _ => return false,
+                }
+            }
+        }
+
+        let shade = |piece: &pieces::Piece| (piece.get_position().x + piece.get_position().y) % 2;
+
+        match minors.as_slice() {
+            [] | [_] => true,
+            [first, rest @ ..] => {
+                first.is_bishop()
+                    && rest
+                        .iter()
+                        .all(|piece| piece.is_bishop() && shade(piece) == shade(first))
+            }
+        }
+    }
+
+    /// ### Checks if the given side could never checkmate, whatever its opponent does
+    ///
+    /// Used to decide if running out of time loses, or only draws
+    pub fn cannot_checkmate(&self, color: &TeamColor) -> bool {
+        let own: Vec<&pieces::Piece> = self.pieces(color).filter(|piece| !piece.is_king()).collect();
+        let enemy = color.opposite();
+
+        if own
+            .iter()
+            .any(|piece| piece.is_pawn() || piece.is_rook() || piece.is_queen())
+        {
+            return false;
+        }
+
+        if own.iter().any(|piece| piece.is_knight()) {
+            // A lone knight can only mate a king boxed in by its own pieces, which a queen cannot do
+            return own.len() == 1 && self.pieces(&enemy).all(|piece| piece.is_king() || piece.is_queen());
+        }
+
+        if own.iter().any(|piece| piece.is_bishop()) {
+            let all_pieces = || self.pieces(color).chain(self.pieces(&enemy));
+            let shade = |piece: &&pieces::Piece| (piece.get_position().x + piece.get_position().y) % 2;
+            let mut bishops = all_pieces()
+                .filter(|piece| piece.is_bishop())
+                .map(|piece| shade(&piece));
+            let first = bishops.next();
+            let same_shade = bishops.all(|s| Some(s) == first);
+
+            return same_shade && !all_pieces().any(|piece| piece.is_pawn() || piece.is_knight());
+        }
+
+        true
+    }
+
+    pub fn pieces<'a>(&'a self, color: &'a TeamColor) -> impl Iterator<Item = &'a pieces::Piece> + 'a {
+        self.tiles.iter().filter_map(move |tile| match tile {
+            tile::Tile::Piece { piece } if piece.get_color() == color => Some(piece),
+            _ => None,
+        })
+    }
+
+    pub fn find_king(&self, color: &TeamColor) -> Option<Position> {
+        if let Some(bitboards) = &self.bitboards {
+            return bitboard::squares(bitboards.pieces(color, pieces::PieceType::King))
+                .next()
+                .map(bitboard::position);
+        }
+
+        self.pieces(color)
+            .find(|piece| piece.is_king())
+            .map(|piece| piece.get_position().clone())
+    }
+
+    /// The rank each side's pawns start on, and are allowed to double push from
+    pub fn pawn_start_rank(&self, color: &TeamColor) -> u16 {
+        match color {
+            TeamColor::White => self.height as u16 - 2,
+            TeamColor::Black => 1,
+        }
+    }
+
+    /// The rank each side's pawns promote on
+    pub fn promotion_rank(&self, color: &TeamColor) -> u16 {
+        self.back_rank(&color.opposite())
+    }
+
+    fn back_rank(&self, color: &TeamColor) -> u16 {
+        match color {
+            TeamColor::White => self.height as u16 - 1,
+            TeamColor::Black => 0,
+        }
+    }
+
+    /// ### Gets castling moves for the given king
+    ///
+    /// The king moves two tiles towards a rook in the corner of its back rank,
+    /// and that rook moves to the tile the king passed over. Every tile between
+    /// them must be empty, and the king may not castle out of, through, or into check.
+    fn castling_moves(&self, king: &pieces::Piece) -> Vec<ValidMove> {
+        let color = king.get_color();
+        let king_pos = king.get_position();
+        let rank = self.back_rank(color);
+        if king_pos.y != rank || king_pos.x < 2 || king_pos.x + 2 >= self.width as u16 {
+            return vec![];
+        }
+
+        let enemy = color.opposite();
+        if self.is_attacked(king_pos, &enemy) {
+            return vec![];
+        }
+
+        let mut moves = Vec::new();
+        for (rook_x, kingside) in [(0, false), (self.width as u16 - 1, true)] {
+            if !self.castling.allows(color, kingside) {
+                continue;
+            }
+
+            let rook_pos = Position { x: rook_x, y: rank };
+            match self.get_tile(&rook_pos) {
+                tile::Tile::Piece { piece } if piece.is_rook() && piece.get_color() == color => (),
+                _ => continue,
+            }
+
+            let (low, high) = (rook_x.min(king_pos.x) + 1, rook_x.max(king_pos.x));
+            let path_clear = (low..high).all(|x| matches!(self.get_tile(&Position { x, y: rank }), tile::Tile::Empty));
+            if !path_clear {
+                continue;
+            }
+
+            let (king_x, rook_target_x) = if rook_x > king_pos.x {
+                (king_pos.x + 2, king_pos.x + 1)
+            } else {
+                (king_pos.x - 2, king_pos.x - 1)
+            };
+
+            let passes_check = [rook_target_x, king_x]
+                .into_iter()
+                .any(|x| self.is_attacked(&Position { x, y: rank }, &enemy));
+            if passes_check {
+                continue;
+            }
+
+            moves.push(ValidMove::castle(
+                self,
+                king_pos.clone(),
+                Position { x: king_x, y: rank },
+                rook_pos,
+                Position {
+                    x: rook_target_x,
+                    y: rank,
+                },
+            ));
+        }
+
+        moves
+    }
+
+    /// ### Checks if playing this move would leave the mover's king attacked
+    fn leaves_king_in_check(&self, valid_move: &ValidMove) -> bool {
+        self.clone().exposes_king(valid_move)
+    }
+
+    /// Plays a move to see if it leaves the mover's king attacked, then takes it back
+    fn exposes_king(&mut self, valid_move: &ValidMove) -> bool {
+        let color = self.turn;
+        self.make_move(valid_move);
+        let in_check = self.is_in_check(&color);
+        self.unmake_move(valid_move);
+        in_check
+    }
+
+    fn index(&self, pos: &Position) -> Option<usize> {
+        match pos.x < self.width as u16 && pos.y < self.height as u16 {
+            true => Some(pos.y as usize * self.width as usize + pos.x as usize),
+            false => None,
+        }
+    }
+
+    pub fn get_tile(&self, pos: &Position) -> &tile::Tile {
+        match self.index(pos) {
+            Some(index) => &self.tiles[index],
+            None => &tile::Tile::Wall,
+        }
+    }
+
+    /// Puts a tile on the board, returning the one it replaced, and keeps the bitboards in step
+    fn replace_tile(&mut self, pos: &Position, tile: tile::Tile) -> tile::Tile {
+        let index = match self.index(pos) {
+            Some(index) => index,
+            None => return tile::Tile::Wall,
+        };
+        let old = std::mem::replace(&mut self.tiles[index], tile);
+
+        if let Some(bitboards) = &mut self.bitboards {
+            for tile in [&old, &self.tiles[index]] {
+                if let tile::Tile::Piece { piece } = tile {
+                    bitboards.toggle(index, piece);
+                }
+            }
+        }
+
+        old
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BoardState {
+    en_passant: Option<Position>,
+    castling: CastlingRights,
+    halfmove_clock: u16,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct CastlingRights {
+    pub white_kingside: bool,
+    pub white_queenside: bool,
+    pub black_kingside: bool,
+    pub black_queenside: bool,
+}
+
+impl CastlingRights {
+    pub fn allows(&self, color: &TeamColor, kingside: bool) -> bool {
+        match (color, kingside) {
+            (TeamColor::White, true) => self.white_kingside,
+            (TeamColor::White, false) => self.white_queenside,
+            (TeamColor::Black, true) => self.black_kingside,
+            (TeamColor::Black, false) => self.black_queenside,
+        }
+    }
+
+    pub fn revoke(&mut self, color: &TeamColor, kingside: bool) {
+        match (color, kingside) {
+            (TeamColor::White, true) => self.white_kingside = false,
+            (TeamColor::White, false) => self.white_queenside = false,
+            (TeamColor::Black, true) => self.black_kingside = false,
+            (TeamColor::Black, false) => self.black_queenside = false,
+        }
+    }
+
+    pub fn revoke_all(&mut self, color: &TeamColor) {
+        self.revoke(color, true);
+        self.revoke(color, false);
     }
 }
 
 impl Default for Board {
     fn default() -> Self {
-        let fen = String::from("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR"); // slightly modified fen string used to have dynamic starting positions for custom boards
-        Board::new(fen, 8, 8)
+        Board::new(STARTING_FEN, 8, 8).expect("Standard starting position should always be valid")
     }
 }
 
-mod tile;
+pub const STARTING_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+pub mod bitboard;
+pub mod fen;
+pub mod perft;
+pub mod san;
+pub mod tile;
+pub mod uci;
+pub mod zobrist;
+
+pub use fen::FenError;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mv(source: (u16, u16), target: (u16, u16)) -> Move {
+        Move {
+            source: Position {
+                x: source.0,
+                y: source.1,
+            },
+            target: Position {
+                x: target.0,
+                y: target.1,
+            },
+            promotion: None,
+        }
+    }
+
+    #[test]
+    fn start_position_has_twenty_moves() {
+        let board = Board::default();
+        assert_eq!(board.legal_moves().len(), 20);
+    }
+
+    #[test]
+    fn rejects_illegal_piece_movement() {
+        let board = Board::default();
+
+        // Rook cannot move diagonally, or through its own pawn
+        assert!(board.validate_move(mv((0, 7), (1, 6))).is_none());
+        assert!(board.validate_move(mv((0, 7), (0, 5))).is_none());
+
+        // Black cannot move on white's turn
+        assert!(board.validate_move(mv((4, 1), (4, 3))).is_none());
+
+        // Knight jumps, and pawns may double push from the start
+        assert!(board.validate_move(mv((6, 7), (5, 5))).is_some());
+        assert!(board.validate_move(mv((4, 6), (4, 4))).is_some());
+    }
+
+    #[test]
+    fn rejects_moves_leaving_king_in_check() {
+        // White king on e1 pinned by a rook on e8 through a bishop on e2
+        let board = Board::new("4r2k/8/8/8/8/8/4B3/4K3 w - - 0 1", 8, 8).unwrap();
+
+        assert!(board.validate_move(mv((4, 6), (3, 5))).is_none());
+        assert!(board.validate_move(mv((4, 7), (3, 7))).is_some());
+    }
+
+    #[test]
+    fn castling_requires_safe_path() {
+        let mut board = Board::new("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", 8, 8).unwrap();
+        assert!(board.validate_move(mv((4, 7), (6, 7))).is_some());
+        assert!(board.validate_move(mv((4, 7), (2, 7))).is_some());
+
+        // A black rook on f8 covers f1, so white may not castle kingside
+        board = Board::new("r3kr2/8/8/8/8/8/8/R3K2R w KQq - 0 1", 8, 8).unwrap();
+        assert!(board.validate_move(mv((4, 7), (6, 7))).is_none());
+        assert!(board.validate_move(mv((4, 7), (2, 7))).is_some());
+    }
+
+    #[test]
+    fn make_and_unmake_special_moves() {
+        // White can capture en passant on d6 and promote on b8
+        let mut board = Board::new("1r2k3/P7/8/3pP3/8/8/8/4K2R w K d6 0 2", 8, 8).unwrap();
+        let original = board.clone();
+
+        let en_passant = board.validate_move(mv((4, 3), (3, 2))).unwrap();
+        assert!(en_passant.captured.as_ref().is_some_and(|p| p.is_pawn()));
+        board.make_move(&en_passant);
+        assert!(matches!(board.get_tile(&Position { x: 3, y: 3 }), tile::Tile::Empty));
+        board.unmake_move(&en_passant);
+
+        let promotion = board.validate_move(mv((0, 1), (1, 0))).unwrap();
+        board.make_move(&promotion);
+        match board.get_tile(&Position { x: 1, y: 0 }) {
+            tile::Tile::Piece { piece } => assert!(piece.is_queen()),
+            _ => panic!("Promoted piece should be on b8"),
+        }
+        board.unmake_move(&promotion);
+
+        let castle = board.validate_move(mv((4, 7), (6, 7))).unwrap();
+        board.make_move(&castle);
+        match board.get_tile(&Position { x: 5, y: 7 }) {
+            tile::Tile::Piece { piece } => assert!(piece.is_rook()),
+            _ => panic!("Rook should be on f1 after castling"),
+        }
+        assert!(!board.castling.white_kingside);
+        board.unmake_move(&castle);
+
+        assert!(board.tiles.iter().zip(original.tiles.iter()).all(|tiles| match tiles {
+            (tile::Tile::Piece { piece }, tile::Tile::Piece { piece: other }) => piece == other,
+            (tile::Tile::Empty, tile::Tile::Empty) => true,
+            _ => false,
+        }));
+        assert_eq!(board.bitboards, original.bitboards);
+        assert_eq!(board.state(), original.state());
+    }
+
+    #[test]
+    fn fen_round_trips() {
+        let fens = [
+            STARTING_FEN,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "rnbqkbnr/pp1ppppp/8/2p5/4P3/8/PPPP1PPP/RNBQKBNR w KQkq c6 0 2",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 b - - 13 37",
+        ];
+
+        for fen in fens {
+            assert_eq!(Board::new(fen, 8, 8).unwrap().to_fen(), fen);
+        }
+
+        let mut board = Board::default();
+        let push = board.validate_move(mv((4, 6), (4, 4))).unwrap();
+        board.make_move(&push);
+        assert_eq!(
+            board.to_fen(),
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1"
+        );
+    }
+
+    #[test]
+    fn fen_reports_errors() {
+        assert_eq!(Board::new("", 8, 8).err(), Some(FenError::Empty));
+        assert_eq!(
+            Board::new("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP", 8, 8).err(),
+            Some(FenError::WrongHeight { expect: 8, found: 7 })
+        );
+        assert_eq!(
+            Board::new("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBN", 8, 8).err(),
+            Some(FenError::WrongWidth {
+                rank: 1,
+                expect: 8,
+                found: 7
+            })
+        );
+        assert_eq!(
+            Board::new("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNX", 8, 8).err(),
+            Some(FenError::InvalidPiece('X'))
+        );
+        assert_eq!(
+            Board::new("8/8/8/8/8/8/8/8 x - - 0 1", 8, 8).err(),
+            Some(FenError::InvalidTurn("x".to_string()))
+        );
+        assert_eq!(
+            Board::new("8/8/8/8/8/8/8/99999999999", 8, 8).err(),
+            Some(FenError::WrongWidth {
+                rank: 1,
+                expect: 8,
+                found: u16::MAX
+            })
+        );
+        assert_eq!(
+            Board::new("8/8/8/8/8/8/8/4000000000", 8, 8).err(),
+            Some(FenError::WrongWidth {
+                rank: 1,
+                expect: 8,
+                found: u16::MAX
+            })
+        );
+        assert_eq!(
+            Board::new("8/8/8/8/8/8/8/K9", 8, 8).err(),
+            Some(FenError::WrongWidth {
+                rank: 1,
+                expect: 8,
+                found: 10
+            })
+        );
+    }
+
+    #[test]
+    fn detects_insufficient_material() {
+        let insufficient = [
+            "4k3/8/8/8/8/8/8/4K3",
+            "4k3/8/8/8/8/8/8/2B1K3",
+            "4k3/8/8/8/8/8/8/1N2K3",
+            "2b1k3/8/8/8/8/8/8/3BK3",
+        ];
+        let sufficient = [
+            "4k3/8/8/8/8/8/8/3PK3",
+            "2b1k3/8/8/8/8/8/8/2B1K3",
+            "1n2k3/8/8/8/8/8/8/1N2K3",
+            "4k3/8/8/8/8/8/8/R3K3",
+        ];
+
+        for fen in insufficient {
+            assert!(Board::new(fen, 8, 8).unwrap().insufficient_material(), "{fen}");
+        }
+        for fen in sufficient {
+            assert!(!Board::new(fen, 8, 8).unwrap().insufficient_material(), "{fen}");
+        }
+    }
+
+    #[test]
+    fn zobrist_hash_follows_position() {
+        let mut board = Board::default();
+        let start = board.zobrist_hash();
+
+        // Shuffling both knights out and back repeats the starting position
+        for (source, target) in [((6, 7), (5, 5)), ((6, 0), (5, 2)), ((5, 5), (6, 7)), ((5, 2), (6, 0))] {
+            let valid_move = board.validate_move(mv(source, target)).unwrap();
+            board.make_move(&valid_move);
+        }
+        assert_eq!(board.zobrist_hash(), start);
+
+        // The en passant square only matters if it can be captured
+        let quiet = Board::new("4k3/8/8/8/4P3/8/8/4K3 b - e3 0 1", 8, 8).unwrap();
+        let capturable = Board::new("4k3/8/8/8/3pP3/8/8/4K3 b - e3 0 1", 8, 8).unwrap();
+        assert_eq!(
+            quiet.zobrist_hash(),
+            Board::new("4k3/8/8/8/4P3/8/8/4K3 b - - 0 1", 8, 8)
+                .unwrap()
+                .zobrist_hash()
+        );
+        assert_ne!(
+            capturable.zobrist_hash(),
+            Board::new("4k3/8/8/8/3pP3/8/8/4K3 b - - 0 1", 8, 8)
+                .unwrap()
+                .zobrist_hash()
+        );
+
+        // Castling rights change the hash
+        let castle = Board::new("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", 8, 8).unwrap();
+        let no_castle = Board::new("r3k2r/8/8/8/8/8/8/R3K2R w - - 0 1", 8, 8).unwrap();
+        assert_ne!(castle.zobrist_hash(), no_castle.zobrist_hash());
+    }
+
+    #[test]
+    fn detects_sides_that_cannot_checkmate() {
+        let board = Board::new("4k3/8/8/8/8/8/7P/4K3 w - - 0 1", 8, 8).unwrap();
+        assert!(board.cannot_checkmate(&TeamColor::Black));
+        assert!(!board.cannot_checkmate(&TeamColor::White));
+
+        // A knight can mate a king that is hemmed in by its own pawn
+        let board = Board::new("4k3/8/8/8/8/8/7P/4K1n1 w - - 0 1", 8, 8).unwrap();
+        assert!(!board.cannot_checkmate(&TeamColor::Black));
+        let board = Board::new("4k3/8/8/8/8/8/7Q/4K1n1 w - - 0 1", 8, 8).unwrap();
+        assert!(board.cannot_checkmate(&TeamColor::Black));
+
+        // Bishops on opposite shades can mate, but not when they share a shade
+        let board = Board::new("4k3/8/8/8/8/8/8/3BK1b1 w - - 0 1", 8, 8).unwrap();
+        assert!(!board.cannot_checkmate(&TeamColor::Black));
+        let board = Board::new("4k3/8/8/8/8/8/8/2B1K1b1 w - - 0 1", 8, 8).unwrap();
+        assert!(board.cannot_checkmate(&TeamColor::Black));
+    }
+
+    #[test]
+    fn writes_and_reads_algebraic_notation() {
+        // Knights on b1 and f1 share a rank, rooks on a1 and a5 share a file, and queens on
+        // d4, d6 and f4 need both
+        let board = Board::new("K5k1/8/3Q4/R7/3Q1Q2/8/8/RN3N2 w - - 0 1", 8, 8).unwrap();
+        let san = |notation: &str| board.to_san(&board.from_san(notation).unwrap());
+
+        assert_eq!(san("Nbd2"), "Nbd2");
+        assert_eq!(san("R1a3"), "R1a3");
+        assert_eq!(san("Qd4e5"), "Qd4e5");
+        assert_eq!(san("Qff8"), "Qff8+");
+        assert!(board.from_san("Nd2").is_none());
+
+        let board = Board::new("r3k3/1P1ppp2/8/8/8/8/8/4K2R w K - 0 1", 8, 8).unwrap();
+        let san = |notation: &str| board.to_san(&board.from_san(notation).unwrap());
+
+        assert_eq!(san("0-0"), "O-O");
+        assert_eq!(san("b8=N"), "b8=N");
+        assert_eq!(san("bxa8=Q"), "bxa8=Q#");
+        assert_eq!(san("Rh8"), "Rh8#");
+
+        let promotion = board.parse_move("b7b8n").unwrap();
+        assert_eq!(board.to_uci(&promotion), "b7b8n");
+        assert_eq!(board.to_san(&promotion), "b8=N");
+        assert_eq!(board.parse_move("e1g1"), board.from_san("O-O"));
+        assert!(board.from_uci("b7b8").is_none());
+    }
+
+    #[test]
+    fn bitboards_match_generic_move_generation() {
+        let fens = [
+            STARTING_FEN,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+        ];
+        let coordinates = |board: &Board| {
+            let mut moves: Vec<String> = board.legal_moves().iter().map(|vm| vm.to_coordinates(8)).collect();
+            moves.sort();
+            moves
+        };
+
+        for fen in fens {
+            let board = Board::new(fen, 8, 8).unwrap();
+            for first in board.legal_moves() {
+                let mut fast = board.clone();
+                fast.make_move(&first);
+                let mut generic = fast.clone();
+                generic.bitboards = None;
+
+                assert_eq!(coordinates(&fast), coordinates(&generic), "after {fen} {first:?}");
+            }
+        }
+    }
+}
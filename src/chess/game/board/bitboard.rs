@@ -0,0 +1,198 @@
+use super::tile::Tile;
+use crate::chess::game::pieces::{Piece, PieceType, Position, TeamColor};
+
+/// Steps sliding pieces take, as `(x, y)`, with rooks on even indices and bishops on odd ones
+const DIRECTIONS: [(i8, i8); 8] = [(0, -1), (1, -1), (1, 0), (1, 1), (0, 1), (-1, 1), (-1, 0), (-1, -1)];
+
+const KNIGHT_STEPS: [(i8, i8); 8] = [(1, 2), (2, 1), (2, -1), (1, -2), (-1, -2), (-2, -1), (-2, 1), (-1, 2)];
+const KING_STEPS: [(i8, i8); 8] = DIRECTIONS;
+
+/// White pawns move towards the top of the board, which is `y = 0`
+const WHITE_PAWN_STEPS: [(i8, i8); 2] = [(-1, -1), (1, -1)];
+const BLACK_PAWN_STEPS: [(i8, i8); 2] = [(-1, 1), (1, 1)];
+
+/// Every square a slider passes over from each square in each direction, on an empty board
+const RAYS: [[u64; 64]; 8] = build_rays();
+
+const KNIGHT_ATTACKS: [u64; 64] = build_steps(&KNIGHT_STEPS);
+const KING_ATTACKS: [u64; 64] = build_steps(&KING_STEPS);
+const PAWN_ATTACKS: [[u64; 64]; 2] = [build_steps(&WHITE_PAWN_STEPS), build_steps(&BLACK_PAWN_STEPS)];
+
+const fn build_steps(steps: &[(i8, i8)]) -> [u64; 64] {
+    let mut table = [0; 64];
+    let mut square = 0;
+    while square < 64 {
+        let (x, y) = ((square % 8) as i8, (square / 8) as i8);
+        let mut i = 0;
+        while i < steps.len() {
+            let (tx, ty) = (x + steps[i].0, y + steps[i].1);
+            if tx >= 0 && tx < 8 && ty >= 0 && ty < 8 {
+                table[square] |= 1 << (ty * 8 + tx);
+            }
+            i += 1;
+        }
+        square += 1;
+    }
+    table
+}
+
+const fn build_rays() -> [[u64; 64]; 8] {
+    let mut rays = [[0; 64]; 8];
+    let mut direction = 0;
+    while direction < 8 {
+        let (dx, dy) = DIRECTIONS[direction];
+        let mut square = 0;
+        while square < 64 {
+            let (mut x, mut y) = ((square % 8) as i8 + dx, (square / 8) as i8 + dy);
+            while x >= 0 && x < 8 && y >= 0 && y < 8 {
+                rays[direction][square] |= 1 << (y * 8 + x);
+                x += dx;
+                y += dy;
+            }
+            square += 1;
+        }
+        direction += 1;
+    }
+    rays
+}
+
+/// ### Attacks of a slider in one direction, stopping at the first piece in the way
+///
+/// The ray beyond the blocker is looked up from the blocker's own square and removed,
+/// which needs no magic multipliers or PEXT instructions
+fn ray_attacks(square: usize, direction: usize, occupied: u64) -> u64 {
+    let ray = RAYS[direction][square];
+    let blockers = ray & occupied;
+    if blockers == 0 {
+        return ray;
+    }
+
+    let (dx, dy) = DIRECTIONS[direction];
+    let blocker = match dy * 8 + dx > 0 {
+        true => blockers.trailing_zeros(),
+        false => 63 - blockers.leading_zeros(),
+    };
+    ray ^ RAYS[direction][blocker as usize]
+}
+
+fn rook_attacks(square: usize, occupied: u64) -> u64 {
+    [0, 2, 4, 6].into_iter().fold(0, |attacks, direction| {
+        attacks | ray_attacks(square, direction, occupied)
+    })
+}
+
+fn bishop_attacks(square: usize, occupied: u64) -> u64 {
+    [1, 3, 5, 7].into_iter().fold(0, |attacks, direction| {
+        attacks | ray_attacks(square, direction, occupied)
+    })
+}
+
+/// ### Piece placement of a standard 8x8 board, one bit per square
+///
+/// Square `y * 8 + x` is set for a piece on `Position { x, y }`. Kept in step with the
+/// board's tiles, so attack and move lookups don't need to walk them
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Bitboards {
+    colors: [u64; 2],
+    kinds: [u64; 6],
+}
+
+impl Bitboards {
+    pub fn new(tiles: &[Tile]) -> Self {
+        let mut bitboards = Self::default();
+        for (square, tile) in tiles.iter().enumerate() {
+            if let Tile::Piece { piece } = tile {
+                bitboards.toggle(square, piece);
+            }
+        }
+        bitboards
+    }
+
+    /// Adds the piece to the square if it is not there, or removes it if it is
+    pub fn toggle(&mut self, square: usize, piece: &Piece) {
+        let bit = 1 << square;
+        self.colors[color_index(piece.get_color())] ^= bit;
+        self.kinds[kind_index(piece.get_kind())] ^= bit;
+    }
+
+    pub fn occupied(&self) -> u64 {
+        self.colors[0] | self.colors[1]
+    }
+
+    pub fn color(&self, color: &TeamColor) -> u64 {
+        self.colors[color_index(color)]
+    }
+
+    pub fn pieces(&self, color: &TeamColor, kind: PieceType) -> u64 {
+        self.color(color) & self.kinds[kind_index(&kind)]
+    }
+
+    /// ### Squares a piece on the given square attacks
+    ///
+    /// For pawns these are only the diagonal captures
+    pub fn attacks(&self, square: usize, color: &TeamColor, kind: PieceType) -> u64 {
+        match kind {
+            PieceType::Pawn => PAWN_ATTACKS[color_index(color)][square],
+            PieceType::Knight => KNIGHT_ATTACKS[square],
+            PieceType::King => KING_ATTACKS[square],
+            PieceType::Bishop => bishop_attacks(square, self.occupied()),
+            PieceType::Rook => rook_attacks(square, self.occupied()),
+            PieceType::Queen => bishop_attacks(square, self.occupied()) | rook_attacks(square, self.occupied()),
+        }
+    }
+
+    /// ### Checks if any piece of the color `by` attacks the square
+    ///
+    /// Looks outwards from the square with each kind of piece, and checks for an enemy of that kind
+    pub fn is_attacked(&self, square: usize, by: &TeamColor) -> bool {
+        let diagonal = self.pieces(by, PieceType::Bishop) | self.pieces(by, PieceType::Queen);
+        let orthogonal = self.pieces(by, PieceType::Rook) | self.pieces(by, PieceType::Queen);
+
+        PAWN_ATTACKS[color_index(&by.opposite())][square] & self.pieces(by, PieceType::Pawn) != 0
+            || KNIGHT_ATTACKS[square] & self.pieces(by, PieceType::Knight) != 0
+            || KING_ATTACKS[square] & self.pieces(by, PieceType::King) != 0
+            || bishop_attacks(square, self.occupied()) & diagonal != 0
+            || rook_attacks(square, self.occupied()) & orthogonal != 0
+    }
+}
+
+pub fn square(pos: &Position) -> usize {
+    pos.y as usize * 8 + pos.x as usize
+}
+
+pub fn position(square: usize) -> Position {
+    Position {
+        x: (square % 8) as u16,
+        y: (square / 8) as u16,
+    }
+}
+
+/// Iterates over the set squares of a bitboard, lowest first
+pub fn squares(mut bitboard: u64) -> impl Iterator<Item = usize> {
+    std::iter::from_fn(move || {
+        if bitboard == 0 {
+            return None;
+        }
+        let square = bitboard.trailing_zeros() as usize;
+        bitboard &= bitboard - 1;
+        Some(square)
+    })
+}
+
+fn color_index(color: &TeamColor) -> usize {
+    match color {
+        TeamColor::White => 0,
+        TeamColor::Black => 1,
+    }
+}
+
+fn kind_index(kind: &PieceType) -> usize {
+    match kind {
+        PieceType::King => 0,
+        PieceType::Queen => 1,
+        PieceType::Pawn => 2,
+        PieceType::Bishop => 3,
+        PieceType::Knight => 4,
+        PieceType::Rook => 5,
+    }
+}
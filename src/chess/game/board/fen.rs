@@ -0,0 +1,313 @@
+use std::{error::Error, fmt::Display};
+
+use super::{bitboard::Bitboards, tile::Tile, Board, CastlingRights};
+use crate::chess::game::pieces::{Piece, PieceType, Position, TeamColor};
+
+impl Board {
+    /// ### Creates a board from a FEN string
+    ///
+    /// Accepts full six-field FEN. Trailing fields may be left off, in which case
+    /// white is to move, nobody may castle, there is no en passant square, and the
+    /// clocks start at `0 1`
+    ///
+    /// The piece placement must have exactly `height` ranks of `width` tiles each
+    pub fn new(fen: &str, height: u8, width: u8) -> Result<Self, FenError> {
+        let mut fields = fen.split_whitespace();
+
+        let placement = fields.next().ok_or(FenError::Empty)?;
+        let tiles = Board::parse_placement(placement, height, width)?;
+
+        let turn = match fields.next().unwrap_or("w") {
+            "w" => TeamColor::White,
+            "b" => TeamColor::Black,
+            other => return Err(FenError::InvalidTurn(other.to_string())),
+        };
+
+        let castling = CastlingRights::parse(fields.next().unwrap_or("-"))?;
+
+        let en_passant = match fields.next().unwrap_or("-") {
+            "-" => None,
+            square => match Position::from_square(square, height) {
+                Some(pos) if pos.x < width as u16 && pos.y < height as u16 => Some(pos),
+                _ => return Err(FenError::InvalidEnPassant(square.to_string())),
+            },
+        };
+
+        let halfmove_clock = match fields.next() {
+            Some(clock) => clock.parse().map_err(|_| FenError::InvalidClock(clock.to_string()))?,
+            None => 0,
+        };
+        let fullmove_number = match fields.next() {
+            Some(number) => match number.parse() {
+                Ok(n) if n > 0 => n,
+                _ => return Err(FenError::InvalidClock(number.to_string())),
+            },
+            None => 1,
+        };
+
+        if fields.next().is_some() {
+            return Err(FenError::TooManyFields);
+        }
+
+        let bitboards = (height == 8 && width == 8).then(|| Bitboards::new(&tiles));
+
+        Ok(Self {
+            tiles,
+            bitboards,
+            height,
+            width,
+            castling,
+            turn,
+            en_passant,
+            halfmove_clock,
+            fullmove_number,
+        })
+    }
+
+    /// ### Creates a board from a FEN string, sized to fit its piece placement
+    ///
+    /// The board is as tall as the placement has ranks, and as wide as its top rank
+    pub fn from_fen(fen: &str) -> Result<Self, FenError> {
+        let placement = fen.split_whitespace().next().ok_or(FenError::Empty)?;
+        let ranks: Vec<&str> = placement.split('/').collect();
+
+        let height = u8::try_from(ranks.len()).map_err(|_| FenError::WrongHeight {
+            expect: u8::MAX,
+            found: ranks.len(),
+        })?;
+        let width = Board::rank_width(ranks[0]);
+        let width = u8::try_from(width).map_err(|_| FenError::WrongWidth {
+            rank: height as u16,
+            expect: u8::MAX,
+            found: width.min(u16::MAX as u32) as u16,
+        })?;
+
+        Board::new(fen, height, width)
+    }
+
+    /// How many tiles a rank of a piece placement covers, saturating instead of overflowing
+    fn rank_width(rank: &str) -> u32 {
+        let mut width = 0u32;
+        let mut run = 0u32;
+        for char in rank.chars() {
+            match char.to_digit(10) {
+                Some(digit) => run = run.saturating_mul(10).saturating_add(digit),
+                None => {
+                    width = width.saturating_add(run).saturating_add(1);
+                    run = 0;
+                }
+            }
+        }
+        width.saturating_add(run)
+    }
+
+    /// Reads the tiles rank by rank, from the top of the board
+    fn parse_placement(placement: &str, height: u8, width: u8) -> Result<Vec<Tile>, FenError> {
+        let mut tiles = Vec::with_capacity(height as usize * width as usize);
+        let ranks: Vec<&str> = placement.split('/').collect();
+
+        if ranks.len() != height as usize {
+            return Err(FenError::WrongHeight {
+                expect: height,
+                found: ranks.len(),
+            });
+        }
+
+        for (y, rank) in ranks.into_iter().enumerate() {
+            let y = y as u16;
+            let mut x = 0u16;
+            let mut chars = rank.chars().peekable();
+
+            while let Some(char) = chars.next() {
+                if let Some(digit) = char.to_digit(10) {
+                    // Empty runs may take more than one digit on wide boards, but can't run past the edge
+                    let mut num = Some(digit);
+                    while let Some(next) = chars.peek().and_then(|c| c.to_digit(10)) {
+                        num = num.and_then(|num| num.checked_mul(10)?.checked_add(next));
+                        chars.next();
+                    }
+
+                    let end = num.and_then(|num| num.checked_add(x as u32));
+                    let num = match end {
+                        Some(end) if end <= width as u32 => end - x as u32,
+                        _ => {
+                            return Err(FenError::WrongWidth {
+                                rank: height as u16 - y,
+                                expect: width,
+                                found: end.map_or(u16::MAX, |end| end.min(u16::MAX as u32) as u16),
+                            })
+                        }
+                    };
+
+                    for _ in 0..num {
+                        tiles.push(Tile::Empty);
+                        x += 1;
+                    }
+                } else {
+                    let piece = Board::char_to_piece(char, Position { x, y })?;
+                    tiles.push(Tile::Piece { piece });
+                    x += 1;
+                }
+            }
+
+            if x != width as u16 {
+                return Err(FenError::WrongWidth {
+                    rank: height as u16 - y,
+                    expect: width,
+                    found: x,
+                });
+            }
+        }
+
+        Ok(tiles)
+    }
+
+    /// ### Serializes the board as a six-field FEN string
+    pub fn to_fen(&self) -> String {
+        let mut ranks = Vec::with_capacity(self.height as usize);
+
+        for y in 0..self.height as u16 {
+            let mut rank = String::new();
+            let mut empty = 0;
+
+            for x in 0..self.width as u16 {
+                match self.get_tile(&Position { x, y }) {
+                    Tile::Piece { piece } => {
+                        if empty > 0 {
+                            rank.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        rank.push(Board::piece_to_char(piece));
+                    }
+                    _ => empty += 1,
+                }
+            }
+
+            if empty > 0 {
+                rank.push_str(&empty.to_string());
+            }
+            ranks.push(rank);
+        }
+
+        let turn = match self.turn {
+            TeamColor::White => "w",
+            TeamColor::Black => "b",
+        };
+
+        let en_passant = match &self.en_passant {
+            Some(pos) => pos.to_square(self.height),
+            None => "-".to_string(),
+        };
+
+        format!(
+            "{} {turn} {} {en_passant} {} {}",
+            ranks.join("/"),
+            self.castling,
+            self.halfmove_clock,
+            self.fullmove_number
+        )
+    }
+
+    pub fn char_to_piece(char: char, pos: Position) -> Result<Piece, FenError> {
+        let color = if char.is_uppercase() {
+            TeamColor::White
+        } else {
+            TeamColor::Black
+        };
+
+        let kind = PieceType::from_char(char).ok_or(FenError::InvalidPiece(char))?;
+
+        Ok(Piece::new(color, pos, kind))
+    }
+
+    pub fn piece_to_char(piece: &Piece) -> char {
+        let char = piece.get_kind().to_char();
+
+        match piece.get_color() {
+            TeamColor::White => char.to_ascii_uppercase(),
+            TeamColor::Black => char,
+        }
+    }
+}
+
+impl CastlingRights {
+    fn parse(field: &str) -> Result<Self, FenError> {
+        let mut rights = CastlingRights::default();
+        if field == "-" {
+            return Ok(rights);
+        }
+
+        for char in field.chars() {
+            match char {
+                'K' => rights.white_kingside = true,
+                'Q' => rights.white_queenside = true,
+                'k' => rights.black_kingside = true,
+                'q' => rights.black_queenside = true,
+                _ => return Err(FenError::InvalidCastling(field.to_string())),
+            }
+        }
+
+        Ok(rights)
+    }
+}
+
+impl Display for CastlingRights {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let rights: String = [
+            (self.white_kingside, 'K'),
+            (self.white_queenside, 'Q'),
+            (self.black_kingside, 'k'),
+            (self.black_queenside, 'q'),
+        ]
+        .into_iter()
+        .filter_map(|(allowed, char)| allowed.then_some(char))
+        .collect();
+
+        if rights.is_empty() {
+            write!(f, "-")
+        } else {
+            write!(f, "{rights}")
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum FenError {
+    Empty,
+    TooManyFields,
+
+    WrongHeight { expect: u8, found: usize },
+    WrongWidth { rank: u16, expect: u8, found: u16 },
+    InvalidPiece(char),
+
+    InvalidTurn(String),
+    InvalidCastling(String),
+    InvalidEnPassant(String),
+    InvalidClock(String),
+}
+
+impl Display for FenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Empty => write!(f, "EmptyFen: No piece placement was provided"),
+            Self::TooManyFields => write!(f, "TooManyFields: FEN must have at most six fields"),
+            Self::WrongHeight { expect, found } => {
+                write!(f, "WrongHeight: Expected {expect} ranks, found {found}")
+            }
+            Self::WrongWidth { rank, expect, found } => {
+                write!(f, "WrongWidth: Expected {expect} tiles on rank {rank}, found {found}")
+            }
+            Self::InvalidPiece(char) => write!(f, "InvalidPiece: '{char}' is not a valid piece"),
+            Self::InvalidTurn(turn) => write!(f, "InvalidTurn: '{turn}' must be either 'w' or 'b'"),
+            Self::InvalidCastling(castling) => {
+                write!(f, "InvalidCastling: '{castling}' is not a valid set of castling rights")
+            }
+            Self::InvalidEnPassant(square) => {
+                write!(f, "InvalidEnPassant: '{square}' is not a valid en passant square")
+            }
+            Self::InvalidClock(clock) => write!(f, "InvalidClock: '{clock}' is not a valid move counter"),
+        }
+    }
+}
+
+impl Error for FenError {}
@@ -0,0 +1,118 @@
+use super::Board;
+use crate::chess::game::pieces::ValidMove;
+
+impl Board {
+    /// ### Counts every move sequence of the given length from this position
+    ///
+    /// Comparing these counts against published results is the standard test of move generation
+    pub fn perft(&self, depth: u32) -> u64 {
+        self.clone().count_leaves(depth)
+    }
+
+    /// ### Splits the perft count by the first move played
+    ///
+    /// Comparing this against another engine shows which move leads to a miscounted subtree
+    pub fn divide(&self, depth: u32) -> Vec<(ValidMove, u64)> {
+        if depth == 0 {
+            return vec![];
+        }
+
+        let mut board = self.clone();
+        self.legal_moves()
+            .into_iter()
+            .map(|valid_move| {
+                board.make_move(&valid_move);
+                let nodes = board.count_leaves(depth - 1);
+                board.unmake_move(&valid_move);
+                (valid_move, nodes)
+            })
+            .collect()
+    }
+
+    fn count_leaves(&mut self, depth: u32) -> u64 {
+        match depth {
+            0 => return 1,
+            // Leaves are counted without playing them
+            1 => return self.legal_moves().len() as u64,
+            _ => (),
+        }
+
+        let mut nodes = 0;
+        for valid_move in self.legal_moves() {
+            self.make_move(&valid_move);
+            nodes += self.count_leaves(depth - 1);
+            self.unmake_move(&valid_move);
+        }
+        nodes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::STARTING_FEN;
+    use super::*;
+
+    /// Checks node counts from the Chess Programming Wiki's perft results
+    fn assert_perft(fen: &str, counts: &[u64]) {
+        let board = Board::new(fen, 8, 8).unwrap();
+        for (depth, &count) in counts.iter().enumerate() {
+            assert_eq!(board.perft(depth as u32 + 1), count, "depth {} of {fen}", depth + 1);
+        }
+    }
+
+    #[test]
+    fn start_position() {
+        assert_perft(STARTING_FEN, &[20, 400, 8_902, 197_281]);
+    }
+
+    #[test]
+    fn kiwipete() {
+        assert_perft(
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            &[48, 2_039, 97_862],
+        );
+    }
+
+    #[test]
+    fn position_3() {
+        assert_perft("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", &[14, 191, 2_812, 43_238]);
+    }
+
+    #[test]
+    fn position_4() {
+        assert_perft(
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            &[6, 264, 9_467],
+        );
+        // The same position with colors swapped must give the same counts
+        assert_perft(
+            "r2q1rk1/pP1p2pp/Q4n2/bbp1p3/Np6/1B3NBn/pPPP1PPP/R3K2R b KQ - 0 1",
+            &[6, 264, 9_467],
+        );
+    }
+
+    #[test]
+    fn position_5() {
+        assert_perft(
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            &[44, 1_486, 62_379],
+        );
+    }
+
+    #[test]
+    fn position_6() {
+        assert_perft(
+            "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+            &[46, 2_079, 89_890],
+        );
+    }
+
+    #[test]
+    fn divide_sums_to_perft() {
+        let board = Board::default();
+        let divided = board.divide(3);
+
+        assert_eq!(divided.len(), 20);
+        assert_eq!(divided.iter().map(|(_, nodes)| nodes).sum::<u64>(), board.perft(3));
+    }
+}
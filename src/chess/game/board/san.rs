@@ -0,0 +1,100 @@
+use super::{tile::Tile, Board};
+use crate::chess::game::pieces::{MoveKind, PieceType, ValidMove};
+
+impl Board {
+    /// ### Writes a move in Standard Algebraic Notation, such as `Nbd7`, `exd6` or `e8=Q#`
+    ///
+    /// The move must be legal in this position
+    pub fn to_san(&self, valid_move: &ValidMove) -> String {
+        let mut san = self.san_without_suffix(valid_move, &self.legal_moves());
+
+        let mut after = self.clone();
+        after.make_move(valid_move);
+        if after.is_in_check(&after.turn) {
+            san.push(match after.legal_moves().is_empty() {
+                true => '#',
+                false => '+',
+            });
+        }
+
+        san
+    }
+
+    /// ### Reads a move in Standard Algebraic Notation
+    ///
+    /// Check, mate and annotation suffixes are ignored, and castling may be written with zeroes
+    ///
+    /// Returns `None` if the move is not legal in this position
+    pub fn from_san(&self, san: &str) -> Option<ValidMove> {
+        let san = san.trim_end_matches(['+', '#', '!', '?']);
+        let san = match san.starts_with("0-0") {
+            true => san.replace('0', "O"),
+            false => san.to_string(),
+        };
+        let legal = self.legal_moves();
+
+        legal
+            .iter()
+            .find(|vm| self.san_without_suffix(vm, &legal) == san)
+            .cloned()
+    }
+
+    fn san_without_suffix(&self, valid_move: &ValidMove, legal: &[ValidMove]) -> String {
+        if let MoveKind::Castle { .. } = valid_move.kind {
+            return match valid_move.target.x > valid_move.source.x {
+                true => "O-O".to_string(),
+                false => "O-O-O".to_string(),
+            };
+        }
+
+        let kind = match self.get_tile(&valid_move.source) {
+            Tile::Piece { piece } => *piece.get_kind(),
+            _ => return String::new(),
+        };
+        let source = valid_move.source.to_square(self.height);
+        let target = valid_move.target.to_square(self.height);
+        let capture = valid_move.captured.is_some() || matches!(valid_move.kind, MoveKind::EnPassant { .. });
+
+        let mut san = String::new();
+        if kind == PieceType::Pawn {
+            if capture {
+                san.push_str(&source[..1]);
+            }
+        } else {
+            san.push(kind.to_char().to_ascii_uppercase());
+            san.push_str(&self.disambiguation(valid_move, kind, legal));
+        }
+
+        if capture {
+            san.push('x');
+        }
+        san.push_str(&target);
+
+        if let Some(promotion) = valid_move.promotion {
+            san.push('=');
+            san.push(promotion.to_char().to_ascii_uppercase());
+        }
+
+        san
+    }
+
+    /// The part of the source square needed to tell this move apart from other pieces of the same kind
+    fn disambiguation(&self, valid_move: &ValidMove, kind: PieceType, legal: &[ValidMove]) -> String {
+        let rivals: Vec<&ValidMove> = legal
+            .iter()
+            .filter(|vm| vm.target == valid_move.target && vm.source != valid_move.source)
+            .filter(|vm| matches!(self.get_tile(&vm.source), Tile::Piece { piece } if piece.get_kind() == &kind))
+            .collect();
+
+        let source = valid_move.source.to_square(self.height);
+        if rivals.is_empty() {
+            String::new()
+        } else if rivals.iter().all(|vm| vm.source.x != valid_move.source.x) {
+            source[..1].to_string()
+        } else if rivals.iter().all(|vm| vm.source.y != valid_move.source.y) {
+            source[1..].to_string()
+        } else {
+            source
+        }
+    }
+}
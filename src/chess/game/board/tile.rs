@@ -1,5 +1,6 @@
 use crate::chess::game::pieces;
 
+#[derive(Clone)]
 pub enum Tile {
     Piece { piece: pieces::Piece },
     Empty,
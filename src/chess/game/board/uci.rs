@@ -0,0 +1,57 @@
+use super::Board;
+use crate::chess::game::pieces::{Move, PieceType, Position, ValidMove};
+
+impl Board {
+    /// ### Writes a move in UCI long algebraic notation, such as `e2e4` or `e7e8q`
+    ///
+    /// Castling is written as the king's move, as in `e1g1`
+    pub fn to_uci(&self, valid_move: &ValidMove) -> String {
+        valid_move.to_coordinates(self.height)
+    }
+
+    /// ### Reads a move in UCI long algebraic notation
+    ///
+    /// Returns `None` if the move is not legal in this position
+    pub fn from_uci(&self, uci: &str) -> Option<ValidMove> {
+        let (source, rest) = split_square(uci)?;
+        let (target, rest) = split_square(rest)?;
+
+        let mut promotion = rest.chars();
+        let promotion = match (promotion.next(), promotion.next()) {
+            (None, _) => None,
+            (Some(c), None) => Some(PieceType::from_char(c).filter(|kind| kind != &PieceType::Pawn)?),
+            _ => return None,
+        };
+
+        let valid_move = self.validate_move(Move {
+            source: Position::from_square(source, self.height)?,
+            target: Position::from_square(target, self.height)?,
+            promotion,
+        })?;
+
+        // UCI always names the promotion piece, so the queen default does not apply
+        match valid_move.promotion == promotion {
+            true => Some(valid_move),
+            false => None,
+        }
+    }
+
+    /// ### Reads a move in either UCI or Standard Algebraic Notation
+    ///
+    /// Returns `None` if the move is not legal in this position
+    pub fn parse_move(&self, notation: &str) -> Option<ValidMove> {
+        let notation = notation.trim();
+        self.from_uci(notation).or_else(|| self.from_san(notation))
+    }
+}
+
+/// Splits a square name, such as `e4` or `b10`, from the start of a move
+fn split_square(text: &str) -> Option<(&str, &str)> {
+    let file_end = text.chars().next().filter(|c| c.is_ascii_lowercase())?.len_utf8();
+    let rank_end = file_end + text[file_end..].chars().take_while(|c| c.is_ascii_digit()).count();
+
+    match rank_end > file_end {
+        true => Some(text.split_at(rank_end)),
+        false => None,
+    }
+}
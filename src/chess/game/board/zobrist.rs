@@ -0,0 +1,88 @@
+use super::{tile::Tile, Board};
+use crate::chess::game::pieces::{Moveset, Piece, PieceType, Position, TeamColor};
+
+// Keys past every possible piece key, as boards are at most 256x256
+const TURN_KEY: u64 = 256 * 256 * 12;
+const CASTLING_KEY: u64 = TURN_KEY + 1;
+const EN_PASSANT_KEY: u64 = CASTLING_KEY + 4;
+
+impl Board {
+    /// ### Hashes the position for repetition detection
+    ///
+    /// Two positions hash the same when the same pieces stand on the same tiles, the
+    /// same side is to move, and the same castling and en passant captures are possible
+    pub fn zobrist_hash(&self) -> u64 {
+        let mut hash = 0;
+
+        for tile in self.tiles.iter() {
+            if let Tile::Piece { piece } = tile {
+                hash ^= key(piece_index(piece));
+            }
+        }
+
+        if self.turn == TeamColor::Black {
+            hash ^= key(TURN_KEY);
+        }
+
+        let castling = [
+            self.castling.white_kingside,
+            self.castling.white_queenside,
+            self.castling.black_kingside,
+            self.castling.black_queenside,
+        ];
+        for (i, _) in castling.into_iter().enumerate().filter(|(_, allowed)| *allowed) {
+            hash ^= key(CASTLING_KEY + i as u64);
+        }
+
+        if let Some(file) = self.en_passant_file() {
+            hash ^= key(EN_PASSANT_KEY + file as u64);
+        }
+
+        hash
+    }
+
+    /// The file of the en passant square, but only if a pawn is actually able to capture onto it
+    fn en_passant_file(&self) -> Option<u16> {
+        let target = self.en_passant.as_ref()?;
+        let behind = match self.turn {
+            TeamColor::White => 1,
+            TeamColor::Black => -1,
+        };
+
+        let can_capture = [-1, 1]
+            .into_iter()
+            .filter_map(|side| target + Moveset::new(side, behind))
+            .any(|pos| match self.get_tile(&pos) {
+                Tile::Piece { piece } => piece.is_pawn() && piece.get_color() == &self.turn,
+                _ => false,
+            });
+
+        can_capture.then_some(target.x)
+    }
+}
+
+fn piece_index(piece: &Piece) -> u64 {
+    let Position { x, y } = piece.get_position();
+    let kind = match piece.get_kind() {
+        PieceType::King => 0,
+        PieceType::Queen => 1,
+        PieceType::Pawn => 2,
+        PieceType::Bishop => 3,
+        PieceType::Knight => 4,
+        PieceType::Rook => 5,
+    };
+    let color = match piece.get_color() {
+        TeamColor::White => 0,
+        TeamColor::Black => 6,
+    };
+
+    (*y as u64 * 256 + *x as u64) * 12 + color + kind
+}
+
+/// Derives a pseudo-random key from an index with splitmix64, so no table is needed for large boards
+fn key(index: u64) -> u64 {
+    let mut z = index.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
@@ -0,0 +1,197 @@
+use std::{collections::VecDeque, error::Error, fmt::Display, sync::OnceLock, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+static RULES: OnceLock<ChatRules> = OnceLock::new();
+
+/// ### What chat messages are allowed
+///
+/// Every field can be left out of the config file, to keep its default
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ChatRules {
+    /// The longest message allowed, in characters
+    pub max_length: usize,
+    /// Words hidden from messages, matched whole and ignoring case
+    pub filtered_words: Vec<String>,
+    pub rate_limit: RateLimit,
+}
+
+impl Default for ChatRules {
+    fn default() -> Self {
+        Self {
+            max_length: 500,
+            filtered_words: vec![],
+            rate_limit: RateLimit::default(),
+        }
+    }
+}
+
+impl ChatRules {
+    /// ### Sets the rules every chat message is checked against
+    ///
+    /// Can only be done once, before any messages are sent. Returns `Err(rules)` if rules are already in use
+    pub fn install(rules: ChatRules) -> Result<(), ChatRules> {
+        RULES.set(rules)
+    }
+
+    /// The installed rules, or the defaults if none were installed
+    pub fn get() -> &'static ChatRules {
+        RULES.get_or_init(ChatRules::default)
+    }
+
+    /// ### Checks a message, and returns the text to show
+    ///
+    /// Surrounding whitespace is trimmed, and filtered words are replaced with asterisks
+    pub fn moderate(&self, message: &str) -> Result<String, ChatError> {
+        let message = message.trim();
+        if message.is_empty() {
+            return Err(ChatError::Empty);
+        }
+        if message.chars().count() > self.max_length {
+            return Err(ChatError::TooLong(self.max_length));
+        }
+
+        let mut moderated = String::with_capacity(message.len());
+        let mut word = String::new();
+        for c in message.chars().chain(std::iter::once(' ')) {
+            if c.is_alphanumeric() {
+                word.push(c);
+                continue;
+            }
+            if self.is_filtered(&word) {
+                moderated.extend(word.chars().map(|_| '*'));
+            } else {
+                moderated.push_str(&word);
+            }
+            word.clear();
+            moderated.push(c);
+        }
+        moderated.pop();
+
+        Ok(moderated)
+    }
+
+    fn is_filtered(&self, word: &str) -> bool {
+        !word.is_empty()
+            && self
+                .filtered_words
+                .iter()
+                .any(|filtered| filtered.to_lowercase() == word.to_lowercase())
+    }
+}
+
+/// How many messages each user may send within a window of time
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct RateLimit {
+    pub messages: usize,
+    pub seconds: u64,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            messages: 5,
+            seconds: 10,
+        }
+    }
+}
+
+/// ### Keeps track of how quickly one user is chatting
+///
+/// Shared across all of their games
+#[derive(Debug)]
+pub struct ChatLimiter {
+    limit: RateLimit,
+    sent: VecDeque<Instant>,
+}
+
+impl ChatLimiter {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            sent: VecDeque::new(),
+        }
+    }
+
+    /// ### Records a message being sent
+    ///
+    /// Returns `Err(ChatError::RateLimited)` without recording it if the user has already sent too many
+    pub fn check(&mut self) -> Result<(), ChatError> {
+        let window = Duration::from_secs(self.limit.seconds);
+        while self.sent.front().is_some_and(|sent| sent.elapsed() >= window) {
+            self.sent.pop_front();
+        }
+
+        if self.sent.len() >= self.limit.messages {
+            return Err(ChatError::RateLimited);
+        }
+        self.sent.push_back(Instant::now());
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub enum ChatError {
+    Empty,
+    TooLong(usize),
+    RateLimited,
+    Disabled,
+    Closed,
+}
+
+impl Display for ChatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Empty => write!(f, "Empty: Messages cannot be blank"),
+            Self::TooLong(max) => write!(f, "TooLong: Messages can be at most {max} characters"),
+            Self::RateLimited => write!(f, "RateLimited: You are sending messages too quickly"),
+            Self::Disabled => write!(f, "Disabled: Chat is turned off for this game"),
+            Self::Closed => write!(f, "Closed: This game's chat has closed"),
+        }
+    }
+}
+
+impl Error for ChatError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules() -> ChatRules {
+        ChatRules {
+            max_length: 10,
+            filtered_words: vec!["darn".to_string()],
+            ..ChatRules::default()
+        }
+    }
+
+    #[test]
+    fn filters_whole_words_ignoring_case() {
+        assert_eq!(rules().moderate("  Darn it!  ").unwrap(), "**** it!");
+        assert_eq!(rules().moderate("darning").unwrap(), "darning");
+    }
+
+    #[test]
+    fn rejects_blank_and_long_messages() {
+        assert!(matches!(rules().moderate(" \n "), Err(ChatError::Empty)));
+        assert!(matches!(rules().moderate("good game!!"), Err(ChatError::TooLong(10))));
+        assert!(rules().moderate("good game!").is_ok());
+    }
+
+    #[tokio::test]
+    async fn limits_messages_per_window() {
+        let mut limiter = ChatLimiter::new(RateLimit {
+            messages: 2,
+            seconds: 1,
+        });
+
+        assert!(limiter.check().is_ok());
+        assert!(limiter.check().is_ok());
+        assert!(matches!(limiter.check(), Err(ChatError::RateLimited)));
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert!(limiter.check().is_ok());
+    }
+}
@@ -0,0 +1,88 @@
+use std::time::Duration;
+
+use serde::Serialize;
+use tokio::time::Instant;
+
+use super::pieces::TeamColor;
+
+/// ### A chess clock for both players
+///
+/// Only the player to move has their time running, which is counted from the
+/// start of their turn
+pub struct Clock {
+    white: Duration,
+    black: Duration,
+    increment: Duration,
+
+    turn_start: Instant,
+}
+
+impl Clock {
+    pub fn new(limit: Duration, increment: Duration) -> Self {
+        Self {
+            white: limit,
+            black: limit,
+            increment,
+            turn_start: Instant::now(),
+        }
+    }
+
+    /// Time left for the given player, counting down if it is their turn
+    pub fn remaining(&self, color: &TeamColor, turn: &TeamColor) -> Duration {
+        let stored = match color {
+            TeamColor::White => self.white,
+            TeamColor::Black => self.black,
+        };
+
+        if color == turn {
+            stored.saturating_sub(self.turn_start.elapsed())
+        } else {
+            stored
+        }
+    }
+
+    /// ### Stops the mover's clock, and starts their opponent's
+    ///
+    /// The mover is given their increment for completing the move
+    pub fn end_turn(&mut self, mover: &TeamColor) {
+        self.switch_turn(mover);
+        match mover {
+            TeamColor::White => self.white += self.increment,
+            TeamColor::Black => self.black += self.increment,
+        }
+    }
+
+    /// ### Hands the turn back to the player whose move was taken back
+    ///
+    /// Stops their opponent's clock, and takes away the increment the move earned
+    pub fn take_back(&mut self, mover: &TeamColor) {
+        self.switch_turn(&mover.opposite());
+        match mover {
+            TeamColor::White => self.white = self.white.saturating_sub(self.increment),
+            TeamColor::Black => self.black = self.black.saturating_sub(self.increment),
+        }
+    }
+
+    /// Stops the given player's clock without any increment, and starts their opponent's
+    pub fn switch_turn(&mut self, from: &TeamColor) {
+        let remaining = self.remaining(from, from);
+        match from {
+            TeamColor::White => self.white = remaining,
+            TeamColor::Black => self.black = remaining,
+        }
+        self.turn_start = Instant::now();
+    }
+
+    pub fn state(&self, turn: &TeamColor) -> ClockState {
+        ClockState {
+            white: self.remaining(&TeamColor::White, turn),
+            black: self.remaining(&TeamColor::Black, turn),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ClockState {
+    pub white: Duration,
+    pub black: Duration,
+}
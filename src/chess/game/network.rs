@@ -1,18 +1,31 @@
-use std::sync::Arc;
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard, Weak,
+    },
+    time::Duration,
+};
 
 use serde::{Deserialize, Serialize};
-use tokio::sync::{broadcast, mpsc, oneshot, watch, RwLock};
+use tokio::{
+    sync::{broadcast, mpsc, oneshot, watch, Notify, RwLock},
+    time::{sleep_until, Instant},
+};
 
-use crate::server::user::UserInfo;
+use crate::server::{user::UserInfo, utils::get_timestamp};
 
 use super::{
     board::Board,
-    pieces::{Move, ValidMove},
+    chat::{ChatError, ChatRules},
+    clock::ClockState,
+    pieces::{TeamColor, Turn, ValidMove},
+    EndState, SpectatorDelay, Winner,
 };
 
 pub struct PlayerInterface {
     user: UserInfo,
-    reciever_tx: mpsc::Sender<oneshot::Sender<(Move, oneshot::Sender<bool>)>>,
+    reciever_tx: mpsc::Sender<oneshot::Sender<(Turn, oneshot::Sender<bool>)>>,
 
     event_interface: EventInterface,
 }
@@ -20,7 +33,7 @@ pub struct PlayerInterface {
 impl PlayerInterface {
     fn new(
         user: UserInfo,
-        tx: mpsc::Sender<oneshot::Sender<(Move, oneshot::Sender<bool>)>>,
+        tx: mpsc::Sender<oneshot::Sender<(Turn, oneshot::Sender<bool>)>>,
         event_tx: mpsc::Sender<Event>,
     ) -> Self {
         Self {
@@ -36,14 +49,22 @@ impl PlayerInterface {
         user: UserInfo,
     ) -> (
         Self,
-        mpsc::Receiver<oneshot::Sender<(Move, oneshot::Sender<bool>)>>,
+        mpsc::Receiver<oneshot::Sender<(Turn, oneshot::Sender<bool>)>>,
         mpsc::Receiver<Event>,
     ) {
         let (tx, rx) = mpsc::channel(2);
-        let (e_tx, e_rx) = mpsc::channel(2);
+        let (e_tx, e_rx) = mpsc::channel(16);
         let this = Self::new(user, tx, e_tx);
         (this, rx, e_rx)
     }
+    pub fn user(&self) -> &UserInfo {
+        &self.user
+    }
+
+    pub async fn send_event(&self, event: Event) -> Result<(), ()> {
+        self.event_interface.send(event).await
+    }
+
     pub async fn valid_move(&self, board: &Board) -> Result<ValidMove, ()> {
         loop {
             let (tx, rx) = oneshot::channel();
@@ -57,7 +78,7 @@ impl PlayerInterface {
                 Err(_) => return Err(()),
             };
 
-            let move_result = board.validate_move(movement);
+            let move_result = board.validate_turn(movement);
             if let Some(vm) = move_result {
                 if result_tx.send(true).is_err() {
                     return Err(());
@@ -73,41 +94,69 @@ impl PlayerInterface {
     }
 }
 
+/// ### Carries a game's chat between its rooms
+///
+/// Players and spectators each have their own room while the game is on, so spectators can't pass hints to players.
+/// Once the game ends, everyone chats in the post-game room
+#[derive(Debug)]
 pub struct MessageInterface {
-    transmitter: broadcast::Sender<ApprovedChatMessage>,
+    // The rooms close once the chat stops, as only its task can send to them
+    players: broadcast::Receiver<RoomMessage>,
+    spectators: broadcast::Receiver<RoomMessage>,
+
+    reciever_tx: mpsc::Sender<(ChatRole, ApprovedChatMessage)>,
 
-    reciever_tx: mpsc::Sender<ChatMessage>,
-    transmitter_rx: broadcast::Receiver<ApprovedChatMessage>,
+    enabled: bool,
+    ended: AtomicBool,
+    /// Each player's opponent, once the game starts
+    opponents: Mutex<BTreeMap<UserInfo, UserInfo>>,
+    /// Players who have muted their opponent
+    muted: Mutex<BTreeSet<UserInfo>>,
 
     halter: RwLock<Option<oneshot::Sender<()>>>,
 }
 
+/// A chat message, and the room it was sent in
+pub type RoomMessage = (ChatRoom, ApprovedChatMessage);
+
 impl MessageInterface {
     fn new(
-        transmitter: broadcast::Sender<ApprovedChatMessage>,
-        reciever_tx: mpsc::Sender<ChatMessage>,
-        transmitter_rx: broadcast::Receiver<ApprovedChatMessage>,
+        players: broadcast::Receiver<RoomMessage>,
+        spectators: broadcast::Receiver<RoomMessage>,
+        reciever_tx: mpsc::Sender<(ChatRole, ApprovedChatMessage)>,
+        enabled: bool,
         halter: oneshot::Sender<()>,
     ) -> Arc<Self> {
         Arc::new(Self {
-            transmitter,
+            players,
+            spectators,
             reciever_tx,
-            transmitter_rx,
+            enabled,
+            ended: AtomicBool::new(false),
+            opponents: Mutex::new(BTreeMap::new()),
+            muted: Mutex::new(BTreeSet::new()),
             halter: RwLock::new(Some(halter)),
         })
     }
 
-    pub fn create() -> Arc<Self> {
+    /// Creates the game's chat, which rejects every message if it is not enabled
+    pub fn create(enabled: bool) -> Arc<Self> {
         // Create channels
         let (shutdown_tx, shutdown_rx) = oneshot::channel();
         let (msg_tx, msg_rx) = mpsc::channel(4);
-        let (app_msg_tx, app_msg_rx) = broadcast::channel(4);
+        let (players_tx, players_rx) = broadcast::channel(4);
+        let (spectators_tx, spectators_rx) = broadcast::channel(4);
 
         // Create this
-        let this = Self::new(app_msg_tx, msg_tx, app_msg_rx, shutdown_tx);
+        let this = Self::new(players_rx, spectators_rx, msg_tx, enabled, shutdown_tx);
 
         // Spawn task
-        tokio::task::spawn(MessageInterface::run(this.clone(), msg_rx, shutdown_rx));
+        tokio::task::spawn(MessageInterface::run(
+            this.clone(),
+            (players_tx, spectators_tx),
+            msg_rx,
+            shutdown_rx,
+        ));
 
         this
     }
@@ -120,17 +169,88 @@ impl MessageInterface {
         }
     }
 
-    pub fn channel(&self) -> (mpsc::Sender<ChatMessage>, broadcast::Receiver<ApprovedChatMessage>) {
-        (self.reciever_tx.clone(), self.transmitter_rx.resubscribe())
+    /// Records who is playing who, so players can mute their opponent
+    pub fn set_players(&self, white: &UserInfo, black: &UserInfo) {
+        let mut opponents = self.opponents.lock().expect("Chat opponents should not be poisoned");
+        opponents.insert(white.clone(), black.clone());
+        opponents.insert(black.clone(), white.clone());
     }
 
-    pub fn spectate(&self) -> broadcast::Receiver<ApprovedChatMessage> {
-        self.transmitter_rx.resubscribe()
+    /// Opens the post-game room, which both players and spectators can see
+    pub fn end(&self) {
+        self.ended.store(true, Ordering::Release);
     }
 
-    async fn run(self: Arc<Self>, mut msg_rx: mpsc::Receiver<ChatMessage>, mut stop_rx: oneshot::Receiver<()>) {
+    /// ### Sends a message to the sender's room
+    ///
+    /// Returns `Err(ChatError::Disabled)` if the game has no chat, and `Err(ChatError::Closed)` once the chat is stopped
+    pub async fn send(&self, role: ChatRole, message: ApprovedChatMessage) -> Result<(), ChatError> {
+        if !self.enabled {
+            return Err(ChatError::Disabled);
+        }
+        self.reciever_tx
+            .send((role, message))
+            .await
+            .map_err(|_| ChatError::Closed)
+    }
+
+    /// The messages players can see, which is everything but the spectators' room
+    pub fn players(&self) -> broadcast::Receiver<RoomMessage> {
+        self.players.resubscribe()
+    }
+
+    /// The messages spectators can see, which is everything but the players' room
+    pub fn spectators(&self) -> broadcast::Receiver<RoomMessage> {
+        self.spectators.resubscribe()
+    }
+
+    /// ### Mutes or unmutes a player's opponent, for that player only
+    ///
+    /// Returns `false` if the user is not playing in this game
+    pub fn mute(&self, user: &UserInfo, muted: bool) -> bool {
+        if !self
+            .opponents
+            .lock()
+            .expect("Chat opponents should not be poisoned")
+            .contains_key(user)
+        {
+            return false;
+        }
+
+        let mut muters = self.muted.lock().expect("Chat mutes should not be poisoned");
+        match muted {
+            true => muters.insert(user.clone()),
+            false => muters.remove(user),
+        };
+        true
+    }
+
+    /// Whether the message should be hidden from the given player, because they muted its sender
+    pub fn is_muted(&self, user: &UserInfo, message: &ApprovedChatMessage) -> bool {
+        if !self
+            .muted
+            .lock()
+            .expect("Chat mutes should not be poisoned")
+            .contains(user)
+        {
+            return false;
+        }
+        self.opponents
+            .lock()
+            .expect("Chat opponents should not be poisoned")
+            .get(user)
+            .is_some_and(|opponent| opponent == &message.sender)
+    }
+
+    /// Passes on messages that were approved by the sender's connection, where any rejection can be reported
+    async fn run(
+        self: Arc<Self>,
+        (players, spectators): (broadcast::Sender<RoomMessage>, broadcast::Sender<RoomMessage>),
+        mut msg_rx: mpsc::Receiver<(ChatRole, ApprovedChatMessage)>,
+        mut stop_rx: oneshot::Receiver<()>,
+    ) {
         loop {
-            let message = tokio::select! {
+            let (role, message) = tokio::select! {
 
                 biased;
 
@@ -141,63 +261,415 @@ impl MessageInterface {
                 }
             };
 
-            match message.try_into() {
-                Ok(approved) => {
-                    if self.transmitter.send(approved).is_err() {
-                        return;
-                    }
-                }
-                Err(_) => (),
+            // No one may be in a room, which is fine
+            if self.ended.load(Ordering::Acquire) {
+                let _ = players.send((ChatRoom::PostGame, message.clone()));
+                let _ = spectators.send((ChatRoom::PostGame, message));
+            } else {
+                let _ = match role {
+                    ChatRole::Player => players.send((ChatRoom::Players, message)),
+                    ChatRole::Spectator => spectators.send((ChatRoom::Spectators, message)),
+                };
             }
         }
     }
 }
 
-#[derive(Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ChatRoom {
+    Players,
+    Spectators,
+    PostGame,
+}
+
+/// Who a message was sent by, which decides its room while the game is on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChatRole {
+    Player,
+    Spectator,
+}
+
+/// ### A message a user wants to send
+///
+/// Only ever created by the server, which decides who sent it and when
 pub struct ChatMessage {
     sender: UserInfo,
     message: String,
     timestamp: u128,
 }
 
+impl ChatMessage {
+    /// Creates a message sent now, timestamped in milliseconds since the unix epoch
+    pub fn new(sender: UserInfo, message: String) -> Self {
+        Self {
+            sender,
+            message,
+            timestamp: get_timestamp(),
+        }
+    }
+}
+
 impl TryFrom<ChatMessage> for ApprovedChatMessage {
-    type Error = ();
+    type Error = ChatError;
+
+    /// Checks the message against the chat rules, filtering its text
     fn try_from(value: ChatMessage) -> Result<Self, Self::Error> {
-        // TODO: Verify chat messages here
+        let message = ChatRules::get().moderate(&value.message)?;
 
         Ok(Self {
             sender: value.sender,
-            message: value.message,
+            message,
             timestamp: value.timestamp,
         })
     }
 }
 
-#[derive(Clone, Serialize)]
+#[derive(Clone, Debug, Serialize)]
 pub struct ApprovedChatMessage {
     sender: UserInfo,
     message: String,
     timestamp: u128,
 }
 
+/// ### Streams a game to everyone watching it
+///
+/// Keeps a snapshot of the game up to date, so spectators joining midway can catch up before following its events.
+/// Moves are held back from spectators by the game's spectator delay
+#[derive(Debug)]
+pub struct SpectatorInterface {
+    // Held while publishing, so a spectator never misses or repeats an event next to their snapshot
+    state: Mutex<SpectatorState>,
+    transmitter: broadcast::Sender<SpectatorEvent>,
+    count: watch::Sender<usize>,
+
+    delay: SpectatorDelay,
+    /// Wakes the task releasing moves held back by time
+    release: Arc<Notify>,
+
+    messenger: Arc<MessageInterface>,
+}
+
+#[derive(Debug)]
+struct SpectatorState {
+    /// What spectators have been shown so far
+    snapshot: Snapshot,
+    /// Moves played but not yet shown, oldest first
+    held: VecDeque<HeldMove>,
+}
+
+#[derive(Debug)]
+struct HeldMove {
+    valid_move: ValidMove,
+    clock: Option<ClockState>,
+    fen: String,
+    played: Instant,
+}
+
+impl SpectatorInterface {
+    pub fn create(board: &Board, delay: SpectatorDelay, messenger: &Arc<MessageInterface>) -> Arc<Self> {
+        let (transmitter, _) = broadcast::channel(64);
+        let (count, _) = watch::channel(0);
+        let this = Arc::new(Self {
+            state: Mutex::new(SpectatorState {
+                snapshot: Snapshot {
+                    start_fen: board.to_fen(),
+                    fen: board.to_fen(),
+                    moves: vec![],
+                    clock: None,
+                    white: None,
+                    black: None,
+                    result: None,
+                    delay,
+                },
+                held: VecDeque::new(),
+            }),
+            transmitter,
+            count,
+            delay,
+            release: Arc::new(Notify::new()),
+            messenger: Arc::clone(messenger),
+        });
+
+        if let SpectatorDelay::Time(delay) = delay {
+            let release = Arc::clone(&this.release);
+            tokio::task::spawn(SpectatorInterface::release_after(Arc::downgrade(&this), release, delay));
+        }
+
+        this
+    }
+
+    /// Fills in the players once colors are decided, along with their starting clocks
+    pub fn set_players(&self, white: &UserInfo, black: &UserInfo, clock: Option<ClockState>) {
+        let mut state = self.lock();
+        state.snapshot.white = Some(white.clone());
+        state.snapshot.black = Some(black.clone());
+        state.snapshot.clock = clock;
+    }
+
+    /// ### Passes an event on to spectators, once the spectator delay allows
+    ///
+    /// `board` is the game's position after the event. Undoing a move that is still held back just drops it,
+    /// and the end of the game shows everything that was held back
+    pub fn publish(&self, board: &Board, event: SpectatorEvent) {
+        let mut state = self.lock();
+        match event {
+            SpectatorEvent::Move { valid_move, clock } if self.delay != SpectatorDelay::None => {
+                state.held.push_back(HeldMove {
+                    valid_move,
+                    clock,
+                    fen: board.to_fen(),
+                    played: Instant::now(),
+                });
+                if let SpectatorDelay::Moves(plies) = self.delay {
+                    while state.held.len() > plies as usize {
+                        let held = state.held.pop_front().expect("More moves are held than the delay");
+                        self.show(&mut state, held);
+                    }
+                }
+                self.release.notify_one();
+            }
+            SpectatorEvent::MoveWasUndone(_) if !state.held.is_empty() => {
+                state.held.pop_back();
+            }
+            SpectatorEvent::GameEnd { .. } => {
+                while let Some(held) = state.held.pop_front() {
+                    self.show(&mut state, held);
+                }
+                self.send(&mut state, &board.to_fen(), event);
+            }
+            event => self.send(&mut state, &board.to_fen(), event),
+        }
+    }
+
+    /// Shows spectators a move that is no longer held back
+    fn show(&self, state: &mut SpectatorState, held: HeldMove) {
+        let event = SpectatorEvent::Move {
+            valid_move: held.valid_move,
+            clock: held.clock,
+        };
+        self.send(state, &held.fen, event);
+    }
+
+    /// Applies an event to the snapshot, and sends it to every spectator
+    fn send(&self, state: &mut SpectatorState, fen: &str, event: SpectatorEvent) {
+        let snapshot = &mut state.snapshot;
+        snapshot.fen = fen.to_string();
+        match &event {
+            SpectatorEvent::Move { valid_move, clock } => {
+                snapshot.moves.push(valid_move.clone());
+                snapshot.clock = *clock;
+            }
+            SpectatorEvent::MoveWasUndone(_) => {
+                snapshot.moves.pop();
+            }
+            SpectatorEvent::GameEnd { winner, state } => snapshot.result = Some((*winner, *state)),
+            SpectatorEvent::Snapshot(_) | SpectatorEvent::Message { .. } => return,
+        }
+
+        // No one may be watching, which is fine
+        let _ = self.transmitter.send(event);
+    }
+
+    /// ### Shows each held back move once it has been held for the delay
+    ///
+    /// Only holds onto the interface while releasing, and stops once it is dropped
+    async fn release_after(this: Weak<Self>, release: Arc<Notify>, delay: Duration) {
+        loop {
+            let next = match this.upgrade() {
+                Some(this) => this.lock().held.front().map(|held| held.played + delay),
+                None => return,
+            };
+            match next {
+                Some(next) => sleep_until(next).await,
+                None => release.notified().await,
+            }
+
+            let this = match this.upgrade() {
+                Some(this) => this,
+                None => return,
+            };
+            let mut state = this.lock();
+            while state.held.front().is_some_and(|held| held.played.elapsed() >= delay) {
+                let held = state.held.pop_front().expect("A held move was just found");
+                this.show(&mut state, held);
+            }
+        }
+    }
+
+    /// Starts watching the game, which first gives a snapshot of it
+    pub fn watch(self: &Arc<Self>) -> Spectator {
+        let state = self.lock();
+        let events = self.transmitter.subscribe();
+        self.count.send_modify(|count| *count += 1);
+
+        Spectator {
+            pending: Some(state.snapshot.clone()),
+            events,
+            chat: self.messenger.spectators(),
+            interface: Arc::clone(self),
+        }
+    }
+
+    /// How many spectators are watching, which changes as they come and go
+    pub fn count(&self) -> watch::Receiver<usize> {
+        self.count.subscribe()
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        self.lock().snapshot.clone()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, SpectatorState> {
+        self.state.lock().expect("Spectator state should not be poisoned")
+    }
+}
+
+/// ### One spectator's view of a game
+///
+/// Can only follow the game, never act in it. Stops counting as a spectator once dropped
+pub struct Spectator {
+    pending: Option<Snapshot>,
+    events: broadcast::Receiver<SpectatorEvent>,
+    chat: broadcast::Receiver<RoomMessage>,
+    interface: Arc<SpectatorInterface>,
+}
+
+impl Spectator {
+    /// ### Waits on the next thing to show the spectator
+    ///
+    /// A spectator who falls too far behind is given a fresh snapshot instead of the events they missed.
+    /// Chat they fall behind on is skipped. Returns `None` once the chat closes after the game
+    pub async fn recv(&mut self) -> Option<SpectatorEvent> {
+        if let Some(snapshot) = self.pending.take() {
+            return Some(SpectatorEvent::Snapshot(snapshot));
+        }
+
+        loop {
+            tokio::select! {
+                event = self.events.recv() => return match event {
+                    Ok(event) => Some(event),
+                    Err(broadcast::error::RecvError::Lagged(_)) => {
+                        self.events = self.events.resubscribe();
+                        Some(SpectatorEvent::Snapshot(self.interface.snapshot()))
+                    }
+                    Err(broadcast::error::RecvError::Closed) => None,
+                },
+                msg = self.chat.recv() => match msg {
+                    Ok((room, msg)) => return Some(SpectatorEvent::Message { room, msg }),
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                },
+            }
+        }
+    }
+
+    /// The game's chat, where spectators can send messages to the spectators' room
+    pub fn messenger(&self) -> Arc<MessageInterface> {
+        Arc::clone(&self.interface.messenger)
+    }
+}
+
+impl Drop for SpectatorInterface {
+    fn drop(&mut self) {
+        // Lets the release task see the game is gone
+        self.release.notify_one();
+    }
+}
+
+impl Drop for Spectator {
+    fn drop(&mut self) {
+        self.interface
+            .count
+            .send_modify(|count| *count = count.saturating_sub(1));
+    }
+}
+
+/// ### A game as a spectator first sees it
+///
+/// The players are `None` until the game has started
+#[derive(Serialize, Clone, Debug)]
+pub struct Snapshot {
+    pub start_fen: String,
+    pub fen: String,
+    pub moves: Vec<ValidMove>,
+    pub clock: Option<ClockState>,
+    pub white: Option<UserInfo>,
+    pub black: Option<UserInfo>,
+    pub result: Option<(Winner, EndState)>,
+    pub delay: SpectatorDelay,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub enum SpectatorEvent {
+    Snapshot(Snapshot),
+    Move {
+        valid_move: ValidMove,
+        clock: Option<ClockState>,
+    },
+    MoveWasUndone(ValidMove),
+    GameEnd {
+        winner: Winner,
+        state: EndState,
+    },
+    Message {
+        room: ChatRoom,
+        msg: ApprovedChatMessage,
+    },
+}
+
 pub struct ActionInterface {
     reciever_tx: watch::Sender<Option<mpsc::Sender<Action>>>,
+
+    transmitter: mpsc::Sender<Action>,
+    reciever: mpsc::Receiver<Action>,
 }
 
 impl ActionInterface {
-    fn new(reciever_tx: watch::Sender<Option<mpsc::Sender<Action>>>) -> Self {
-        Self { reciever_tx }
+    fn new(
+        reciever_tx: watch::Sender<Option<mpsc::Sender<Action>>>,
+        transmitter: mpsc::Sender<Action>,
+        reciever: mpsc::Receiver<Action>,
+    ) -> Self {
+        Self {
+            reciever_tx,
+            transmitter,
+            reciever,
+        }
     }
     pub fn create() -> (Self, watch::Receiver<Option<mpsc::Sender<Action>>>) {
         let (tx, rx) = watch::channel(None);
-        (Self::new(tx), rx)
+        let (action_tx, action_rx) = mpsc::channel(4);
+        (Self::new(tx, action_tx, action_rx), rx)
+    }
+
+    /// Lets players start submitting actions
+    pub fn open(&self) {
+        self.reciever_tx.send_replace(Some(self.transmitter.clone()));
+    }
+
+    /// Stops accepting actions from players
+    pub fn close(&self) {
+        self.reciever_tx.send_replace(None);
+    }
+
+    pub async fn recv(&mut self) -> Action {
+        self.reciever
+            .recv()
+            .await
+            .expect("ActionInterface always holds a transmitter, so this should never close")
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct Action {
-    sender: UserInfo,
-    kind: ActionType,
+    pub sender: UserInfo,
+    pub kind: ActionType,
+}
+
+impl Action {
+    pub fn new(sender: UserInfo, kind: ActionType) -> Self {
+        Self { sender, kind }
+    }
 }
 
 pub struct EventInterface {
@@ -205,24 +677,41 @@ pub struct EventInterface {
     transmitter: mpsc::Sender<Event>,
 }
 
-#[derive(Serialize)]
+impl EventInterface {
+    async fn send(&self, event: Event) -> Result<(), ()> {
+        self.transmitter.send(event).await.map_err(|_| ())
+    }
+}
+
+#[derive(Serialize, Clone)]
 pub enum Event {
-    OfferDraw,
+    OfferDraw(TeamColor),
 
-    RequestUndo,
+    RequestUndo(TeamColor),
     MoveWasUndone(ValidMove),
 
-    GameEnd(),
+    GameEnd {
+        winner: Winner,
+        state: EndState,
+    },
+
+    Check,
+
+    CanClaimDraw,
+    DrawClaimRejected,
 
     YourTurn,
     YourTurnEnded,
 
-    ValidMove(ValidMove),
+    ValidMove {
+        valid_move: ValidMove,
+        clock: Option<ClockState>,
+    },
 
     Nudge,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ActionType {
     OfferDraw,
     AcceptDraw,
@@ -230,6 +719,8 @@ pub enum ActionType {
     RequestUndo,
     AcceptUndo,
 
+    ClaimDraw,
+
     Resign,
 
     Nudge,
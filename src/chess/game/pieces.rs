@@ -1,35 +1,44 @@
 use std::ops::Add;
-use std::sync::{Arc, Weak};
 
 use serde::{Deserialize, Serialize};
 
+use super::board::{tile::Tile, Board, BoardState};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct Piece {
     color: TeamColor,
     position: Position,
     kind: PieceType,
-    moves: Vec<MoveList>,
 }
 
 impl Piece {
     pub fn new(color: TeamColor, position: Position, kind: PieceType) -> Self {
-        Self {
-            color,
-            position,
-            kind,
-            moves: vec![],
-        }
+        Self { color, position, kind }
     }
 
     // get color name
-    pub fn get_color<'a>(&'a self) -> &'a TeamColor {
+    pub fn get_color(&self) -> &TeamColor {
         &self.color
     }
 
     // get position
-    pub fn get_position<'a>(&'a self) -> &'a Position {
+    pub fn get_position(&self) -> &Position {
         &self.position
     }
 
+    // get piece type
+    pub fn get_kind(&self) -> &PieceType {
+        &self.kind
+    }
+
+    pub fn set_position(&mut self, position: Position) {
+        self.position = position;
+    }
+
+    pub fn set_kind(&mut self, kind: PieceType) {
+        self.kind = kind;
+    }
+
     // get material value
     pub fn get_value(&self) -> i16 {
         match self.kind {
@@ -42,9 +51,15 @@ impl Piece {
         }
     }
 
-    // get moveset for each piece
+    // get moveset for each piece, facing the piece's direction of play
     pub fn get_moveset(&self) -> FullMoveset {
-        self.kind.get_moveset()
+        let mut moveset = self.kind.get_moveset();
+        if self.color == TeamColor::Black {
+            for m in moveset.moves.iter_mut() {
+                m.y_modifier = -m.y_modifier;
+            }
+        }
+        moveset
     }
 
     // piece type functions
@@ -72,61 +87,152 @@ impl Piece {
         self.kind == PieceType::Rook
     }
 
-    // pub fn get_all_moves(&self) -> Vec<Vec<MoveList>> {
-    //     // get movelists for every piece
-    // }
+    /// ### Gets every pseudo-legal move for this piece
+    ///
+    /// Moves are blocked by other pieces and by walls, but may still leave the
+    /// mover's own king in check. Castling is handled by the `Board`, as it
+    /// depends on castling rights and attacked squares.
+    pub fn get_piece_moves(&self, board: &Board) -> Vec<ValidMove> {
+        if self.is_pawn() {
+            return self.get_pawn_moves(board);
+        }
 
-    pub fn get_piece_moves(self) -> Vec<MoveList> {
-        // get movelists for specific piece
         let moveset = self.get_moveset();
-        let all_moves: Vec<MoveList> = Vec::new();
-        let piece_position: &Position = self.get_position();
-
-            for moveset_list in moveset.moves {
-                let all_moves_len = all_moves.len();
-                let mut new_head: MoveLink;
-
-
-                loop {
-                    let new_position = self.position.add(moveset_list.clone());
-                    match new_position {
-                        None => break,
-                        Some(pos) => {
-                            if pos.x > 8 || pos.y > 8{
-                                break;
-                            }
-
-                            // head
-                            if all_moves.len() == all_moves_len {
-                               new_head = MoveLink {
-                                    pos: pos,
-                                    next: Arc::new(None),
-                                    prev: Weak::new(),
-                                }
-                            }
+        let mut all_moves = Vec::new();
+
+        for modifier in moveset.moves {
+            let mut current = self.position.clone();
+
+            while let Some(target) = &current + modifier.clone() {
+                match board.get_tile(&target) {
+                    Tile::Wall => break,
+                    Tile::Empty => all_moves.push(ValidMove::new(board, self.position.clone(), target.clone())),
+                    Tile::Piece { piece } => {
+                        if piece.color != self.color {
+                            all_moves.push(ValidMove::new(board, self.position.clone(), target.clone()));
                         }
-                    }
-                    
-                    if moveset.iterative == false {
                         break;
+                    }
+                }
+
+                if !moveset.iterative {
+                    break;
+                }
+                current = target;
+            }
+        }
+
+        all_moves
+    }
+
+    fn get_pawn_moves(&self, board: &Board) -> Vec<ValidMove> {
+        let mut all_moves = Vec::new();
+        let forward = self.get_moveset().moves[0].y_modifier;
+
+        // Pushes
+        if let Some(one) = &self.position + Moveset::new(0, forward) {
+            if let Tile::Empty = board.get_tile(&one) {
+                self.push_pawn_move(
+                    board,
+                    &mut all_moves,
+                    ValidMove::new(board, self.position.clone(), one.clone()),
+                );
+
+                if self.position.y == board.pawn_start_rank(&self.color) {
+                    if let Some(two) = &one + Moveset::new(0, forward) {
+                        if let Tile::Empty = board.get_tile(&two) {
+                            all_moves.push(ValidMove {
+                                kind: MoveKind::DoublePush,
+                                ..ValidMove::new(board, self.position.clone(), two)
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        // Captures
+        for side in [-1, 1] {
+            let target = match &self.position + Moveset::new(side, forward) {
+                Some(pos) => pos,
+                None => continue,
+            };
+
+            match board.get_tile(&target) {
+                Tile::Piece { piece } if piece.color != self.color => self.push_pawn_move(
+                    board,
+                    &mut all_moves,
+                    ValidMove::new(board, self.position.clone(), target),
+                ),
+                Tile::Empty if board.en_passant.as_ref() == Some(&target) => {
+                    let captured = Position {
+                        x: target.x,
+                        y: self.position.y,
                     };
+                    all_moves.push(ValidMove {
+                        captured: match board.get_tile(&captured) {
+                            Tile::Piece { piece } => Some(piece.clone()),
+                            _ => None,
+                        },
+                        kind: MoveKind::EnPassant { captured },
+                        ..ValidMove::new(board, self.position.clone(), target)
+                    });
                 }
+                _ => (),
             }
+        }
+
         all_moves
     }
 
-    pub fn get_valid_moves(self) {
-        // validate all piece moves
-        //    let moves = self.get_all_moves();
+    /// Pushes a pawn move, expanding it into every promotion choice if it reaches the last rank
+    fn push_pawn_move(&self, board: &Board, all_moves: &mut Vec<ValidMove>, valid_move: ValidMove) {
+        if valid_move.target.y != board.promotion_rank(&self.color) {
+            return all_moves.push(valid_move);
+        }
+
+        for kind in PieceType::PROMOTIONS {
+            all_moves.push(ValidMove {
+                promotion: Some(kind),
+                ..valid_move.clone()
+            });
+        }
     }
 
-    pub fn can_move(&self, id: i16) -> bool {
-        // check if tile is one of the possible moves in Piece.get_valid_moves
+    /// ### Checks if this piece attacks the given position
+    ///
+    /// Unlike `get_piece_moves`, this does not care about what is on the target
+    /// tile, so it can be used to test empty squares for castling
+    pub fn attacks(&self, board: &Board, target: &Position) -> bool {
+        if self.is_pawn() {
+            let forward = self.get_moveset().moves[0].y_modifier;
+            return [-1, 1]
+                .into_iter()
+                .filter_map(|side| &self.position + Moveset::new(side, forward))
+                .any(|pos| &pos == target);
+        }
+
+        let moveset = self.get_moveset();
+        for modifier in moveset.moves {
+            let mut current = self.position.clone();
+
+            while let Some(next) = &current + modifier.clone() {
+                if &next == target {
+                    return true;
+                }
+
+                match board.get_tile(&next) {
+                    Tile::Empty if moveset.iterative => current = next,
+                    _ => break,
+                }
+            }
+        }
+
         false
     }
 }
 
-#[derive(PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum PieceType {
     King,
     Queen,
@@ -137,9 +243,36 @@ pub enum PieceType {
 }
 
 impl PieceType {
+    /// Every piece a pawn may promote into
+    pub const PROMOTIONS: [PieceType; 4] = [PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight];
+
+    /// The lowercase letter naming this piece, as used in FEN
+    pub fn to_char(&self) -> char {
+        match self {
+            PieceType::King => 'k',
+            PieceType::Queen => 'q',
+            PieceType::Pawn => 'p',
+            PieceType::Bishop => 'b',
+            PieceType::Knight => 'n',
+            PieceType::Rook => 'r',
+        }
+    }
+
+    pub fn from_char(char: char) -> Option<Self> {
+        match char.to_ascii_lowercase() {
+            'k' => Some(PieceType::King),
+            'q' => Some(PieceType::Queen),
+            'p' => Some(PieceType::Pawn),
+            'b' => Some(PieceType::Bishop),
+            'n' => Some(PieceType::Knight),
+            'r' => Some(PieceType::Rook),
+            _ => None,
+        }
+    }
+
     pub fn get_moveset(&self) -> FullMoveset {
         match self {
-            PieceType::King { .. } => FullMoveset {
+            PieceType::King => FullMoveset {
                 moves: vec![
                     Moveset {
                         x_modifier: -1,
@@ -176,7 +309,7 @@ impl PieceType {
                 ],
                 iterative: false,
             },
-            PieceType::Queen { .. } => FullMoveset {
+            PieceType::Queen => FullMoveset {
                 moves: vec![
                     Moveset {
                         x_modifier: -1,
@@ -213,14 +346,14 @@ impl PieceType {
                 ],
                 iterative: true,
             },
-            PieceType::Pawn { .. } => FullMoveset {
+            PieceType::Pawn => FullMoveset {
                 moves: vec![Moveset {
                     x_modifier: 0,
                     y_modifier: -1,
                 }],
                 iterative: false,
             },
-            PieceType::Bishop { .. } => FullMoveset {
+            PieceType::Bishop => FullMoveset {
                 moves: vec![
                     Moveset {
                         x_modifier: -1,
@@ -241,7 +374,7 @@ impl PieceType {
                 ],
                 iterative: true,
             },
-            PieceType::Knight { .. } => FullMoveset {
+            PieceType::Knight => FullMoveset {
                 moves: vec![
                     Moveset {
                         x_modifier: 2,
@@ -278,7 +411,7 @@ impl PieceType {
                 ],
                 iterative: false,
             },
-            PieceType::Rook { .. } => FullMoveset {
+            PieceType::Rook => FullMoveset {
                 moves: vec![
                     Moveset {
                         x_modifier: -1,
@@ -301,42 +434,40 @@ impl PieceType {
             },
         }
     }
-
-    pub fn get_valid_moves(&self) {}
-}
-
-struct MoveList {
-    head: Arc<Option<MoveLink>>,
-    tail: Arc<Option<MoveLink>>,
-}
-
-struct MoveLink {
-    pos: Position,
-    next: Arc<Option<MoveLink>>,
-    prev: Weak<Option<MoveLink>>,
 }
 
-struct FullMoveset {
-    moves: Vec<Moveset>,
-    iterative: bool,
+pub struct FullMoveset {
+    pub moves: Vec<Moveset>,
+    pub iterative: bool,
 }
 
 #[derive(Clone)]
-struct Moveset {
-    x_modifier: i16,
-    y_modifier: i16,
+pub struct Moveset {
+    pub x_modifier: i16,
+    pub y_modifier: i16,
 }
 
-enum Progress<T> {
-    Valid(T),
-    Invalid(T),
+impl Moveset {
+    pub fn new(x_modifier: i16, y_modifier: i16) -> Self {
+        Self { x_modifier, y_modifier }
+    }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum TeamColor {
     White,
     Black,
 }
 
+impl TeamColor {
+    pub fn opposite(&self) -> Self {
+        match self {
+            Self::White => Self::Black,
+            Self::Black => Self::White,
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
 pub struct Position {
     pub x: u16,
@@ -344,8 +475,34 @@ pub struct Position {
 }
 
 impl Position {
+    /// ### Converts this position into a square name, such as `e4`
+    ///
+    /// Files are lettered from the left, and ranks are numbered from the bottom
+    /// of a board with the given height
+    pub fn to_square(&self, height: u8) -> String {
+        let file = (b'a' + self.x as u8) as char;
+        let rank = height as u16 - self.y;
+        format!("{file}{rank}")
+    }
+
+    /// ### Parses a square name, such as `e4`, on a board with the given height
+    pub fn from_square(square: &str, height: u8) -> Option<Self> {
+        let mut chars = square.chars();
+        let file = chars.next().filter(|c| c.is_ascii_lowercase())?;
+        let rank: u16 = chars.as_str().parse().ok()?;
+
+        if rank == 0 || rank > height as u16 {
+            return None;
+        }
+
+        Some(Self {
+            x: file as u16 - 'a' as u16,
+            y: height as u16 - rank,
+        })
+    }
+
     pub fn add_i16_to_u16(u16: u16, i16: i16) -> Option<u16> {
-        let result = u16 as i16 + i16 as i16;
+        let result = u16 as i16 + i16;
         if result < 0 {
             None
         } else {
@@ -371,18 +528,94 @@ impl Add<Moveset> for &Position {
 pub struct Move {
     pub source: Position,
     pub target: Position,
-    // More
+    #[serde(default)]
+    pub promotion: Option<PieceType>,
+}
+
+/// ### A move as sent by a player
+///
+/// Either raw coordinates, or a string in UCI or Standard Algebraic Notation
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum Turn {
+    Coordinates(Move),
+    Notation(String),
 }
 
-impl From<Move> for ValidMove {
+impl From<Move> for Turn {
     fn from(value: Move) -> Self {
-        let Move { source, target } = value;
-        Self { source, target }
+        Self::Coordinates(value)
     }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct ValidMove {
-    source: Position,
-    target: Position,
+    pub source: Position,
+    pub target: Position,
+    pub kind: MoveKind,
+    pub promotion: Option<PieceType>,
+    pub captured: Option<Piece>,
+
+    /// State of the board before this move, used to undo it
+    #[serde(skip)]
+    pub previous: BoardState,
+}
+
+impl ValidMove {
+    pub(super) fn new(board: &Board, source: Position, target: Position) -> Self {
+        let captured = match board.get_tile(&target) {
+            Tile::Piece { piece } => Some(piece.clone()),
+            _ => None,
+        };
+        Self {
+            source,
+            target,
+            kind: MoveKind::Standard,
+            promotion: None,
+            captured,
+            previous: board.state(),
+        }
+    }
+
+    pub fn castle(
+        board: &Board,
+        source: Position,
+        target: Position,
+        rook_source: Position,
+        rook_target: Position,
+    ) -> Self {
+        Self {
+            kind: MoveKind::Castle {
+                rook_source,
+                rook_target,
+            },
+            ..Self::new(board, source, target)
+        }
+    }
+
+    /// ### Names the move by its squares, such as `e2e4`, or `e7e8q` for a promotion
+    pub fn to_coordinates(&self, height: u8) -> String {
+        let promotion = self
+            .promotion
+            .map(|kind| kind.to_char().to_string())
+            .unwrap_or_default();
+        format!(
+            "{}{}{promotion}",
+            self.source.to_square(height),
+            self.target.to_square(height)
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum MoveKind {
+    Standard,
+    DoublePush,
+    EnPassant {
+        captured: Position,
+    },
+    Castle {
+        rook_source: Position,
+        rook_target: Position,
+    },
 }
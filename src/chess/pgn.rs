@@ -0,0 +1,517 @@
+use std::{error::Error, fmt::Display, time::Duration};
+
+use super::game::{
+    board::{Board, FenError, STARTING_FEN},
+    pieces::{TeamColor, ValidMove},
+    EndState, TimeConfig, Winner,
+};
+use crate::server::{database::games::GameRecord, user::UserInfo};
+
+/// Movetext lines are wrapped to this width, as in the PGN export format
+const LINE_WIDTH: usize = 80;
+
+/// ### A game in Portable Game Notation
+///
+/// Holds the tag pairs in order, the starting position, and every move played from it
+pub struct Pgn {
+    pub tags: Vec<(String, String)>,
+    pub start: Board,
+    pub moves: Vec<ValidMove>,
+    /// The mover's remaining time after each move, if known
+    pub clocks: Vec<Option<Duration>>,
+    /// `None` if the game did not finish
+    pub result: Option<Winner>,
+}
+
+impl Pgn {
+    /// ### Describes a finished game
+    ///
+    /// Fills in the Seven Tag Roster, and adds `SetUp` and `FEN` tags when the game did not
+    /// start from the standard position
+    pub fn new(
+        white: &UserInfo,
+        black: &UserInfo,
+        winner: Winner,
+        end_state: EndState,
+        time: TimeConfig,
+        start: Board,
+        moves: Vec<ValidMove>,
+    ) -> Self {
+        let mut tags = vec![
+            ("Event".to_string(), "Chesstacean game".to_string()),
+            ("Site".to_string(), "Chesstacean".to_string()),
+            ("Date".to_string(), "????.??.??".to_string()),
+            ("Round".to_string(), "-".to_string()),
+            ("White".to_string(), white.get_display()),
+            ("Black".to_string(), black.get_display()),
+            ("Result".to_string(), result_token(Some(winner)).to_string()),
+        ];
+
+        let fen = start.to_fen();
+        if fen != STARTING_FEN {
+            tags.push(("SetUp".to_string(), "1".to_string()));
+            tags.push(("FEN".to_string(), fen));
+        }
+
+        tags.push(("TimeControl".to_string(), time.to_string()));
+        tags.push((
+            "Termination".to_string(),
+            match end_state {
+                EndState::Timeout => "time forfeit".to_string(),
+                EndState::Abandoned => "abandoned".to_string(),
+                _ => "normal".to_string(),
+            },
+        ));
+
+        let clocks = vec![None; moves.len()];
+        Self {
+            tags,
+            start,
+            moves,
+            clocks,
+            result: Some(winner),
+        }
+    }
+
+    /// Sets the `Date` tag from a time in milliseconds since the unix epoch
+    pub fn with_date(mut self, timestamp: u128) -> Self {
+        self.set_tag("Date", format_date(timestamp));
+        self
+    }
+
+    /// Adds each mover's remaining time, written as `%clk` comments after their move
+    pub fn with_clocks(mut self, clocks: Vec<Duration>) -> Self {
+        self.clocks = clocks.into_iter().map(Some).collect();
+        self.clocks.resize(self.moves.len(), None);
+        self
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&String> {
+        self.tags.iter().find(|(tag, _)| tag == name).map(|(_, value)| value)
+    }
+
+    pub fn set_tag(&mut self, name: &str, value: String) {
+        match self.tags.iter_mut().find(|(tag, _)| tag == name) {
+            Some((_, existing)) => *existing = value,
+            None => self.tags.push((name.to_string(), value)),
+        }
+    }
+
+    /// ### Reads a single game
+    ///
+    /// Comments, variations and annotation glyphs are skipped, except for `%clk` times
+    pub fn parse(text: &str) -> Result<Self, PgnError> {
+        let mut tags = vec![];
+        let mut movetext = String::new();
+        for line in text.lines().map(str::trim) {
+            if line.starts_with('[') && movetext.trim().is_empty() {
+                tags.push(parse_tag(line)?);
+            } else if !line.starts_with('%') {
+                movetext.push_str(line);
+                movetext.push('\n');
+            }
+        }
+
+        let start = match tags.iter().find(|(tag, _)| tag == "FEN") {
+            Some((_, fen)) => Board::from_fen(fen)?,
+            None => Board::default(),
+        };
+
+        let mut board = start.clone();
+        let mut moves = vec![];
+        let mut clocks = vec![];
+        let mut result = None;
+
+        let mut chars = movetext.chars().peekable();
+        while let Some(&char) = chars.peek() {
+            match char {
+                '{' => {
+                    chars.next();
+                    let comment: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                    if let (Some(clock), Some(last)) = (parse_clock(&comment), clocks.last_mut()) {
+                        *last = Some(clock);
+                    }
+                }
+                ';' => {
+                    chars.by_ref().take_while(|&c| c != '\n').for_each(drop);
+                }
+                '(' => {
+                    let mut depth = 0;
+                    for c in chars.by_ref() {
+                        match c {
+                            '(' => depth += 1,
+                            ')' => depth -= 1,
+                            _ => (),
+                        }
+                        if depth == 0 {
+                            break;
+                        }
+                    }
+                }
+                c if c.is_whitespace() => {
+                    chars.next();
+                }
+                _ => {
+                    let mut token = String::new();
+                    while let Some(&c) = chars.peek() {
+                        if c.is_whitespace() || "{;(".contains(c) {
+                            break;
+                        }
+                        token.push(c);
+                        chars.next();
+                    }
+
+                    if let Some(winner) = parse_result(&token) {
+                        result = winner;
+                        break;
+                    }
+
+                    // Move numbers may be attached to their move, as in `1.e4`
+                    let token = token.trim_start_matches(|c: char| c.is_ascii_digit() || c == '.');
+                    if token.is_empty() || token.starts_with('$') {
+                        continue;
+                    }
+
+                    let valid_move = board.from_san(token).ok_or_else(|| PgnError::IllegalMove {
+                        ply: moves.len() + 1,
+                        san: token.to_string(),
+                    })?;
+                    board.make_move(&valid_move);
+                    moves.push(valid_move);
+                    clocks.push(None);
+                }
+            }
+        }
+
+        Ok(Self {
+            tags,
+            start,
+            moves,
+            clocks,
+            result,
+        })
+    }
+
+    /// The position after every move has been played
+    pub fn final_board(&self) -> Board {
+        let mut board = self.start.clone();
+        for valid_move in self.moves.iter() {
+            board.make_move(valid_move);
+        }
+        board
+    }
+}
+
+impl TryFrom<&GameRecord> for Pgn {
+    type Error = PgnError;
+
+    /// Replays a stored game, rebuilding each mover's clock from the time of their move
+    fn try_from(record: &GameRecord) -> Result<Self, Self::Error> {
+        let start = Board::new(&record.start_fen, record.height, record.width)?;
+
+        let mut board = start.clone();
+        let mut moves = vec![];
+        for (ply, coordinates) in record.moves.iter().enumerate() {
+            let valid_move = board
+                .legal_moves()
+                .into_iter()
+                .find(|vm| &vm.to_coordinates(record.height) == coordinates)
+                .ok_or_else(|| PgnError::IllegalMove {
+                    ply: ply + 1,
+                    san: coordinates.clone(),
+                })?;
+            board.make_move(&valid_move);
+            moves.push(valid_move);
+        }
+
+        let pgn = Self::new(
+            &record.white,
+            &record.black,
+            record.winner,
+            record.end_state,
+            record.time,
+            start,
+            moves,
+        )
+        .with_date(record.started);
+
+        let (limit, added) = match record.time {
+            TimeConfig::Timed { limit, added } => (limit, added),
+            TimeConfig::NotTimed => return Ok(pgn),
+        };
+
+        let mut remaining = [limit; 2];
+        let mut previous = record.started;
+        let mut clocks = vec![];
+        for (ply, &time) in record.move_times.iter().enumerate() {
+            let elapsed = Duration::from_millis(time.saturating_sub(previous) as u64);
+            let clock = &mut remaining[ply % 2];
+            *clock = clock.saturating_sub(elapsed) + added;
+            clocks.push(*clock);
+            previous = time;
+        }
+
+        Ok(pgn.with_clocks(clocks))
+    }
+}
+
+impl Display for Pgn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (tag, value) in self.tags.iter() {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+            writeln!(f, "[{tag} \"{value}\"]")?;
+        }
+        writeln!(f)?;
+
+        let mut tokens = vec![];
+        let mut board = self.start.clone();
+        for (i, valid_move) in self.moves.iter().enumerate() {
+            match board.turn {
+                TeamColor::White => tokens.push(format!("{}.", board.fullmove_number)),
+                TeamColor::Black if i == 0 => tokens.push(format!("{}...", board.fullmove_number)),
+                TeamColor::Black => (),
+            }
+            tokens.push(board.to_san(valid_move));
+            if let Some(Some(clock)) = self.clocks.get(i) {
+                tokens.push(format!("{{[%clk {}]}}", format_clock(*clock)));
+            }
+            board.make_move(valid_move);
+        }
+        tokens.push(result_token(self.result).to_string());
+
+        let mut line = String::new();
+        for token in tokens {
+            if !line.is_empty() && line.len() + token.len() + 1 > LINE_WIDTH {
+                writeln!(f, "{line}")?;
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&token);
+        }
+        writeln!(f, "{line}")
+    }
+}
+
+fn parse_tag(line: &str) -> Result<(String, String), PgnError> {
+    let invalid = || PgnError::InvalidTag(line.to_string());
+
+    let inner = line
+        .strip_prefix('[')
+        .and_then(|l| l.strip_suffix(']'))
+        .ok_or_else(invalid)?;
+    let (name, value) = inner.split_once(char::is_whitespace).ok_or_else(invalid)?;
+    let value = value
+        .trim()
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .ok_or_else(invalid)?;
+
+    Ok((name.to_string(), value.replace("\\\"", "\"").replace("\\\\", "\\")))
+}
+
+/// Reads a game termination marker, where `Some(None)` is an unfinished game
+fn parse_result(token: &str) -> Option<Option<Winner>> {
+    match token {
+        "1-0" => Some(Some(Winner::White)),
+        "0-1" => Some(Some(Winner::Black)),
+        "1/2-1/2" => Some(Some(Winner::None)),
+        "*" => Some(None),
+        _ => None,
+    }
+}
+
+fn result_token(result: Option<Winner>) -> &'static str {
+    match result {
+        Some(Winner::White) => "1-0",
+        Some(Winner::Black) => "0-1",
+        Some(Winner::None) => "1/2-1/2",
+        None => "*",
+    }
+}
+
+/// Writes a duration as `h:mm:ss`
+fn format_clock(clock: Duration) -> String {
+    let seconds = clock.as_secs();
+    format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+/// Finds a `[%clk h:mm:ss]` command in a comment
+fn parse_clock(comment: &str) -> Option<Duration> {
+    let start = comment.find("[%clk")? + "[%clk".len();
+    let end = start + comment[start..].find(']')?;
+
+    let mut seconds = 0.0;
+    for part in comment[start..end].trim().split(':') {
+        seconds = seconds * 60.0 + part.parse::<f64>().ok()?;
+    }
+    Some(Duration::from_secs_f64(seconds))
+}
+
+/// Writes a unix timestamp in milliseconds as a PGN date, such as `2024.03.09`
+fn format_date(timestamp: u128) -> String {
+    // Converts days since the epoch to a civil date, from Howard Hinnant's `civil_from_days`
+    let days = (timestamp / 86_400_000) as i64 + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+    format!("{year:04}.{month:02}.{day:02}")
+}
+
+#[derive(Debug)]
+pub enum PgnError {
+    InvalidTag(String),
+    InvalidFen(FenError),
+    IllegalMove { ply: usize, san: String },
+}
+
+impl Display for PgnError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidTag(line) => write!(f, "InvalidTag: Could not read the tag pair {line}"),
+            Self::InvalidFen(e) => write!(f, "InvalidFen: {e}"),
+            Self::IllegalMove { ply, san } => write!(f, "IllegalMove: Move {ply} ({san}) is not legal"),
+        }
+    }
+}
+
+impl Error for PgnError {}
+
+impl From<FenError> for PgnError {
+    fn from(value: FenError) -> Self {
+        Self::InvalidFen(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPERA_GAME: &str = r#"[Event "Paris"]
+[Site "Paris FRA"]
+[Date "1858.??.??"]
+[Round "?"]
+[White "Paul Morphy"]
+[Black "Duke Karl / Count Isouard"]
+[Result "1-0"]
+
+1. e4 e5 2. Nf3 d6 3. d4 Bg4 {This is a weak move already.} 4. dxe5 Bxf3 5. Qxf3 dxe5
+6. Bc4 Nf6 7. Qb3 Qe7 8. Nc3 c6 9. Bg5 b5 10. Nxb5 cxb5 11. Bxb5+ Nbd7 12. O-O-O Rd8
+13. Rxd7 Rxd7 14. Rd1 Qe6 (14... Qb4 15. Qxb4) 15. Bxd7+ Nxd7 16. Qb8+ Nxb8 17. Rd8# 1-0
+"#;
+
+    #[test]
+    fn parses_and_rewrites_games() {
+        let pgn = Pgn::parse(OPERA_GAME).unwrap();
+
+        assert_eq!(pgn.moves.len(), 33);
+        assert_eq!(pgn.result, Some(Winner::White));
+        assert_eq!(pgn.tag("White").unwrap(), "Paul Morphy");
+
+        let board = pgn.final_board();
+        assert!(board.is_in_check(&board.turn) && board.legal_moves().is_empty());
+
+        let written = pgn.to_string();
+        assert!(written.contains("11. Bxb5+ Nbd7 12. O-O-O Rd8"));
+        assert!(written.contains("17. Rd8# 1-0"));
+        assert!(written.lines().all(|line| line.len() <= LINE_WIDTH));
+
+        let reread = Pgn::parse(&written).unwrap();
+        assert_eq!(reread.moves, pgn.moves);
+    }
+
+    #[test]
+    fn exports_custom_positions_and_clocks() {
+        let start = Board::new("4k3/P7/8/8/8/8/8/4K3 w - - 0 40", 8, 8).unwrap();
+        let promotion = start.from_san("a8=Q+").unwrap();
+        let pgn = Pgn::new(
+            &UserInfo::new_user("white", "White"),
+            &UserInfo::new_guest(),
+            Winner::None,
+            EndState::Agreement,
+            TimeConfig::Timed {
+                limit: Duration::from_secs(180),
+                added: Duration::from_secs(2),
+            },
+            start,
+            vec![promotion],
+        )
+        .with_date(1_700_000_000_000)
+        .with_clocks(vec![Duration::from_secs(75)]);
+
+        let written = pgn.to_string();
+        assert!(written.contains("[Date \"2023.11.14\"]"));
+        assert!(written.contains("[SetUp \"1\"]\n[FEN \"4k3/P7/8/8/8/8/8/4K3 w - - 0 40\"]"));
+        assert!(written.contains("[TimeControl \"180+2\"]"));
+        assert!(written.contains("40. a8=Q+ {[%clk 0:01:15]} 1/2-1/2"));
+
+        let reread = Pgn::parse(&written).unwrap();
+        assert_eq!(reread.start.to_fen(), "4k3/P7/8/8/8/8/8/4K3 w - - 0 40");
+        assert_eq!(reread.clocks, vec![Some(Duration::from_secs(75))]);
+        assert_eq!(reread.result, Some(Winner::None));
+    }
+
+    #[test]
+    fn rereads_smaller_boards() {
+        let start = Board::new("k5/6/6/6/6/5K w - - 0 1", 6, 6).unwrap();
+        let king = start.from_san("Ke2").unwrap();
+        let pgn = Pgn::new(
+            &UserInfo::new_guest(),
+            &UserInfo::new_guest(),
+            Winner::None,
+            EndState::Agreement,
+            TimeConfig::NotTimed,
+            start,
+            vec![king],
+        );
+
+        let reread = Pgn::parse(&pgn.to_string()).unwrap();
+        assert_eq!((reread.start.height, reread.start.width), (6, 6));
+        assert_eq!(reread.moves, pgn.moves);
+    }
+
+    #[test]
+    fn converts_recorded_games() {
+        let record = GameRecord {
+            code: "abc".to_string(),
+            white: UserInfo::new_user("white", "White"),
+            black: UserInfo::new_user("black", "Black"),
+            winner: Winner::Black,
+            end_state: EndState::Checkmate,
+            time: TimeConfig::Timed {
+                limit: Duration::from_secs(60),
+                added: Duration::from_secs(1),
+            },
+            start_fen: STARTING_FEN.to_string(),
+            width: 8,
+            height: 8,
+            moves: ["f2f3", "e7e5", "g2g4", "d8h4"].map(str::to_string).to_vec(),
+            move_times: vec![2_000, 5_000, 15_000, 16_000],
+            started: 0,
+            ended: 16_000,
+        };
+
+        let written = Pgn::try_from(&record).unwrap().to_string();
+        assert!(written.contains("[Date \"1970.01.01\"]"));
+        assert!(written.contains("1. f3 {[%clk 0:00:59]} e5 {[%clk 0:00:58]} 2. g4 {[%clk 0:00:50]} Qh4#"));
+        assert!(written.ends_with("{[%clk 0:00:58]} 0-1\n"));
+        assert!(!written.contains("FEN"));
+    }
+
+    #[test]
+    fn rejects_illegal_moves() {
+        let result = Pgn::parse("1. e4 e5 2. Ke3 *");
+        assert!(matches!(result, Err(PgnError::IllegalMove { ply: 3, .. })));
+    }
+}
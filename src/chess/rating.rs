@@ -0,0 +1,225 @@
+use std::{f64::consts::PI, fmt::Display, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use super::game::TimeConfig;
+
+/// Converts between the Glicko and Glicko-2 scales
+const SCALE: f64 = 173.7178;
+
+/// Constrains how quickly volatility can change
+const TAU: f64 = 0.5;
+
+/// Convergence tolerance when solving for the new volatility
+const EPSILON: f64 = 0.000001;
+
+const DEFAULT_RATING: f64 = 1500.0;
+const DEFAULT_DEVIATION: f64 = 350.0;
+const DEFAULT_VOLATILITY: f64 = 0.06;
+
+/// ### A Glicko-2 rating
+///
+/// Stored on the original Glicko scale, so ratings read like Elo
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Rating {
+    pub rating: f64,
+    pub deviation: f64,
+    pub volatility: f64,
+}
+
+impl Default for Rating {
+    fn default() -> Self {
+        Self {
+            rating: DEFAULT_RATING,
+            deviation: DEFAULT_DEVIATION,
+            volatility: DEFAULT_VOLATILITY,
+        }
+    }
+}
+
+impl Rating {
+    /// ### Computes the rating after a rating period
+    ///
+    /// Each result pairs an opponent's rating with the score against them,
+    /// 1.0 for a win, 0.5 for a draw, and 0.0 for a loss
+    pub fn update(&self, results: &[(Rating, f64)]) -> Rating {
+        let mu = (self.rating - DEFAULT_RATING) / SCALE;
+        let phi = self.deviation / SCALE;
+
+        if results.is_empty() {
+            let phi = (phi.powi(2) + self.volatility.powi(2)).sqrt();
+            return Rating {
+                deviation: (phi * SCALE).min(DEFAULT_DEVIATION),
+                ..*self
+            };
+        }
+
+        let mut inverse_variance = 0.0;
+        let mut improvement = 0.0;
+        for (opponent, score) in results {
+            let opponent_mu = (opponent.rating - DEFAULT_RATING) / SCALE;
+            let g = g(opponent.deviation / SCALE);
+            let expected = 1.0 / (1.0 + (-g * (mu - opponent_mu)).exp());
+
+            inverse_variance += g.powi(2) * expected * (1.0 - expected);
+            improvement += g * (score - expected);
+        }
+        let variance = 1.0 / inverse_variance;
+        let delta = variance * improvement;
+
+        let volatility = self.new_volatility(phi, variance, delta);
+
+        let phi_star = (phi.powi(2) + volatility.powi(2)).sqrt();
+        let new_phi = 1.0 / (1.0 / phi_star.powi(2) + 1.0 / variance).sqrt();
+        let new_mu = mu + new_phi.powi(2) * improvement;
+
+        Rating {
+            rating: new_mu * SCALE + DEFAULT_RATING,
+            deviation: (new_phi * SCALE).min(DEFAULT_DEVIATION),
+            volatility,
+        }
+    }
+
+    /// Solves for the new volatility with the Illinois algorithm
+    fn new_volatility(&self, phi: f64, variance: f64, delta: f64) -> f64 {
+        let a = self.volatility.powi(2).ln();
+        let f = |x: f64| {
+            let ex = x.exp();
+            let denominator = phi.powi(2) + variance + ex;
+            (ex * (delta.powi(2) - phi.powi(2) - variance - ex)) / (2.0 * denominator.powi(2)) - (x - a) / TAU.powi(2)
+        };
+
+        let mut upper = a;
+        let mut lower = if delta.powi(2) > phi.powi(2) + variance {
+            (delta.powi(2) - phi.powi(2) - variance).ln()
+        } else {
+            let mut k = 1.0;
+            while f(a - k * TAU) < 0.0 {
+                k += 1.0;
+            }
+            a - k * TAU
+        };
+
+        let mut f_upper = f(upper);
+        let mut f_lower = f(lower);
+        while (lower - upper).abs() > EPSILON {
+            let next = upper + (upper - lower) * f_upper / (f_lower - f_upper);
+            let f_next = f(next);
+            if f_next * f_lower <= 0.0 {
+                upper = lower;
+                f_upper = f_lower;
+            } else {
+                f_upper /= 2.0;
+            }
+            lower = next;
+            f_lower = f_next;
+        }
+
+        (upper / 2.0).exp()
+    }
+}
+
+fn g(phi: f64) -> f64 {
+    1.0 / (1.0 + 3.0 * phi.powi(2) / PI.powi(2)).sqrt()
+}
+
+/// ### The time controls that are rated separately
+///
+/// Categorized by the expected length of a game, assuming 40 moves per player
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum TimeControl {
+    Bullet,
+    Blitz,
+    Rapid,
+    Classical,
+    Unlimited,
+}
+
+impl From<&TimeConfig> for TimeControl {
+    fn from(value: &TimeConfig) -> Self {
+        match value {
+            TimeConfig::NotTimed => Self::Unlimited,
+            TimeConfig::Timed { limit, added } => match (*limit + *added * 40).as_secs() {
+                0..=179 => Self::Bullet,
+                180..=479 => Self::Blitz,
+                480..=1499 => Self::Rapid,
+                _ => Self::Classical,
+            },
+        }
+    }
+}
+
+impl Display for TimeControl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Bullet => "bullet",
+                Self::Blitz => "blitz",
+                Self::Rapid => "rapid",
+                Self::Classical => "classical",
+                Self::Unlimited => "unlimited",
+            }
+        )
+    }
+}
+
+impl FromStr for TimeControl {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bullet" => Ok(Self::Bullet),
+            "blitz" => Ok(Self::Blitz),
+            "rapid" => Ok(Self::Rapid),
+            "classical" => Ok(Self::Classical),
+            "unlimited" => Ok(Self::Unlimited),
+            _ => Err(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn matches_glickman_example() {
+        // The worked example from Glickman's "Example of the Glicko-2 system"
+        let player = Rating {
+            rating: 1500.0,
+            deviation: 200.0,
+            volatility: 0.06,
+        };
+        let opponent = |rating, deviation| Rating {
+            rating,
+            deviation,
+            volatility: 0.06,
+        };
+        let updated = player.update(&[
+            (opponent(1400.0, 30.0), 1.0),
+            (opponent(1550.0, 100.0), 0.0),
+            (opponent(1700.0, 300.0), 0.0),
+        ]);
+
+        assert!((updated.rating - 1464.06).abs() < 0.01);
+        assert!((updated.deviation - 151.52).abs() < 0.01);
+        assert!((updated.volatility - 0.05999).abs() < 0.00001);
+    }
+
+    #[test]
+    fn categorizes_time_controls() {
+        let timed = |limit, added| TimeConfig::Timed {
+            limit: Duration::from_secs(limit),
+            added: Duration::from_secs(added),
+        };
+
+        assert_eq!(TimeControl::from(&timed(60, 0)), TimeControl::Bullet);
+        assert_eq!(TimeControl::from(&timed(180, 2)), TimeControl::Blitz);
+        assert_eq!(TimeControl::from(&timed(600, 5)), TimeControl::Rapid);
+        assert_eq!(TimeControl::from(&timed(1800, 20)), TimeControl::Classical);
+        assert_eq!(TimeControl::from(&TimeConfig::NotTimed), TimeControl::Unlimited);
+    }
+}
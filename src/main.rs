@@ -1,4 +1,5 @@
 use chesstacean::{
+    chess::{analysis::AnalysisPool, game::chat::ChatRules},
     server::{self, database, routes, tokens::TokenManager, user::registry::Registry, ServerConfig},
     word_loader,
 };
@@ -11,6 +12,15 @@ async fn main() {
 
     let words = word_loader::load().await;
 
+    // Read Args
+    let mut args = env::args();
+    // Ignore first arg (represents name of program)
+    args.next();
+
+    // Create config
+    let config = ServerConfig::build(args).unwrap_or(ServerConfig::new([127, 0, 0, 1], 3000, None));
+    ChatRules::install(config.chat.clone()).expect("Chat rules should be installed before any chat is sent");
+
     // Create TokenManager
     let token_manager = Arc::new(TokenManager::new());
 
@@ -21,7 +31,7 @@ async fn main() {
     let (db_tx, db_rx) = mpsc::channel(10);
 
     // Create and start user registry thread
-    let user_registry = Registry::new(words, &db_tx).await;
+    let user_registry = Registry::new(words, &db_tx, config.bots.clone()).await;
     tokio::task::spawn(Registry::start(user_registry.clone(), ws_rx, token_manager.clone()));
 
     // Create and start database thread, and session flusher
@@ -29,12 +39,18 @@ async fn main() {
     tokio::task::spawn(database);
     tokio::task::spawn(flusher);
 
+    // Create analysis workers
+    let analysis = AnalysisPool::start(&db_tx);
+
     // Create routes
     let routes = routes::attach_404(routes::ws_make(
-        routes::post_make(
-            routes::page_make(routes::static_make(), &db_tx),
-            &db_tx,
-            user_registry.clone(),
+        routes::analysis_make(
+            routes::post_make(
+                routes::page_make(routes::static_make(), &db_tx),
+                &db_tx,
+                user_registry.clone(),
+            ),
+            analysis,
         ),
         ws_tx,
         &db_tx,
@@ -42,13 +58,7 @@ async fn main() {
         user_registry,
     ));
 
-    // Read Args
-    let mut args = env::args();
-    // Ignore first arg (represents name of program)
-    args.next();
-
-    // Create config, and start server using config and routes
-    let config = ServerConfig::build(args).unwrap_or(ServerConfig::new([127, 0, 0, 1], 3000, None));
+    // Start server using config and routes
     match config.tls {
         Some(_) => {
             let tls_svr = server::run_tls_server(&config, routes).expect("Could not start tls server successfully");
@@ -1,4 +1,5 @@
 use self::ws::Connection;
+use crate::chess::{engine::bot::BotConfig, game::chat::ChatRules};
 use futures_util::Future;
 use std::fmt::Display;
 use std::net::{Ipv4Addr, SocketAddrV4};
@@ -35,10 +36,17 @@ pub fn run_tls_server(
     }
 }
 
+/// Where bot opponents are configured, as a JSON list of `BotConfig`s
+const BOTS_PATH: &str = "./util/bots.json";
+/// Where chat moderation is configured, as JSON `ChatRules`
+const CHAT_PATH: &str = "./util/chat.json";
+
 #[derive(Debug)]
 pub struct ServerConfig {
     pub addr: SocketAddrV4,
     pub tls: Option<Tls>,
+    pub bots: Vec<BotConfig>,
+    pub chat: ChatRules,
 }
 
 fn parse_arg<T: FromStr>(arg: Option<String>) -> Result<T, ()> {
@@ -56,6 +64,8 @@ impl ServerConfig {
         Self {
             addr: SocketAddrV4::new(Ipv4Addr::new(ipv4[0], ipv4[1], ipv4[2], ipv4[3]), port),
             tls,
+            bots: load_bots(BOTS_PATH),
+            chat: load_chat_rules(CHAT_PATH),
         }
     }
     pub fn build(mut args: impl Iterator<Item = String>) -> Result<Self, ()> {
@@ -82,17 +92,61 @@ impl ServerConfig {
     }
 }
 
+/// ### Reads the bot opponents from the config file
+///
+/// Falls back to one bot for each strength of the built-in engine if there is no file, or it can't be read
+fn load_bots(path: &str) -> Vec<BotConfig> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(_) => return BotConfig::builtin(),
+    };
+
+    match serde_json::from_str(&contents) {
+        Ok(bots) => {
+            console::success_msg(format!("Bots successfully loaded from {path}!"));
+            bots
+        }
+        Err(e) => {
+            eprint!("\rCould not read bots from {path} ({e}), using the built-in ones\n\n > ");
+            BotConfig::builtin()
+        }
+    }
+}
+
+/// ### Reads the chat rules from the config file
+///
+/// Falls back to the default rules if there is no file, or it can't be read
+fn load_chat_rules(path: &str) -> ChatRules {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(_) => return ChatRules::default(),
+    };
+
+    match serde_json::from_str(&contents) {
+        Ok(rules) => {
+            console::success_msg(format!("Chat rules successfully loaded from {path}!"));
+            rules
+        }
+        Err(e) => {
+            eprint!("\rCould not read chat rules from {path} ({e}), using the defaults\n\n > ");
+            ChatRules::default()
+        }
+    }
+}
+
 impl Display for ServerConfig {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "IPv4  {0}\nPort  {1}\nTls?  {2}",
+            "IPv4  {0}\nPort  {1}\nTls?  {2}\nBots  {3}\nChat  {4} filtered words",
             self.addr.ip(),
             self.addr.port(),
             match &self.tls {
                 None => "No",
                 Some(_) => "Yes",
-            }
+            },
+            self.bots.len(),
+            self.chat.filtered_words.len()
         )
     }
 }
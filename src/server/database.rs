@@ -22,10 +22,13 @@ use tokio::{
 
 pub mod auth;
 pub mod games;
+pub mod ratings;
 pub mod sessions;
 
+use crate::chess::rating::Rating;
 use auth::Auth;
-use games::Games;
+use games::{Games, StoredGame};
+use ratings::Ratings;
 use sessions::Sessions;
 
 pub fn init(
@@ -81,6 +84,10 @@ impl Database {
         Games::new(&self.conn)
     }
 
+    pub fn ratings<'a>(&'a self) -> Ratings<'a> {
+        Ratings::new(&self.conn)
+    }
+
     pub fn flush(&self, timestamp: u64) -> Result<Vec<String>> {
         let mut stmnt = self
             .conn
@@ -154,6 +161,11 @@ pub enum DatabaseResult {
     ResultBool(Result<bool>),
     UserInfo(Option<UserInfo>),
     FlushResult(Result<Vec<String>>),
+    Rating(Result<Rating>),
+    RatingPair(Result<(Rating, Rating)>),
+    ResultEmpty(Result<()>),
+    ResultOptionString(Result<Option<String>>),
+    StoredGame(Result<Option<StoredGame>>),
 }
 
 impl From<bool> for DatabaseResult {
@@ -198,6 +210,36 @@ impl From<Result<Vec<String>>> for DatabaseResult {
     }
 }
 
+impl From<Result<Rating>> for DatabaseResult {
+    fn from(value: Result<Rating>) -> Self {
+        Self::Rating(value)
+    }
+}
+
+impl From<Result<(Rating, Rating)>> for DatabaseResult {
+    fn from(value: Result<(Rating, Rating)>) -> Self {
+        Self::RatingPair(value)
+    }
+}
+
+impl From<Result<()>> for DatabaseResult {
+    fn from(value: Result<()>) -> Self {
+        Self::ResultEmpty(value)
+    }
+}
+
+impl From<Result<Option<String>>> for DatabaseResult {
+    fn from(value: Result<Option<String>>) -> Self {
+        Self::ResultOptionString(value)
+    }
+}
+
+impl From<Result<Option<StoredGame>>> for DatabaseResult {
+    fn from(value: Result<Option<StoredGame>>) -> Self {
+        Self::StoredGame(value)
+    }
+}
+
 impl Display for DatabaseResult {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -210,6 +252,11 @@ impl Display for DatabaseResult {
                 Self::ResultBool(b) => format!("{b:?}"),
                 Self::UserInfo(ui) => format!("{ui:?}"),
                 Self::FlushResult(sv) => format!("{sv:?}"),
+                Self::Rating(r) => format!("{r:?}"),
+                Self::RatingPair(rp) => format!("{rp:?}"),
+                Self::ResultEmpty(r) => format!("{r:?}"),
+                Self::ResultOptionString(r) => format!("{r:?}"),
+                Self::StoredGame(g) => format!("{g:?}"),
             }
         )
     }
@@ -284,9 +331,20 @@ fn attempt_create(database: &Connection) -> Result<()> {
         "CREATE TABLE IF NOT EXISTS games (
         id INTEGER PRIMARY KEY,
         name TEXT UNIQUE NOT NULL,
-        black INTEGER NOT NULL,
-        white INTEGER NOT NULL,
-        moves TEXT,
+        black INTEGER,
+        white INTEGER,
+        black_name TEXT NOT NULL,
+        white_name TEXT NOT NULL,
+        result TEXT NOT NULL,
+        end_state TEXT NOT NULL,
+        time_control TEXT NOT NULL,
+        start_fen TEXT NOT NULL,
+        width INTEGER NOT NULL,
+        height INTEGER NOT NULL,
+        moves TEXT NOT NULL,
+        move_times TEXT NOT NULL,
+        started INTEGER NOT NULL,
+        ended INTEGER NOT NULL,
         CONSTRAINT fk_black FOREIGN KEY (black) REFERENCES users(id),
         CONSTRAINT fk_white FOREIGN KEY (white) REFERENCES users(id)
        );",
@@ -305,6 +363,32 @@ fn attempt_create(database: &Connection) -> Result<()> {
         [],
     )?;
 
+    database.execute(
+        "CREATE TABLE IF NOT EXISTS ratings (
+            id INTEGER PRIMARY KEY,
+            user INTEGER NOT NULL,
+            time_control TEXT NOT NULL,
+            rating REAL NOT NULL,
+            deviation REAL NOT NULL,
+            volatility REAL NOT NULL,
+            games INTEGER NOT NULL DEFAULT 0,
+            UNIQUE (user, time_control),
+            CONSTRAINT fk_user FOREIGN KEY (user) REFERENCES users(id)
+       );",
+        [],
+    )?;
+
+    database.execute(
+        "CREATE TABLE IF NOT EXISTS analyses (
+            id INTEGER PRIMARY KEY,
+            game INTEGER NOT NULL UNIQUE,
+            analysis TEXT NOT NULL,
+            created INTEGER NOT NULL,
+            CONSTRAINT fk_game FOREIGN KEY (game) REFERENCES games(id)
+       );",
+        [],
+    )?;
+
     Ok(())
 }
 
@@ -329,9 +413,20 @@ fn get_tables() -> Vec<TableInfo> {
         columns: vec![
             ColumnInfo::default().name("id").kind("INTEGER").primary_key(true),
             ColumnInfo::default().name("name").kind("TEXT").not_null(true),
-            ColumnInfo::default().name("black").kind("INTEGER").not_null(true),
-            ColumnInfo::default().name("white").kind("INTEGER").not_null(true),
-            ColumnInfo::default().name("moves"),
+            ColumnInfo::default().name("black").kind("INTEGER"),
+            ColumnInfo::default().name("white").kind("INTEGER"),
+            ColumnInfo::default().name("black_name").not_null(true),
+            ColumnInfo::default().name("white_name").not_null(true),
+            ColumnInfo::default().name("result").not_null(true),
+            ColumnInfo::default().name("end_state").not_null(true),
+            ColumnInfo::default().name("time_control").not_null(true),
+            ColumnInfo::default().name("start_fen").not_null(true),
+            ColumnInfo::default().name("width").kind("INTEGER").not_null(true),
+            ColumnInfo::default().name("height").kind("INTEGER").not_null(true),
+            ColumnInfo::default().name("moves").not_null(true),
+            ColumnInfo::default().name("move_times").not_null(true),
+            ColumnInfo::default().name("started").kind("INTEGER").not_null(true),
+            ColumnInfo::default().name("ended").kind("INTEGER").not_null(true),
         ],
     });
 
@@ -356,6 +451,33 @@ fn get_tables() -> Vec<TableInfo> {
         ],
     });
 
+    tables.push(TableInfo {
+        name: "ratings".to_owned(),
+        columns: vec![
+            ColumnInfo::default().name("id").kind("INTEGER").primary_key(true),
+            ColumnInfo::default().name("user").kind("INTEGER").not_null(true),
+            ColumnInfo::default().name("time_control").not_null(true),
+            ColumnInfo::default().name("rating").kind("REAL").not_null(true),
+            ColumnInfo::default().name("deviation").kind("REAL").not_null(true),
+            ColumnInfo::default().name("volatility").kind("REAL").not_null(true),
+            ColumnInfo::default()
+                .name("games")
+                .kind("INTEGER")
+                .not_null(true)
+                .default_value(Some("0".to_owned())),
+        ],
+    });
+
+    tables.push(TableInfo {
+        name: "analyses".to_owned(),
+        columns: vec![
+            ColumnInfo::default().name("id").kind("INTEGER").primary_key(true),
+            ColumnInfo::default().name("game").kind("INTEGER").not_null(true),
+            ColumnInfo::default().name("analysis").not_null(true),
+            ColumnInfo::default().name("created").kind("INTEGER").not_null(true),
+        ],
+    });
+
     tables
 }
 
@@ -451,3 +573,120 @@ impl ColumnInfo {
         self
     }
 }
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::chess::{
+        game::{board::STARTING_FEN, EndState, TimeConfig, Winner},
+        rating::TimeControl,
+    };
+    use games::GameRecord;
+
+    fn test_database() -> Database {
+        Database::new(Connection::open_in_memory().unwrap())
+    }
+
+    /// Starts an in-memory database on the runtime, for tests that talk to it through messages
+    pub fn spawn_test_database() -> Sender<DatabaseMessage> {
+        let (tx, rx) = tokio::sync::mpsc::channel(16);
+        tokio::task::spawn(test_database().start(rx));
+        tx
+    }
+
+    #[test]
+    fn tables_match_their_definitions() {
+        let database = test_database();
+        for template in get_tables() {
+            assert_eq!(TableInfo::from_query(&database.conn, &template.name).unwrap(), template);
+        }
+    }
+
+    #[test]
+    fn records_games_with_guests() {
+        let database = test_database();
+        database
+            .auth()
+            .create_user("white".into(), "White".into(), "password".into())
+            .unwrap();
+
+        let code = "quiet-otter".to_string();
+        database
+            .games()
+            .record_game(GameRecord {
+                code: code.clone(),
+                white: UserInfo::new_user("white", "White"),
+                black: UserInfo::new_guest(),
+                winner: Winner::White,
+                end_state: EndState::Checkmate,
+                time: TimeConfig::NotTimed,
+                start_fen: STARTING_FEN.to_string(),
+                width: 8,
+                height: 8,
+                moves: vec!["e2e4".to_string(), "e7e5".to_string()],
+                move_times: vec![2, 3],
+                started: 1,
+                ended: 4,
+            })
+            .unwrap();
+
+        assert!(database.games().game_exists(code.clone()).unwrap());
+
+        let stored = database.games().get_game(code).unwrap().unwrap();
+        assert_eq!(stored.white_name, "White");
+        assert_eq!(stored.moves, vec!["e2e4".to_string(), "e7e5".to_string()]);
+    }
+
+    #[test]
+    fn stores_analysis_next_to_games() {
+        let database = test_database();
+        let code = "loud-heron".to_string();
+        database
+            .games()
+            .record_game(GameRecord {
+                code: code.clone(),
+                white: UserInfo::new_guest(),
+                black: UserInfo::new_guest(),
+                winner: Winner::None,
+                end_state: EndState::Agreement,
+                time: TimeConfig::NotTimed,
+                start_fen: STARTING_FEN.to_string(),
+                width: 8,
+                height: 8,
+                moves: vec![],
+                move_times: vec![],
+                started: 1,
+                ended: 2,
+            })
+            .unwrap();
+
+        let games = database.games();
+        assert_eq!(games.get_analysis(code.clone()).unwrap(), None);
+        games.record_analysis(code.clone(), "{}".to_string()).unwrap();
+        games.record_analysis(code.clone(), "[]".to_string()).unwrap();
+        assert_eq!(games.get_analysis(code).unwrap(), Some("[]".to_string()));
+        assert!(games.get_game("no-such-game".to_string()).unwrap().is_none());
+    }
+
+    #[test]
+    fn rated_games_update_both_players() {
+        let database = test_database();
+        let auth = database.auth();
+        auth.create_user("white".into(), "White".into(), "password".into())
+            .unwrap();
+        auth.create_user("black".into(), "Black".into(), "password".into())
+            .unwrap();
+
+        let ratings = database.ratings();
+        let (white, black) = ratings
+            .record_game("white".into(), "black".into(), TimeControl::Blitz, 1.0)
+            .unwrap();
+
+        assert!(white.rating > 1500.0 && black.rating < 1500.0);
+        assert_eq!(ratings.get_rating("white".into(), TimeControl::Blitz).unwrap(), white);
+        assert_eq!(
+            ratings.get_rating("white".into(), TimeControl::Rapid).unwrap(),
+            Rating::default()
+        );
+    }
+}
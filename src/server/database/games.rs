@@ -1,4 +1,6 @@
 use super::*;
+use crate::chess::game::{EndState, TimeConfig, Winner};
+use serde::Serialize;
 
 pub struct Games<'a> {
     conn: &'a Connection,
@@ -19,4 +21,140 @@ impl<'a> Games<'a> {
 
         Ok(result)
     }
+
+    /// ### Writes a finished game
+    ///
+    /// Guests have no user id, so they are only stored by display name
+    pub fn record_game(&self, record: GameRecord) -> Result<()> {
+        let mut stmnt = self
+            .conn
+            .prepare_cached(
+                "INSERT INTO games (
+                    name, black, white, black_name, white_name, result, end_state,
+                    time_control, start_fen, width, height, moves, move_times, started, ended
+                ) VALUES (
+                    ?1, (SELECT id FROM users WHERE handle = ?2), (SELECT id FROM users WHERE handle = ?3),
+                    ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15
+                )",
+            )
+            .expect("Should be a valid sql statement");
+
+        let move_times: Vec<String> = record.move_times.iter().map(|t| t.to_string()).collect();
+
+        stmnt.execute(params![
+            record.code,
+            record.black.get_handle(),
+            record.white.get_handle(),
+            record.black.get_display(),
+            record.white.get_display(),
+            to_text(record.winner)?,
+            to_text(record.end_state)?,
+            record.time.to_string(),
+            record.start_fen,
+            record.width,
+            record.height,
+            record.moves.join(" "),
+            move_times.join(" "),
+            record.started as i64,
+            record.ended as i64,
+        ])?;
+
+        Ok(())
+    }
+
+    /// Reads back the players and moves of a finished game, or `None` if there is no such game
+    pub fn get_game(&self, name: String) -> Result<Option<StoredGame>> {
+        let mut stmnt = self
+            .conn
+            .prepare_cached("SELECT white_name, black_name, start_fen, width, height, moves FROM games WHERE name = ?1")
+            .expect("Should be a valid sql statement");
+
+        let mut rows = stmnt.query(params![name])?;
+        let row = match rows.next()? {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+
+        let moves: String = row.get(5)?;
+        Ok(Some(StoredGame {
+            white_name: row.get(0)?,
+            black_name: row.get(1)?,
+            start_fen: row.get(2)?,
+            width: row.get(3)?,
+            height: row.get(4)?,
+            moves: moves.split_whitespace().map(String::from).collect(),
+        }))
+    }
+
+    /// ### Stores the analysis of a game, as JSON
+    ///
+    /// A game has at most one analysis, so analysing it again replaces the old one
+    pub fn record_analysis(&self, name: String, analysis: String) -> Result<()> {
+        let mut stmnt = self
+            .conn
+            .prepare_cached(
+                "INSERT INTO analyses (game, analysis, created)
+                VALUES ((SELECT id FROM games WHERE name = ?1), ?2, ?3)
+                ON CONFLICT (game) DO UPDATE SET analysis = excluded.analysis, created = excluded.created",
+            )
+            .expect("Should be a valid sql statement");
+
+        stmnt.execute(params![name, analysis, get_timestamp() as i64])?;
+
+        Ok(())
+    }
+
+    /// The stored analysis of a game as JSON, or `None` if it has not been analysed
+    pub fn get_analysis(&self, name: String) -> Result<Option<String>> {
+        let mut stmnt = self
+            .conn
+            .prepare_cached("SELECT analysis FROM analyses WHERE game = (SELECT id FROM games WHERE name = ?1)")
+            .expect("Should be a valid sql statement");
+
+        let mut rows = stmnt.query(params![name])?;
+        match rows.next()? {
+            Some(row) => Ok(Some(row.get(0)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// ### The parts of a stored game needed to replay it
+///
+/// Moves are in coordinate notation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredGame {
+    pub white_name: String,
+    pub black_name: String,
+    pub start_fen: String,
+    pub width: u8,
+    pub height: u8,
+    pub moves: Vec<String>,
+}
+
+/// ### A finished game, as it is stored
+///
+/// Moves are in coordinate notation, and times are milliseconds since the unix epoch
+pub struct GameRecord {
+    pub code: String,
+    pub white: UserInfo,
+    pub black: UserInfo,
+    pub winner: Winner,
+    pub end_state: EndState,
+    pub time: TimeConfig,
+    pub start_fen: String,
+    pub width: u8,
+    pub height: u8,
+    pub moves: Vec<String>,
+    pub move_times: Vec<u128>,
+    pub started: u128,
+    pub ended: u128,
+}
+
+/// Stores a unit enum variant as its name
+fn to_text(value: impl Serialize) -> Result<String> {
+    match serde_json::to_value(value)? {
+        serde_json::Value::String(text) => Ok(text),
+        _ => bail!(SQLError),
+    }
 }
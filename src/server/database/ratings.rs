@@ -0,0 +1,92 @@
+use super::*;
+use crate::chess::rating::{Rating, TimeControl};
+
+pub struct Ratings<'a> {
+    conn: &'a Connection,
+}
+
+impl<'a> Ratings<'a> {
+    pub(super) fn new(conn: &'a Connection) -> Self {
+        Self { conn }
+    }
+
+    /// ### Gets a user's rating in a time control
+    ///
+    /// Users who have not played a rated game in the time control have the default rating
+    pub fn get_rating(&self, handle: String, time_control: TimeControl) -> Result<Rating> {
+        let mut stmnt = self
+            .conn
+            .prepare_cached(
+                "SELECT rating, deviation, volatility FROM ratings
+                WHERE user = (SELECT id FROM users WHERE handle = ?1) AND time_control = ?2",
+            )
+            .expect("Should be a valid sql statement");
+
+        let result = stmnt.query_row(params![handle, time_control.to_string()], |row| {
+            Ok(Rating {
+                rating: row.get(0)?,
+                deviation: row.get(1)?,
+                volatility: row.get(2)?,
+            })
+        });
+
+        match result {
+            Ok(rating) => Ok(rating),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(Rating::default()),
+            Err(e) => bail!(e),
+        }
+    }
+
+    fn set_rating(&self, handle: &String, time_control: TimeControl, rating: Rating) -> Result<()> {
+        let mut stmnt = self
+            .conn
+            .prepare_cached(
+                "INSERT INTO ratings (user, time_control, rating, deviation, volatility, games)
+                VALUES ((SELECT id FROM users WHERE handle = ?1), ?2, ?3, ?4, ?5, 1)
+                ON CONFLICT (user, time_control) DO UPDATE SET
+                    rating = excluded.rating,
+                    deviation = excluded.deviation,
+                    volatility = excluded.volatility,
+                    games = games + 1",
+            )
+            .expect("Should be a valid sql statement");
+
+        stmnt.execute(params![
+            handle,
+            time_control.to_string(),
+            rating.rating,
+            rating.deviation,
+            rating.volatility
+        ])?;
+
+        Ok(())
+    }
+
+    /// ### Updates both players' ratings after a rated game
+    ///
+    /// `white_score` is 1.0 for a white win, 0.5 for a draw, and 0.0 for a black win
+    ///
+    /// Returns the new ratings of white and black, in that order
+    pub fn record_game(
+        &self,
+        white: String,
+        black: String,
+        time_control: TimeControl,
+        white_score: f64,
+    ) -> Result<(Rating, Rating)> {
+        let transaction = self.conn.unchecked_transaction()?;
+
+        let white_rating = self.get_rating(white.clone(), time_control)?;
+        let black_rating = self.get_rating(black.clone(), time_control)?;
+
+        let new_white = white_rating.update(&[(black_rating, white_score)]);
+        let new_black = black_rating.update(&[(white_rating, 1.0 - white_score)]);
+
+        self.set_rating(&white, time_control, new_white)?;
+        self.set_rating(&black, time_control, new_black)?;
+
+        transaction.commit()?;
+
+        Ok((new_white, new_black))
+    }
+}
@@ -7,6 +7,7 @@ use std::{
 };
 use warp::{filters::fs::File, reply::Reply};
 
+use crate::chess::analysis::{AnalysisPool, AnalysisRequestError, AnalysisStatus};
 use crate::server::{
     database::{auth::ArgonError, SQLError},
     utils::input::{validate_display, validate_handle, validate_password},
@@ -160,6 +161,36 @@ pub fn post_make(
     routes.or(auth_base.and(login.or(logout).or(signup)))
 }
 
+/// ### Creates the server's game analysis endpoints
+///
+/// `GET /analysis/<code>` gives a finished game's analysis as JSON, and
+/// `POST /analysis/<code>` queues the game to be analysed if it hasn't been already.
+/// Games still being analysed are answered with `202 Accepted` and their status
+pub fn analysis_make(
+    routes: impl Filter<Extract = (impl warp::Reply,), Error = Rejection> + Clone + Send + Sync + 'static,
+    analysis: Arc<AnalysisPool>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = Rejection> + Clone + Send + Sync {
+    let get = {
+        let analysis = analysis.clone();
+        warp::get()
+            .and(warp::path!("analysis" / String))
+            .and_then(move |code: String| {
+                let analysis = analysis.clone();
+                async move { get_analysis(code, analysis).await }
+            })
+    };
+
+    let request = warp::post()
+        .and(warp::path!("analysis" / String))
+        .and_then(move |code: String| {
+            let analysis = analysis.clone();
+            async move { request_analysis(code, analysis).await }
+        });
+
+    // Boxed, as the filter's type has grown too deeply nested for the compiler to handle
+    routes.or(get.or(request)).boxed()
+}
+
 /// ### Creates the server's 404 page.
 ///
 /// Keep in mind, the values are hardcoded into this function (at least for now),
@@ -182,6 +213,47 @@ pub fn attach_404(
     routes.or(none_found_route)
 }
 
+async fn get_analysis(code: String, analysis: Arc<AnalysisPool>) -> Result<impl Reply, Rejection> {
+    match analysis.get(code).await {
+        Ok(Ok(json)) => Ok(json_reply(json)),
+        Ok(Err(Some(status))) => Ok(status_reply(status)),
+        Ok(Err(None)) => Ok(not_found("NotAnalysed: This game has not been analysed")),
+        Err(_) => Ok(server_error("Error fetching analysis")),
+    }
+}
+
+async fn request_analysis(code: String, analysis: Arc<AnalysisPool>) -> Result<impl Reply, Rejection> {
+    match analysis.request(code).await {
+        Ok(Ok(json)) => Ok(json_reply(json)),
+        Ok(Err(status)) => Ok(status_reply(status)),
+        Err(e) => match e.downcast_ref::<AnalysisRequestError>() {
+            Some(err @ AnalysisRequestError::NoSuchGame) => Ok(not_found(err)),
+            Some(err @ AnalysisRequestError::QueueFull) => {
+                Ok(Response::builder().status(503).body(err.to_string()).unwrap())
+            }
+            _ => Ok(server_error("Error requesting analysis")),
+        },
+    }
+}
+
+fn json_reply(json: String) -> Response<String> {
+    Response::builder()
+        .header("Content-Type", "application/json")
+        .body(json)
+        .unwrap()
+}
+
+fn status_reply(status: AnalysisStatus) -> Response<String> {
+    match serde_json::to_string(&status) {
+        Ok(status) => Response::builder().status(202).body(status).unwrap(),
+        Err(_) => server_error("Could not serialize analysis status"),
+    }
+}
+
+fn not_found(msg: impl ToString) -> Response<String> {
+    Response::builder().status(404).body(msg.to_string()).unwrap()
+}
+
 async fn log_in(
     cookie: String,
     data: Login,
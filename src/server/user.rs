@@ -3,11 +3,17 @@ use super::{
     ws,
 };
 use crate::{
-    chess::controller::GameControllerInterface,
-    server::ws::{Connection, ControlEvent, RecievedMessage, SentMessage},
+    chess::{
+        controller::{ControllerError, GameControllerInterface},
+        game::{
+            chat::{ChatError, ChatLimiter, ChatRules},
+            network::{ApprovedChatMessage, ChatMessage, ChatRole, MessageInterface, Spectator},
+        },
+    },
+    server::ws::{Connection, ControlEvent, GameEvent, RecievedMessage, SentMessage},
 };
 use anyhow::Result;
-use interface::GameInterface;
+use interface::{GameInterface, InterfaceError};
 use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
@@ -17,8 +23,8 @@ use std::{
     },
 };
 use tokio::{
-    sync::{mpsc, RwLock},
-    task::JoinSet,
+    sync::{mpsc, oneshot, RwLock},
+    task::{AbortHandle, JoinSet},
 };
 
 pub mod interface;
@@ -99,6 +105,8 @@ impl UserConnection {
         let mut writer = self.connections.write().await;
         if let Some(conn) = writer.remove(session) {
             conn.close().await;
+            drop(writer);
+            self.listener.clean_up_if_disconnected().await;
             self.listener.interrupt().await.unwrap();
             true
         } else {
@@ -140,6 +148,9 @@ impl From<(UserInfo, Arc<GameControllerInterface>)> for UserConnection {
 struct ConnectionListener {
     connections: ArcLock<HashMap<String, SessionConnections>>,
     targets: Targets,
+    /// Games this user is watching, which can never be sent moves or actions
+    spectating: RwLock<HashMap<String, Spectating>>,
+    chat_limiter: RwLock<ChatLimiter>,
     controller: Arc<GameControllerInterface>,
     interrupt: mpsc::Sender<()>,
 
@@ -186,7 +197,8 @@ impl ConnectionListener {
                                 break 'inner;
                             }
                         }
-                        drop(hash_map)
+                        drop(hash_map);
+                        self.clean_up_if_disconnected().await;
                     }
                     ws::ListenerResult::Error(err) => {
                         eprint!("\rNew Error: {err}\n\n > ");
@@ -226,6 +238,8 @@ impl ConnectionListener {
         Self {
             connections,
             targets: Targets::new(),
+            spectating: RwLock::new(HashMap::new()),
+            chat_limiter: RwLock::new(ChatLimiter::new(ChatRules::get().rate_limit)),
             interrupt,
             controller,
             info: RwLock::new(info.clone()),
@@ -247,7 +261,10 @@ impl ConnectionListener {
                     CreateLobby => {
                         let code = controller.create_lobby(&reader, (&self.connections).into()).await;
                         match code {
-                            Ok(code) => self.send(ControlEvent::LobbyCreated { code }.into()).await,
+                            Ok((code, rx)) => {
+                                self.send(ControlEvent::LobbyCreated { code }.into()).await;
+                                tokio::task::spawn(ConnectionListener::await_lobby(self.clone(), rx));
+                            }
                             Err(e) => {
                                 eprint!("\rExperienced an error creating a lobby: {e:?}\n\n > ");
                                 self.send(SentMessage::error(e)).await;
@@ -259,14 +276,20 @@ impl ConnectionListener {
                             self.send(SentMessage::error(e)).await;
                         }
                     }
-                    StartLobby { config } => {}
+                    StartLobby { code, config } => {
+                        if let Err(e) = controller.start_lobby(reader, code, config).await {
+                            self.send(SentMessage::error(e)).await;
+                        }
+                    }
 
                     JoinLobby { code } => {
                         let result = controller.join_lobby(&code, &reader, (&self.connections).into()).await;
-                        if let Err(e) = result {
-                            self.send(SentMessage::error(e)).await;
-                        } else {
-                            self.send(ControlEvent::JoinedLobby { code }.into()).await;
+                        match result {
+                            Ok(rx) => {
+                                self.send(ControlEvent::JoinedLobby { code }.into()).await;
+                                tokio::task::spawn(ConnectionListener::await_lobby(self.clone(), rx));
+                            }
+                            Err(e) => self.send(SentMessage::error(e)).await,
                         }
                     }
                     LeaveLobby { code } => {
@@ -278,21 +301,227 @@ impl ConnectionListener {
                         }
                     }
 
-                    JoinQueue => (),
-                    LeaveQueue => (),
+                    JoinQueue { pools } => match controller.join_queue(reader, &pools).await {
+                        Ok(rx) => {
+                            self.send(ControlEvent::JoinedQueue { pools }.into()).await;
+                            tokio::task::spawn(ConnectionListener::await_match(self.clone(), rx));
+                        }
+                        Err(e) => self.send(SentMessage::error(e)).await,
+                    },
+                    LeaveQueue => {
+                        // Leaving the queue is reported by the task waiting on the match
+                        if !controller.leave_queue(reader).await {
+                            self.send(SentMessage::error(ControllerError::NotInQueue)).await;
+                        }
+                    }
+
+                    ListBots => {
+                        let names = controller.bots();
+                        self.send(ControlEvent::Bots { names }.into()).await;
+                    }
+                    PlayBot { bot, pool } => match controller.play_bot(reader, &bot, pool).await {
+                        Ok(interface) => {
+                            let code = interface.code().clone();
+                            self.targets.insert(Arc::clone(&interface)).await;
+                            self.send(ControlEvent::Matched { code }.into()).await;
+                            tokio::task::spawn(ConnectionListener::play(self.clone(), interface));
+                        }
+                        Err(e) => self.send(SentMessage::error(e)).await,
+                    },
 
-                    JoinAsSpectator { code } => (),
+                    JoinAsSpectator { code } => {
+                        if self.targets.get(&code).await.is_some() {
+                            return self.send(SentMessage::error(ControllerError::IsPlayer)).await;
+                        }
+                        let mut spectating = self.spectating.write().await;
+                        if spectating.contains_key(&code) {
+                            drop(spectating);
+                            return self.send(SentMessage::error(ControllerError::AlreadySpectating)).await;
+                        }
+                        match controller.spectate(&code).await {
+                            Ok(spectator) => {
+                                self.send(ControlEvent::JoinedAsSpectator { code: code.clone() }.into())
+                                    .await;
+                                // Still holding the lock, so the task cannot finish before it is recorded
+                                let messenger = spectator.messenger();
+                                let task = tokio::task::spawn(ConnectionListener::spectate(
+                                    self.clone(),
+                                    code.clone(),
+                                    spectator,
+                                ));
+                                spectating.insert(
+                                    code,
+                                    Spectating {
+                                        task: task.abort_handle(),
+                                        messenger,
+                                    },
+                                );
+                            }
+                            Err(e) => {
+                                drop(spectating);
+                                self.send(SentMessage::error(e)).await;
+                            }
+                        }
+                    }
+                    StopSpectating { code } => {
+                        let spectated = self.spectating.write().await.remove(&code);
+                        match spectated {
+                            Some(spectated) => {
+                                spectated.task.abort();
+                                self.send(ControlEvent::StoppedSpectating { code }.into()).await;
+                            }
+                            None => self.send(SentMessage::error(ControllerError::NotSpectating)).await,
+                        }
+                    }
                 }
             }
-            RecievedMessage::GameAction { action } => {
-                use ws::GameAction::*;
-                match action {
-                    Turn { .. } => {}
-                    _ => (),
+            RecievedMessage::GameAction { action } => match self.answer_game_action(action).await {
+                Ok(event) => self.send(event.into()).await,
+                Err(e) => self.send(SentMessage::error(e)).await,
+            },
+        }
+    }
+
+    /// ### Waits on the matchmaker to place this user in a game
+    ///
+    /// If a game is found, it is registered as a target and played until its chat closes
+    async fn await_match(self: Arc<Self>, rx: oneshot::Receiver<Option<Arc<GameInterface>>>) {
+        match rx.await {
+            Ok(Some(interface)) => {
+                let code = interface.code().clone();
+                self.targets.insert(Arc::clone(&interface)).await;
+                self.send(ControlEvent::Matched { code }.into()).await;
+                self.play(interface).await;
+            }
+            Ok(None) | Err(_) => self.send(ControlEvent::LeftQueue.into()).await,
+        }
+    }
+
+    /// ### Waits on a lobby this user is in to start
+    ///
+    /// Nothing is sent if the lobby closes or the user leaves it, as those are already reported
+    async fn await_lobby(self: Arc<Self>, rx: oneshot::Receiver<Option<Arc<GameInterface>>>) {
+        if let Ok(Some(interface)) = rx.await {
+            let code = interface.code().clone();
+            self.targets.insert(Arc::clone(&interface)).await;
+            self.send(ControlEvent::LobbyStarted { code }.into()).await;
+            self.play(interface).await;
+        }
+    }
+
+    /// ### Sends a game this user is playing to them until its chat closes
+    ///
+    /// The game stays a target until then, so the players can keep chatting after it ends
+    async fn play(self: Arc<Self>, interface: Arc<GameInterface>) {
+        let code = interface.code().clone();
+        interface.run((&self.connections).into()).await;
+        self.targets.remove(&code).await;
+    }
+
+    /// ### Sends a spectated game to the user until its chat closes
+    ///
+    /// Starts with a snapshot of the game, so the user can follow along from its current position.
+    /// Keeps going after the game ends, so they can join in the post-game chat
+    async fn spectate(self: Arc<Self>, code: String, mut spectator: Spectator) {
+        while let Some(event) = spectator.recv().await {
+            self.send(
+                GameEvent::Spectate {
+                    code: code.clone(),
+                    event,
                 }
+                .into(),
+            )
+            .await;
+        }
+        self.spectating.write().await.remove(&code);
+    }
+
+    /// Removes the user from matchmaking, and stops them spectating, once they no longer have any open connections
+    async fn clean_up_if_disconnected(&self) {
+        let connected = self
+            .connections
+            .read()
+            .await
+            .values()
+            .any(|session| !session.is_empty());
+        if !connected {
+            self.controller.leave_queue(&*self.info.read().await).await;
+            for (_, spectated) in self.spectating.write().await.drain() {
+                spectated.task.abort();
+            }
+        }
+    }
+
+    /// ### Handles a game action, returning the acknowledgement or rejection to send back
+    ///
+    /// Returns `Err` if a chat message was moderated, which is reported as an error instead
+    async fn answer_game_action(&self, action: ws::GameAction) -> Result<GameEvent, ChatError> {
+        let code = action.code().clone();
+        match self.handle_game_action(action).await {
+            Ok(event) => Ok(event),
+            Err(InterfaceError::Moderated(e)) => Err(e),
+            Err(error) => Ok(GameEvent::Rejected { code, error }),
+        }
+    }
+
+    /// Passes a game action on to the matching game, returning the acknowledgement to send back
+    async fn handle_game_action(&self, action: ws::GameAction) -> Result<GameEvent, InterfaceError> {
+        use ws::GameAction::*;
+        let target = match self.targets.get(action.code()).await {
+            Some(target) => target,
+            None => return self.handle_spectator_action(action).await,
+        };
+        let sender = self.info.read().await.clone();
+
+        match action {
+            // Finished games are only kept for their post-game chat
+            Turn { .. } | Action { .. } if target.has_ended() => Err(InterfaceError::UnknownGame),
+            Turn { code, turn } => {
+                target.send_move(turn).await?;
+                Ok(GameEvent::MoveAccepted { code })
+            }
+            Message { code, msg } => {
+                target.send_message(self.moderate(sender, msg).await?).await?;
+                Ok(GameEvent::MessageAccepted { code })
+            }
+            Action { code, action } => {
+                target.send_action(sender, action).await?;
+                Ok(GameEvent::ActionAccepted { code, action })
+            }
+            Mute { code, muted } => {
+                target.mute_opponent(muted)?;
+                Ok(GameEvent::MuteAccepted { code, muted })
+            }
+        }
+    }
+
+    /// Spectators can only chat, and only in the spectators' room, or the post-game room once the game is over
+    async fn handle_spectator_action(&self, action: ws::GameAction) -> Result<GameEvent, InterfaceError> {
+        let messenger = match self.spectating.read().await.get(action.code()) {
+            Some(spectated) => Arc::clone(&spectated.messenger),
+            None => return Err(InterfaceError::UnknownGame),
+        };
+
+        match action {
+            ws::GameAction::Message { code, msg } => {
+                let sender = self.info.read().await.clone();
+                messenger
+                    .send(ChatRole::Spectator, self.moderate(sender, msg).await?)
+                    .await?;
+                Ok(GameEvent::MessageAccepted { code })
             }
+            _ => Err(InterfaceError::UnknownGame),
         }
     }
+
+    /// ### Checks a message against the chat rules and this user's rate limit
+    ///
+    /// Only messages that pass the rules count towards the rate limit
+    async fn moderate(&self, sender: UserInfo, msg: String) -> Result<ApprovedChatMessage, ChatError> {
+        let message = ApprovedChatMessage::try_from(ChatMessage::new(sender, msg))?;
+        self.chat_limiter.write().await.check()?;
+        Ok(message)
+    }
 }
 
 impl Sender for ConnectionListener {
@@ -309,8 +538,14 @@ impl From<&ArcLock<HashMap<String, SessionConnections>>> for ConnectionExtension
     }
 }
 
+/// A game this user is spectating
+struct Spectating {
+    task: AbortHandle,
+    messenger: Arc<MessageInterface>,
+}
+
 struct Targets {
-    inner: RwLock<HashMap<String, GameInterface>>,
+    inner: RwLock<HashMap<String, Arc<GameInterface>>>,
 }
 
 impl Targets {
@@ -320,6 +555,21 @@ impl Targets {
         }
     }
 
+    /// Registers a game this user is playing, under its code
+    async fn insert(&self, interface: Arc<GameInterface>) {
+        let mut writer = self.inner.write().await;
+        writer.insert(interface.code().clone(), interface);
+    }
+
+    async fn get(&self, code: &str) -> Option<Arc<GameInterface>> {
+        let reader = self.inner.read().await;
+        reader.get(code).cloned()
+    }
+
+    async fn remove(&self, code: &str) {
+        self.inner.write().await.remove(code);
+    }
+
     async fn upgrade(&self, user: UserInfo) {}
 }
 
@@ -341,6 +591,9 @@ impl SessionConnections {
     fn add_connection(&mut self, conn: Connection) {
         self.connections.push(Arc::new(conn))
     }
+    fn is_empty(&self) -> bool {
+        self.connections.is_empty()
+    }
     fn listen(&self, joins_set: &mut JoinSet<ws::ListenerResult>) {
         for conn in self.connections.iter() {
             joins_set.spawn(conn.clone().listen());
@@ -382,6 +635,7 @@ static GUEST_COUNT: AtomicU32 = AtomicU32::new(1);
 pub enum UserInfo {
     User { handle: String, display: String },
     Guest { guest_num: u32 },
+    Bot { name: String },
 }
 
 impl UserInfo {
@@ -397,16 +651,253 @@ impl UserInfo {
         }
     }
 
+    pub fn new_bot(name: impl ToString) -> Self {
+        Self::Bot { name: name.to_string() }
+    }
+
     pub fn get_handle(&self) -> Option<String> {
         match self {
-            Self::Guest { .. } => None,
+            Self::Guest { .. } | Self::Bot { .. } => None,
             Self::User { handle, .. } => Some(handle.clone()),
         }
     }
     pub fn get_display(&self) -> String {
         match self {
             Self::Guest { guest_num } => format!("Guest{guest_num}"),
+            Self::Bot { name } => name.clone(),
             Self::User { display, .. } => display.clone(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess::{
+        controller::{tests::test_controller, Pool},
+        game::{
+            network::{ActionType, Event},
+            pieces::Turn,
+            EndState, GameConfig, Winner,
+        },
+    };
+    use std::time::Duration;
+    use tokio::{sync::watch, time};
+
+    /// The game's side of a game interface, so tests can play the game by hand
+    struct FakeGame {
+        moves: mpsc::Sender<oneshot::Sender<(Turn, oneshot::Sender<bool>)>>,
+        events: mpsc::Sender<Event>,
+        messenger: Arc<MessageInterface>,
+    }
+
+    /// Registers a game with the listener, as if its user had just been matched into it
+    async fn fake_game(listener: &Arc<ConnectionListener>, code: &str) -> FakeGame {
+        let (moves, move_rx) = mpsc::channel(2);
+        let (events, event_rx) = mpsc::channel(16);
+        let (_, action_rx) = watch::channel(None);
+        let (_, spectators) = watch::channel(0);
+        let messenger = MessageInterface::create(true);
+        let user = listener.info.read().await.clone();
+
+        let interface = GameInterface::new(
+            move_rx,
+            action_rx,
+            event_rx,
+            code.to_string(),
+            user,
+            &messenger,
+            spectators,
+        );
+        listener.targets.insert(Arc::clone(&interface)).await;
+        tokio::task::spawn(ConnectionListener::play(Arc::clone(listener), interface));
+        FakeGame {
+            moves,
+            events,
+            messenger,
+        }
+    }
+
+    impl FakeGame {
+        /// Asks for the player's move, and accepts it if it is `valid`
+        async fn request_move(&self, valid: bool) {
+            let (tx, rx) = oneshot::channel();
+            self.moves.send(tx).await.unwrap();
+            tokio::task::spawn(async move {
+                let (_, result_tx) = rx.await.unwrap();
+                result_tx.send(valid).unwrap();
+            });
+        }
+    }
+
+    fn turn(code: &str) -> ws::GameAction {
+        ws::GameAction::Turn {
+            code: code.to_string(),
+            turn: Turn::Notation("e4".to_string()),
+        }
+    }
+
+    fn listener(controller: &Arc<GameControllerInterface>) -> Arc<ConnectionListener> {
+        let (interrupt, _) = mpsc::channel(1);
+        Arc::new(ConnectionListener::new(
+            ArcLock::new_arclock(HashMap::new()),
+            interrupt,
+            Arc::clone(controller),
+            &UserInfo::new_guest(),
+        ))
+    }
+
+    #[tokio::test]
+    async fn disconnecting_leaves_the_queue() {
+        let controller = test_controller().await;
+        let listener = listener(&controller);
+        let user = listener.info.read().await.clone();
+
+        let rx = controller.join_queue(&user, &[Pool::Blitz, Pool::Rapid]).await.unwrap();
+        listener.clean_up_if_disconnected().await;
+
+        assert!(rx.await.unwrap().is_none());
+        assert!(!controller.leave_queue(&user).await);
+    }
+
+    #[tokio::test]
+    async fn moves_are_routed_to_the_game() {
+        let controller = test_controller().await;
+        let listener = listener(&controller);
+        let game = fake_game(&listener, "game").await;
+
+        assert!(matches!(
+            listener.answer_game_action(turn("game")).await,
+            Ok(GameEvent::Rejected {
+                error: InterfaceError::NotYourTurn,
+                ..
+            })
+        ));
+
+        game.request_move(true).await;
+        assert!(matches!(
+            listener.answer_game_action(turn("game")).await,
+            Ok(GameEvent::MoveAccepted { code }) if code == "game"
+        ));
+
+        game.request_move(false).await;
+        assert!(matches!(
+            listener.answer_game_action(turn("game")).await,
+            Ok(GameEvent::Rejected {
+                error: InterfaceError::InvalidMove,
+                ..
+            })
+        ));
+
+        assert!(matches!(
+            listener.answer_game_action(turn("other")).await,
+            Ok(GameEvent::Rejected {
+                error: InterfaceError::UnknownGame,
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn finished_games_only_keep_their_chat() {
+        let controller = test_controller().await;
+        let listener = listener(&controller);
+        let game = fake_game(&listener, "game").await;
+        let code = || "game".to_string();
+        let message = || ws::GameAction::Message {
+            code: code(),
+            msg: "gg".to_string(),
+        };
+
+        let end = Event::GameEnd {
+            winner: Winner::White,
+            state: EndState::Resignation,
+        };
+        game.events.send(end).await.unwrap();
+        let ended = async {
+            while !listener.targets.get("game").await.unwrap().has_ended() {
+                tokio::task::yield_now().await;
+            }
+        };
+        time::timeout(Duration::from_secs(1), ended).await.unwrap();
+
+        let action = ws::GameAction::Action {
+            code: code(),
+            action: ActionType::Resign,
+        };
+        assert!(matches!(
+            listener.handle_game_action(turn("game")).await,
+            Err(InterfaceError::UnknownGame)
+        ));
+        assert!(matches!(
+            listener.handle_game_action(action).await,
+            Err(InterfaceError::UnknownGame)
+        ));
+        assert!(matches!(
+            listener.handle_game_action(message()).await,
+            Ok(GameEvent::MessageAccepted { .. })
+        ));
+
+        game.messenger.stop().await.unwrap();
+        let removed = async {
+            while listener.targets.get("game").await.is_some() {
+                tokio::task::yield_now().await;
+            }
+        };
+        time::timeout(Duration::from_secs(1), removed).await.unwrap();
+        assert!(matches!(
+            listener.handle_game_action(message()).await,
+            Err(InterfaceError::UnknownGame)
+        ));
+    }
+
+    #[tokio::test]
+    async fn players_cannot_spectate_their_own_game() {
+        let controller = test_controller().await;
+        let (player, watcher) = (listener(&controller), listener(&controller));
+        let host = player.info.read().await.clone();
+        let no_connections = || ConnectionExtension::from(&ArcLock::new_arclock(HashMap::new()));
+
+        let (code, host_rx) = controller.create_lobby(&host, no_connections()).await.unwrap();
+        let _client_rx = controller
+            .join_lobby(&code, &UserInfo::new_guest(), no_connections())
+            .await
+            .unwrap();
+        controller
+            .start_lobby(&host, code.clone(), GameConfig::default())
+            .await
+            .unwrap();
+        player.targets.insert(host_rx.await.unwrap().unwrap()).await;
+
+        let join = || RecievedMessage::ControlAction {
+            action: ws::ControlAction::JoinAsSpectator { code: code.clone() },
+        };
+        Arc::clone(&player).handle_message(join()).await;
+        Arc::clone(&watcher).handle_message(join()).await;
+
+        assert!(!player.spectating.read().await.contains_key(&code));
+        assert!(watcher.spectating.read().await.contains_key(&code));
+    }
+
+    #[tokio::test]
+    async fn rejected_messages_do_not_count_towards_the_rate_limit() {
+        let controller = test_controller().await;
+        let listener = listener(&controller);
+        let user = listener.info.read().await.clone();
+        let limit = ChatRules::get().rate_limit.messages;
+
+        for _ in 0..limit {
+            assert!(matches!(
+                listener.moderate(user.clone(), " ".to_string()).await,
+                Err(ChatError::Empty)
+            ));
+        }
+        for _ in 0..limit {
+            assert!(listener.moderate(user.clone(), "gg".to_string()).await.is_ok());
+        }
+        assert!(matches!(
+            listener.moderate(user.clone(), "gg".to_string()).await,
+            Err(ChatError::RateLimited)
+        ));
+    }
+}
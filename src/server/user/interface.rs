@@ -1,41 +1,80 @@
-use std::{error::Error, fmt::Display, sync::Arc};
+use std::{
+    error::Error,
+    fmt::Display,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 
 use crate::{
     chess::game::{
-        network::{Action, ApprovedChatMessage, ChatMessage, Event},
-        pieces::Move,
+        chat::ChatError,
+        network::{Action, ActionType, ApprovedChatMessage, ChatRole, Event, MessageInterface, RoomMessage},
+        pieces::Turn,
     },
-    server::ws::{GameEvent, SentMessage},
+    server::ws::GameEvent,
 };
-use tokio::sync::{
-    broadcast::{self, error::RecvError},
-    mpsc, oneshot, watch, RwLock,
+use serde::Serialize;
+use tokio::{
+    sync::{
+        broadcast::{self, error::RecvError},
+        mpsc, oneshot, watch, RwLock,
+    },
+    time::{self, Duration},
 };
 
-use super::{Sender, UserConnection};
+use super::{ConnectionExtension, Sender, UserInfo};
 
-type MoveRx = mpsc::Receiver<oneshot::Sender<(Move, oneshot::Sender<bool>)>>;
+type MoveRx = mpsc::Receiver<oneshot::Sender<(Turn, oneshot::Sender<bool>)>>;
+
+/// How long to wait on the game to replace a move request that an action interrupted
+const REPLACED_REQUEST_WAIT: Duration = Duration::from_millis(100);
 
 #[derive(Debug)]
 pub struct GameInterface {
-    move_target: MoveRx,
+    move_target: RwLock<MoveRx>,
     action_target: watch::Receiver<Option<mpsc::Sender<Action>>>,
 
     event_rx: RwLock<mpsc::Receiver<Event>>,
 
     code: String,
+    /// The player this interface belongs to
+    user: UserInfo,
+
+    messenger: Arc<MessageInterface>,
+    message_rx: RwLock<broadcast::Receiver<RoomMessage>>,
 
-    message_target: mpsc::Sender<ChatMessage>,
-    message_rx: RwLock<broadcast::Receiver<ApprovedChatMessage>>,
+    spectators: RwLock<watch::Receiver<usize>>,
+    /// Set once the game's end has been passed on, after which only its chat is open
+    ended: AtomicBool,
 }
 
 impl GameInterface {
-    pub async fn send_move(&mut self, movement: Move) -> Result<(), InterfaceError> {
-        let target = match self.move_target.try_recv() {
-            Ok(t) => t,
-            Err(_) => return Err(InterfaceError::NotYourTurn),
+    pub async fn send_move(&self, movement: Turn) -> Result<(), InterfaceError> {
+        let mut move_target = self.move_target.write().await;
+
+        // Requests left over from a turn that was interrupted by an action are closed, so skip them.
+        // The game may still be sending the request that replaced them, so wait a moment for it
+        let mut skipped = false;
+        let target = loop {
+            let request = match move_target.try_recv() {
+                Ok(t) => Some(t),
+                Err(_) if skipped => time::timeout(REPLACED_REQUEST_WAIT, move_target.recv())
+                    .await
+                    .ok()
+                    .flatten(),
+                Err(_) => None,
+            };
+            match request {
+                Some(t) if t.is_closed() => skipped = true,
+                Some(t) => break t,
+                None => return Err(InterfaceError::NotYourTurn),
+            }
         };
 
+        drop(move_target);
+
         let (tx, rx) = oneshot::channel();
 
         if let Err(_) = target.send((movement, tx)) {
@@ -50,31 +89,74 @@ impl GameInterface {
         }
     }
 
+    /// ### Submits an action on behalf of the given user
+    ///
+    /// Returns `Err(InterfaceError::GameNotActive)` if the game is not accepting actions
+    pub async fn send_action(&self, sender: UserInfo, kind: ActionType) -> Result<(), InterfaceError> {
+        let target = self.action_target.borrow().clone();
+        match target {
+            Some(target) => target
+                .send(Action::new(sender, kind))
+                .await
+                .map_err(|_| InterfaceError::GameNotActive),
+            None => Err(InterfaceError::GameNotActive),
+        }
+    }
+
+    /// Sends a message to the players' room, or the post-game room once the game is over
+    pub async fn send_message(&self, message: ApprovedChatMessage) -> Result<(), InterfaceError> {
+        Ok(self.messenger.send(ChatRole::Player, message).await?)
+    }
+
+    /// ### Hides or shows the opponent's messages, for this player only
+    ///
+    /// Returns `Err(InterfaceError::GameNotActive)` if the game has not started
+    pub fn mute_opponent(&self, muted: bool) -> Result<(), InterfaceError> {
+        match self.messenger.mute(&self.user, muted) {
+            true => Ok(()),
+            false => Err(InterfaceError::GameNotActive),
+        }
+    }
+
+    pub fn code(&self) -> &String {
+        &self.code
+    }
+
+    pub fn has_ended(&self) -> bool {
+        self.ended.load(Ordering::Acquire)
+    }
+
     pub fn new(
         move_target: MoveRx,
         action_target: watch::Receiver<Option<mpsc::Sender<Action>>>,
         event_rx: mpsc::Receiver<Event>,
         code: String,
-        message_target: mpsc::Sender<ChatMessage>,
-        message_rx: broadcast::Receiver<ApprovedChatMessage>,
+        user: UserInfo,
+        messenger: &Arc<MessageInterface>,
+        spectators: watch::Receiver<usize>,
     ) -> Arc<Self> {
         Arc::new(Self {
-            move_target,
+            move_target: RwLock::new(move_target),
             action_target,
             event_rx: RwLock::new(event_rx),
             code,
-            message_target,
-            message_rx: RwLock::new(message_rx),
+            user,
+            message_rx: RwLock::new(messenger.players()),
+            messenger: Arc::clone(messenger),
+            spectators: RwLock::new(spectators),
+            ended: AtomicBool::new(false),
         })
     }
 
-    pub fn start(self: Arc<Self>, conn: Arc<UserConnection>) {
-        tokio::task::spawn(GameInterface::run(Arc::clone(&self), conn));
-    }
-    async fn run(self: Arc<Self>, conn: Arc<UserConnection>) {
+    /// ### Sends the game's events and chat to the player
+    ///
+    /// Runs until the game's chat closes, which is a while after the game ends
+    pub async fn run(self: Arc<Self>, conn: ConnectionExtension) {
         // These writers should never drop
         let mut events = self.event_rx.write().await;
         let mut messages = self.message_rx.write().await;
+        let mut spectators = self.spectators.write().await;
+        let mut watched = true;
         loop {
             let result = tokio::select! {
                 e = events.recv() => {
@@ -91,11 +173,20 @@ impl GameInterface {
                         Err(RecvError::Closed) => InterfaceResult::ChannelClose,
                     }
                 }
+                c = spectators.changed(), if watched => {
+                    match c {
+                        Ok(()) => InterfaceResult::Spectators(*spectators.borrow_and_update()),
+                        Err(_) => InterfaceResult::SpectatorsClosed,
+                    }
+                }
             };
 
             match result {
-                InterfaceResult::ChannelClose => (), // TODO: Something here
+                InterfaceResult::ChannelClose => return,
                 InterfaceResult::Event(event) => {
+                    if let Event::GameEnd { .. } = event {
+                        self.ended.store(true, Ordering::Release);
+                    }
                     conn.send(
                         GameEvent::Event {
                             code: self.code.clone(),
@@ -105,16 +196,32 @@ impl GameInterface {
                     )
                     .await;
                 }
-                InterfaceResult::Message(msg) => {
+                InterfaceResult::Message((room, msg)) => {
+                    if self.messenger.is_muted(&self.user, &msg) {
+                        continue;
+                    }
                     conn.send(
                         GameEvent::Message {
                             code: self.code.clone(),
+                            room,
                             msg,
                         }
                         .into(),
                     )
                     .await;
                 }
+                InterfaceResult::Spectators(count) => {
+                    conn.send(
+                        GameEvent::Spectators {
+                            code: self.code.clone(),
+                            count,
+                        }
+                        .into(),
+                    )
+                    .await;
+                }
+                // The game outlives its spectators, so only stop watching the count
+                InterfaceResult::SpectatorsClosed => watched = false,
                 InterfaceResult::MessagesLagged(count) => {
                     conn.send(
                         GameEvent::MessagesLagged {
@@ -132,34 +239,49 @@ impl GameInterface {
 
 enum InterfaceResult {
     Event(Event),
-    Message(ApprovedChatMessage),
+    Message(RoomMessage),
     MessagesLagged(u64),
+    Spectators(usize),
+    SpectatorsClosed,
     ChannelClose,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub enum InterfaceError {
     NotYourTurn,
     InvalidMove,
+    UnknownGame,
+    GameNotActive,
+    /// Reported to the sender as an error, rather than as a rejected game action
+    Moderated(ChatError),
     UnknownError,
 }
 
 impl Display for InterfaceError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}",
-            match self {
-                Self::InvalidMove => "InvalidMove: Attempted move was not valid",
-                Self::NotYourTurn => "NotYourTurn: It is not this player's turn",
-                Self::UnknownError => "UnknownError",
-            }
-        )
+        let message = match self {
+            Self::InvalidMove => "InvalidMove: Attempted move was not valid",
+            Self::NotYourTurn => "NotYourTurn: It is not this player's turn",
+            Self::UnknownGame => "UnknownGame: This user is not playing a game with that code",
+            Self::GameNotActive => "GameNotActive: The game is not accepting input",
+            Self::Moderated(e) => return write!(f, "{e}"),
+            Self::UnknownError => "UnknownError",
+        };
+        write!(f, "{message}")
     }
 }
 
 impl Error for InterfaceError {}
 
+impl From<ChatError> for InterfaceError {
+    fn from(value: ChatError) -> Self {
+        match value {
+            ChatError::Closed => Self::GameNotActive,
+            value => Self::Moderated(value),
+        }
+    }
+}
+
 impl From<oneshot::error::RecvError> for InterfaceError {
     fn from(_value: oneshot::error::RecvError) -> Self {
         Self::UnknownError
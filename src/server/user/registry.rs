@@ -1,5 +1,6 @@
 use super::*;
 use crate::{
+    chess::engine::bot::BotConfig,
     server::{database::DatabaseMessage, tokens::TokenManager, ws::SentMessage},
     word_loader::WordList,
 };
@@ -18,11 +19,11 @@ pub struct Registry {
 }
 
 impl Registry {
-    pub async fn new(word_list: WordList, db_tx: &Sender<DatabaseMessage>) -> Arc<Self> {
+    pub async fn new(word_list: WordList, db_tx: &Sender<DatabaseMessage>, bots: Vec<BotConfig>) -> Arc<Self> {
         Arc::new(Self {
             users: RwLock::new(HashMap::new()),
             active_sessions: RwLock::new(HashSet::new()),
-            controller: GameControllerInterface::new(word_list, db_tx.clone()).await,
+            controller: GameControllerInterface::new(word_list, db_tx.clone(), bots).await,
         })
     }
 
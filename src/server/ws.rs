@@ -11,10 +11,16 @@ use warp::{
     Error,
 };
 
-use crate::chess::game::{
-    network::{ActionType, ApprovedChatMessage, Event},
-    pieces::Move,
-    GameConfig,
+use crate::{
+    chess::{
+        controller::Pool,
+        game::{
+            network::{ActionType, ApprovedChatMessage, ChatRoom, Event, SpectatorEvent},
+            pieces::Turn,
+            GameConfig,
+        },
+    },
+    server::user::interface::InterfaceError,
 };
 
 static ID: AtomicU64 = AtomicU64::new(0);
@@ -131,10 +137,52 @@ impl From<GameEvent> for SentMessage {
 
 #[derive(Serialize)]
 pub enum GameEvent {
-    GameStart { code: String },
-    Event { code: String, event: Event },
-    Message { code: String, msg: ApprovedChatMessage },
-    MessagesLagged { code: String, count: u64 },
+    GameStart {
+        code: String,
+    },
+    Event {
+        code: String,
+        event: Event,
+    },
+    Message {
+        code: String,
+        room: ChatRoom,
+        msg: ApprovedChatMessage,
+    },
+    MessagesLagged {
+        code: String,
+        count: u64,
+    },
+    /// How many spectators are watching a game the user is playing
+    Spectators {
+        code: String,
+        count: usize,
+    },
+    /// Something for a spectator to see, starting with a snapshot of the game
+    Spectate {
+        code: String,
+        event: SpectatorEvent,
+    },
+
+    // * Responses to game actions
+    MoveAccepted {
+        code: String,
+    },
+    MessageAccepted {
+        code: String,
+    },
+    ActionAccepted {
+        code: String,
+        action: ActionType,
+    },
+    MuteAccepted {
+        code: String,
+        muted: bool,
+    },
+    Rejected {
+        code: String,
+        error: InterfaceError,
+    },
 }
 
 #[derive(Serialize)]
@@ -151,12 +199,16 @@ pub enum ControlEvent {
     LobbyStarted { code: String },
 
     // * Matchmaking responses
-    JoinedQueue,
+    JoinedQueue { pools: Vec<Pool> },
     LeftQueue,
     Matched { code: String },
 
+    // * Bot games
+    Bots { names: Vec<String> },
+
     // * Spectators
     JoinedAsSpectator { code: String },
+    StoppedSpectating { code: String },
 }
 
 #[derive(Deserialize)]
@@ -173,23 +225,54 @@ pub enum ControlAction {
     // * Host controls
     CreateLobby,
     CloseLobby { code: String },
-    StartLobby { config: GameConfig },
+    StartLobby { code: String, config: GameConfig },
 
     // * Client controls
     JoinLobby { code: String },
     LeaveLobby { code: String },
 
     // * Matchmaking controls
-    JoinQueue,
+    JoinQueue { pools: Vec<Pool> },
     LeaveQueue,
 
+    // * Bot games
+    ListBots,
+    PlayBot { bot: String, pool: Pool },
+
     // * Spectators
     JoinAsSpectator { code: String },
+    StopSpectating { code: String },
 }
 
 #[derive(Deserialize, Debug)]
 pub enum GameAction {
-    Message { msg: String },
-    Turn { turn: Move },
-    Action { action: ActionType },
+    Message {
+        code: String,
+        msg: String,
+    },
+    Turn {
+        code: String,
+        turn: Turn,
+    },
+    Action {
+        code: String,
+        action: ActionType,
+    },
+    /// Hides the opponent's messages from this player, or shows them again
+    Mute {
+        code: String,
+        muted: bool,
+    },
+}
+
+impl GameAction {
+    /// The code of the game this action targets
+    pub fn code(&self) -> &String {
+        match self {
+            Self::Message { code, .. }
+            | Self::Turn { code, .. }
+            | Self::Action { code, .. }
+            | Self::Mute { code, .. } => code,
+        }
+    }
 }